@@ -1,7 +1,6 @@
 extern crate stm32cubeprog_rs;
 
 use std::env;
-use std::error::Error;
 
 // TODO: Fix slice from raw parts when no ST-Link is connected
 fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -0,0 +1,26 @@
+extern crate stm32cubeprog_rs;
+
+use std::env;
+
+use stm32cubeprog_rs::gang::{self, Gang, GangJob};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Workers are started from this executable
+    gang::run_worker_if_requested();
+
+    dotenvy::dotenv()?;
+
+    let stm32prog_path = env::var("CUBE_API_DIR")?;
+    let image = env::args().nth(1).ok_or("usage: gang_program IMAGE SERIAL...")?;
+    let serial_numbers: Vec<String> = env::args().skip(2).collect();
+
+    // Program every board in parallel
+    let report = Gang::new(stm32prog_path)?.program(&serial_numbers, &GangJob::new(image))?;
+    println!("{report}");
+
+    for board in report.failed() {
+        println!("{}:\n{}", board.serial_number, board.logs.join("\n"));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,25 @@
+extern crate serde_json;
+extern crate stm32cubeprog_rs;
+
+use std::env;
+
+// Print the connected STLinks as JSON, requires the `serde` feature
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    dotenvy::dotenv()?;
+
+    let stm32prog_path = env::var("CUBE_API_DIR")?;
+
+    // Load STM32CubeProgmmer API library
+    let stm32prog = stm32cubeprog_rs::STM32CubeProg::new(stm32prog_path)?;
+
+    // Snapshot connected STLinks
+    let stlinks = stm32prog
+        .discover()?
+        .iter()
+        .map(|stlink| stlink.snapshot())
+        .collect::<Result<Vec<_>, _>>()?;
+
+    println!("{}", serde_json::to_string_pretty(&stlinks)?);
+
+    Ok(())
+}
@@ -0,0 +1,746 @@
+extern crate stm32cubeprog_rs;
+
+use stm32cubeprog_rs::checksum::{ChecksumAlgorithm, ChecksumMethod};
+use stm32cubeprog_rs::err;
+use stm32cubeprog_rs::probe::{ProbeSelector, ProbeUri};
+use stm32cubeprog_rs::{STLink, STM32CubeProg};
+
+const USAGE: &str = "\
+Usage: stm32cubeprog-rs [OPTIONS] <COMMAND> [ARGS]
+
+Options:
+  --api-dir <DIR>    STM32CubeProgrammer installation, $CUBE_API_DIR by default
+  -p, --probe <ID>   Serial number, glob pattern or stlink:// URI of the probe
+  --shared           Open the probe in shared mode
+  --json             Print the results as JSON
+  -h, --help         Print this help
+
+Commands:
+  list                                 List the plugged probes
+  info                                 Print the connected device information
+  read <ADDRESS> <SIZE> [-o <FILE>]    Read memory, as a hex dump or into FILE
+  write <ADDRESS> <FILE>               Write the content of FILE to memory
+  erase [SECTOR...]                    Erase the given sectors, or the whole flash
+  flash <FILE> [--address <ADDRESS>] [--skip-erase] [--no-verify]
+                                       Program an ELF, HEX, SREC or binary file
+  verify <ADDRESS> <FILE>              Compare memory with the content of FILE
+  reset                                Reset the target
+  registers                            Read the core registers
+  option-bytes [NAME=VALUE...]         List or program option bytes
+
+Exit codes:
+  0      Success
+  1-17   STM32CubeProgrammer error code, negated
+  64     Invalid command line
+  70     Any other error";
+
+const EXIT_USAGE: i32 = 64;
+const EXIT_SOFTWARE: i32 = 70;
+
+enum CliError {
+    Usage(String),
+    Library(err::Error),
+}
+
+impl From<err::Error> for CliError {
+    fn from(error: err::Error) -> CliError {
+        CliError::Library(error)
+    }
+}
+
+impl From<std::io::Error> for CliError {
+    fn from(error: std::io::Error) -> CliError {
+        CliError::Library(error.into())
+    }
+}
+
+impl CliError {
+    fn exit_code(&self) -> i32 {
+        match self {
+            CliError::Usage(_) => EXIT_USAGE,
+            CliError::Library(err::Error::CubeProgrammerError(error)) => -(*error as i32),
+            CliError::Library(_) => EXIT_SOFTWARE,
+        }
+    }
+}
+
+impl std::fmt::Display for CliError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CliError::Usage(message) => write!(f, "{}", message),
+            CliError::Library(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+enum Json {
+    Null,
+    Bool(bool),
+    Number(i64),
+    Float(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(&'static str, Json)>),
+}
+
+impl std::fmt::Display for Json {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(value) => write!(f, "{}", value),
+            Json::Number(value) => write!(f, "{}", value),
+            Json::Float(value) if value.is_finite() => write!(f, "{}", value),
+            Json::Float(_) => write!(f, "null"),
+            Json::String(value) => {
+                write!(f, "\"")?;
+                for c in value.chars() {
+                    match c {
+                        '"' => write!(f, "\\\"")?,
+                        '\\' => write!(f, "\\\\")?,
+                        '\n' => write!(f, "\\n")?,
+                        '\r' => write!(f, "\\r")?,
+                        '\t' => write!(f, "\\t")?,
+                        c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+                        c => write!(f, "{}", c)?,
+                    }
+                }
+                write!(f, "\"")
+            }
+            Json::Array(values) => {
+                write!(f, "[")?;
+                for (index, value) in values.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(fields) => {
+                write!(f, "{{")?;
+                for (index, (key, value)) in fields.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}:{}", Json::String(key.to_string()), value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+/// Result of a command, printed as `text` or as `json`.
+struct Output {
+    text: String,
+    json: Json,
+}
+
+struct Options {
+    api_dir: Option<String>,
+    probe: Option<String>,
+    shared: bool,
+    json: bool,
+    command: String,
+    arguments: Arguments,
+}
+
+/// Remaining arguments of a command.
+struct Arguments(Vec<String>);
+
+impl Arguments {
+    fn flag(&mut self, name: &str) -> bool {
+        match self.0.iter().position(|argument| argument == name) {
+            Some(index) => {
+                self.0.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn value(&mut self, names: &[&str]) -> Result<Option<String>, CliError> {
+        match self
+            .0
+            .iter()
+            .position(|argument| names.contains(&argument.as_str()))
+        {
+            Some(index) if index + 1 < self.0.len() => {
+                self.0.remove(index);
+                Ok(Some(self.0.remove(index)))
+            }
+            Some(_) => Err(CliError::Usage(format!("missing value for {}", names[0]))),
+            None => Ok(None),
+        }
+    }
+
+    fn positional(&mut self, what: &str) -> Result<String, CliError> {
+        if let Some(argument) = self.0.iter().find(|argument| argument.starts_with('-')) {
+            return Err(CliError::Usage(format!("unexpected option {}", argument)));
+        }
+        if self.0.is_empty() {
+            return Err(CliError::Usage(format!("missing {}", what)));
+        }
+        Ok(self.0.remove(0))
+    }
+
+    fn rest(&mut self) -> Result<Vec<String>, CliError> {
+        if let Some(argument) = self.0.iter().find(|argument| argument.starts_with('-')) {
+            return Err(CliError::Usage(format!("unexpected option {}", argument)));
+        }
+        Ok(std::mem::take(&mut self.0))
+    }
+
+    fn finish(&self) -> Result<(), CliError> {
+        match self.0.first() {
+            Some(argument) => Err(CliError::Usage(format!("unexpected argument {}", argument))),
+            None => Ok(()),
+        }
+    }
+}
+
+fn parse_options(mut arguments: std::env::Args) -> Result<Option<Options>, CliError> {
+    let mut options = Options {
+        api_dir: None,
+        probe: None,
+        shared: false,
+        json: false,
+        command: String::new(),
+        arguments: Arguments(Vec::new()),
+    };
+
+    arguments.next();
+    while let Some(argument) = arguments.next() {
+        match argument.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "--api-dir" => {
+                options.api_dir = Some(
+                    arguments
+                        .next()
+                        .ok_or_else(|| CliError::Usage("missing value for --api-dir".into()))?,
+                )
+            }
+            "-p" | "--probe" => {
+                options.probe = Some(
+                    arguments
+                        .next()
+                        .ok_or_else(|| CliError::Usage("missing value for --probe".into()))?,
+                )
+            }
+            "--shared" => options.shared = true,
+            "--json" => options.json = true,
+            option if option.starts_with('-') => {
+                return Err(CliError::Usage(format!("unknown option {}", option)))
+            }
+            _ => {
+                options.command = argument;
+                options.arguments = Arguments(arguments.collect());
+                return Ok(Some(options));
+            }
+        }
+    }
+
+    Err(CliError::Usage("missing command".into()))
+}
+
+fn parse_u32(text: &str, what: &str) -> Result<u32, CliError> {
+    let text = text.replace('_', "");
+    let parsed = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+    parsed.map_err(|_| CliError::Usage(format!("invalid {} '{}'", what, text)))
+}
+
+fn hex(value: u32) -> Json {
+    Json::String(format!("0x{:08X}", value))
+}
+
+fn string(value: Result<String, err::Error>) -> Json {
+    value.map(Json::String).unwrap_or(Json::Null)
+}
+
+fn programmer(options: &Options) -> Result<STM32CubeProg, CliError> {
+    let api_dir = match &options.api_dir {
+        Some(api_dir) => api_dir.clone(),
+        None => std::env::var("CUBE_API_DIR").map_err(|_| {
+            CliError::Usage(
+                "set --api-dir or $CUBE_API_DIR to the STM32CubeProgrammer installation".into(),
+            )
+        })?,
+    };
+
+    Ok(STM32CubeProg::builder(api_dir)
+        .shared(options.shared)
+        .build()?)
+}
+
+fn select_probe(programmer: &STM32CubeProg, options: &Options) -> Result<STLink, CliError> {
+    match &options.probe {
+        Some(probe) if probe.starts_with("stlink://") => {
+            Ok(programmer.probe_from_uri(&probe.parse::<ProbeUri>()?)?)
+        }
+        Some(serial_number) => {
+            Ok(programmer.select_probe(&ProbeSelector::by_serial(serial_number))?)
+        }
+        None => Ok(programmer.select_probe(&ProbeSelector::any())?),
+    }
+}
+
+fn list(programmer: &STM32CubeProg) -> Result<Output, CliError> {
+    let stlinks = match programmer.discover() {
+        Ok(stlinks) => stlinks,
+        Err(err::Error::CubeProgrammerError(err::CubeProgrammerError::NoDeviceFound)) => Vec::new(),
+        Err(error) => return Err(error.into()),
+    };
+    let busy = programmer.busy_probes()?;
+
+    let mut text = Vec::new();
+    let mut json = Vec::new();
+    for stlink in stlinks.iter() {
+        let voltage = stlink.target_voltage().ok();
+        text.push(format!(
+            "#{} {} {} (firmware {}, {:.2} V)",
+            stlink.index(),
+            stlink.serial_number().unwrap_or_default(),
+            stlink.board().unwrap_or_default(),
+            stlink.firmware_version().unwrap_or_default(),
+            voltage.unwrap_or(0.0)
+        ));
+        json.push(Json::Object(vec![
+            ("index", Json::Number(stlink.index().into())),
+            ("serial_number", string(stlink.serial_number())),
+            ("board", string(stlink.board())),
+            ("firmware_version", string(stlink.firmware_version())),
+            (
+                "target_voltage",
+                voltage
+                    .map(|voltage| Json::Float(voltage.into()))
+                    .unwrap_or(Json::Null),
+            ),
+        ]));
+    }
+    for serial_number in busy.iter() {
+        text.push(format!("{} (held by another process)", serial_number));
+    }
+
+    Ok(Output {
+        text: text.join("\n"),
+        json: Json::Object(vec![
+            ("probes", Json::Array(json)),
+            (
+                "busy",
+                Json::Array(busy.into_iter().map(Json::String).collect()),
+            ),
+        ]),
+    })
+}
+
+fn info(programmer: &STM32CubeProg, stlink: &STLink) -> Result<Output, CliError> {
+    let device_info = programmer.device_info()?;
+    Ok(Output {
+        text: format!(
+            "Probe: {}\n{}",
+            stlink.serial_number().unwrap_or_default(),
+            device_info
+        ),
+        json: Json::Object(vec![
+            ("probe", string(stlink.serial_number())),
+            ("name", string(device_info.name())),
+            ("series", string(device_info.series())),
+            ("category", string(device_info.category())),
+            ("cpu", string(device_info.cpu())),
+            ("description", string(device_info.description())),
+            ("revision_id", string(device_info.revision_id())),
+            ("board", string(device_info.board())),
+            ("device_id", hex(device_info.device_id() as u32)),
+            ("flash_size", Json::Number(device_info.flash_size().into())),
+            (
+                "bootloader_version",
+                Json::Number(device_info.bootloader_version().into()),
+            ),
+        ]),
+    })
+}
+
+fn read(programmer: &STM32CubeProg, arguments: &mut Arguments) -> Result<Output, CliError> {
+    let output = arguments.value(&["-o", "--output"])?;
+    let address = parse_u32(&arguments.positional("address")?, "address")?;
+    let size = parse_u32(&arguments.positional("size")?, "size")?;
+    arguments.finish()?;
+
+    let mut data = vec![0u8; size as usize];
+    programmer.read_slice(address, &mut data)?;
+
+    let hex_data: String = data.iter().map(|byte| format!("{:02x}", byte)).collect();
+    let text = match &output {
+        Some(path) => {
+            std::fs::write(path, &data)?;
+            format!("Read {} bytes at 0x{:08X} into {}", size, address, path)
+        }
+        None => data
+            .chunks(16)
+            .enumerate()
+            .map(|(index, line)| {
+                let bytes: Vec<String> = line.iter().map(|byte| format!("{:02x}", byte)).collect();
+                format!(
+                    "0x{:08X}: {}",
+                    address as usize + index * 16,
+                    bytes.join(" ")
+                )
+            })
+            .collect::<Vec<String>>()
+            .join("\n"),
+    };
+
+    Ok(Output {
+        text,
+        json: Json::Object(vec![
+            ("address", hex(address)),
+            ("size", Json::Number(size.into())),
+            (
+                "data",
+                if output.is_some() {
+                    Json::Null
+                } else {
+                    Json::String(hex_data)
+                },
+            ),
+        ]),
+    })
+}
+
+fn write(programmer: &STM32CubeProg, arguments: &mut Arguments) -> Result<Output, CliError> {
+    let address = parse_u32(&arguments.positional("address")?, "address")?;
+    let path = arguments.positional("file")?;
+    arguments.finish()?;
+
+    let data = std::fs::read(&path)?;
+    programmer.write_slice(address, &data)?;
+
+    Ok(Output {
+        text: format!("Wrote {} bytes at 0x{:08X}", data.len(), address),
+        json: Json::Object(vec![
+            ("address", hex(address)),
+            ("size", Json::Number(data.len() as i64)),
+        ]),
+    })
+}
+
+fn erase(programmer: &STM32CubeProg, arguments: &mut Arguments) -> Result<Output, CliError> {
+    let sectors = arguments
+        .rest()?
+        .iter()
+        .map(|sector| parse_u32(sector, "sector"))
+        .collect::<Result<Vec<u32>, CliError>>()?;
+
+    if sectors.is_empty() {
+        programmer.mass_erase()?;
+    } else {
+        programmer.sector_erase(&sectors)?;
+    }
+
+    Ok(Output {
+        text: if sectors.is_empty() {
+            "Mass erase done".to_string()
+        } else {
+            format!("Erased {} sectors", sectors.len())
+        },
+        json: Json::Object(vec![(
+            "sectors",
+            if sectors.is_empty() {
+                Json::String("all".to_string())
+            } else {
+                Json::Array(
+                    sectors
+                        .into_iter()
+                        .map(|sector| Json::Number(sector.into()))
+                        .collect(),
+                )
+            },
+        )]),
+    })
+}
+
+fn flash(programmer: &STM32CubeProg, arguments: &mut Arguments) -> Result<Output, CliError> {
+    let address = match arguments.value(&["--address"])? {
+        Some(address) => Some(parse_u32(&address, "address")?),
+        None => None,
+    };
+    let skip_erase = arguments.flag("--skip-erase");
+    let verify = !arguments.flag("--no-verify");
+    let path = arguments.positional("file")?;
+    arguments.finish()?;
+
+    let start = std::time::Instant::now();
+    programmer.download(&path, address, Some(skip_erase), Some(verify))?;
+    let duration = start.elapsed();
+
+    Ok(Output {
+        text: format!("Programmed {} in {:.1} s", path, duration.as_secs_f32()),
+        json: Json::Object(vec![
+            ("file", Json::String(path)),
+            ("verified", Json::Bool(verify)),
+            ("duration_ms", Json::Number(duration.as_millis() as i64)),
+        ]),
+    })
+}
+
+fn verify(programmer: &STM32CubeProg, arguments: &mut Arguments) -> Result<Output, CliError> {
+    let address = parse_u32(&arguments.positional("address")?, "address")?;
+    let path = arguments.positional("file")?;
+    arguments.finish()?;
+
+    let data = std::fs::read(&path)?;
+    let crc = programmer.verify_checksum(
+        address,
+        &data,
+        ChecksumAlgorithm::Crc32,
+        ChecksumMethod::ReadBack,
+    )?;
+
+    Ok(Output {
+        text: format!(
+            "{} matches memory at 0x{:08X} (CRC32 0x{:08X})",
+            path, address, crc
+        ),
+        json: Json::Object(vec![
+            ("file", Json::String(path)),
+            ("address", hex(address)),
+            ("crc32", hex(crc)),
+        ]),
+    })
+}
+
+fn registers(programmer: &STM32CubeProg) -> Result<Output, CliError> {
+    let registers = programmer.read_registers()?;
+    Ok(Output {
+        text: registers
+            .iter()
+            .map(|(register, value)| format!("{:<4} 0x{:08X}", format!("{:?}", register), value))
+            .collect::<Vec<String>>()
+            .join("\n"),
+        json: Json::Object(vec![(
+            "registers",
+            Json::Array(
+                registers
+                    .iter()
+                    .map(|(register, value)| {
+                        Json::Object(vec![
+                            ("name", Json::String(format!("{:?}", register))),
+                            ("value", hex(*value)),
+                        ])
+                    })
+                    .collect(),
+            ),
+        )]),
+    })
+}
+
+fn option_bytes(programmer: &STM32CubeProg, arguments: &mut Arguments) -> Result<Output, CliError> {
+    let mut values = Vec::new();
+    for assignment in arguments.rest()? {
+        let (name, value) = assignment
+            .split_once('=')
+            .ok_or_else(|| CliError::Usage(format!("expected NAME=VALUE, got '{}'", assignment)))?;
+        values.push((name.to_string(), parse_u32(value, "option byte value")?));
+    }
+
+    if !values.is_empty() {
+        let values: Vec<(&str, u32)> = values
+            .iter()
+            .map(|(name, value)| (name.as_str(), *value))
+            .collect();
+        programmer.set_option_bytes(&values)?;
+    }
+
+    let option_bytes = programmer.option_bytes()?;
+    Ok(Output {
+        text: option_bytes
+            .iter()
+            .map(|option_byte| option_byte.to_string())
+            .collect::<Vec<String>>()
+            .join("\n"),
+        json: Json::Object(vec![(
+            "option_bytes",
+            Json::Array(
+                option_bytes
+                    .into_iter()
+                    .map(|option_byte| {
+                        Json::Object(vec![
+                            (
+                                "description",
+                                option_byte
+                                    .value_description()
+                                    .map(|description| Json::String(description.to_string()))
+                                    .unwrap_or(Json::Null),
+                            ),
+                            ("name", Json::String(option_byte.name)),
+                            ("category", Json::String(option_byte.category)),
+                            ("value", hex(option_byte.value)),
+                        ])
+                    })
+                    .collect(),
+            ),
+        )]),
+    })
+}
+
+const COMMANDS: [&str; 10] = [
+    "list",
+    "info",
+    "read",
+    "write",
+    "erase",
+    "flash",
+    "verify",
+    "reset",
+    "registers",
+    "option-bytes",
+];
+
+fn run(options: &mut Options) -> Result<Output, CliError> {
+    if !COMMANDS.contains(&options.command.as_str()) {
+        return Err(CliError::Usage(format!(
+            "unknown command {}",
+            options.command
+        )));
+    }
+
+    let programmer = programmer(options)?;
+    if options.command == "list" {
+        options.arguments.finish()?;
+        return list(&programmer);
+    }
+
+    let stlink = select_probe(&programmer, options)?;
+    programmer.connect(&stlink)?;
+
+    let arguments = &mut options.arguments;
+    let output = match options.command.as_str() {
+        "info" => arguments.finish().and_then(|()| info(&programmer, &stlink)),
+        "read" => read(&programmer, arguments),
+        "write" => write(&programmer, arguments),
+        "erase" => erase(&programmer, arguments),
+        "flash" => flash(&programmer, arguments),
+        "verify" => verify(&programmer, arguments),
+        "reset" => arguments.finish().and_then(|()| {
+            programmer.reset(&stlink)?;
+            Ok(Output {
+                text: "Target reset".to_string(),
+                json: Json::Object(vec![("reset", Json::Bool(true))]),
+            })
+        }),
+        "registers" => arguments.finish().and_then(|()| registers(&programmer)),
+        _ => option_bytes(&programmer, arguments),
+    };
+
+    programmer.disconnect();
+    output
+}
+
+fn main() {
+    let mut options = match parse_options(std::env::args()) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return;
+        }
+        Err(error) => {
+            eprintln!("error: {}\n\n{}", error, USAGE);
+            std::process::exit(error.exit_code());
+        }
+    };
+
+    match run(&mut options) {
+        Ok(output) if options.json => println!("{}", output.json),
+        Ok(output) => {
+            if !output.text.is_empty() {
+                println!("{}", output.text)
+            }
+        }
+        Err(error) => {
+            if options.json {
+                println!(
+                    "{}",
+                    Json::Object(vec![
+                        ("error", Json::String(error.to_string())),
+                        ("code", Json::Number(error.exit_code().into())),
+                    ])
+                );
+            }
+            eprintln!("error: {}", error);
+            std::process::exit(error.exit_code());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate serde_json;
+
+    use super::*;
+
+    #[test]
+    fn json_strings_are_escaped() {
+        let value = "quote \" backslash \\ newline \n tab \t bell \u{7} é";
+        let json = Json::String(value.to_string()).to_string();
+        assert_eq!(
+            json,
+            "\"quote \\\" backslash \\\\ newline \\n tab \\t bell \\u0007 é\""
+        );
+        assert_eq!(serde_json::from_str::<String>(&json).unwrap(), value);
+    }
+
+    #[test]
+    fn json_values_are_valid() {
+        let json = Json::Object(vec![
+            ("null", Json::Null),
+            ("flag", Json::Bool(true)),
+            ("size", Json::Number(-4)),
+            ("voltage", Json::Float(3.25)),
+            ("nan", Json::Float(f64::NAN)),
+            (
+                "list",
+                Json::Array(vec![Json::String("a\"b".to_string()), Json::Number(1)]),
+            ),
+        ])
+        .to_string();
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&json).unwrap(),
+            serde_json::json!({
+                "null": null,
+                "flag": true,
+                "size": -4,
+                "voltage": 3.25,
+                "nan": null,
+                "list": ["a\"b", 1],
+            })
+        );
+    }
+
+    #[test]
+    fn exit_codes() {
+        let library = |error: err::CubeProgrammerError| CliError::Library(error.into());
+        assert_eq!(
+            library(err::CubeProgrammerError::DeviceNotConnected).exit_code(),
+            1
+        );
+        assert_eq!(
+            library(err::CubeProgrammerError::MemoryReadError).exit_code(),
+            9
+        );
+        assert_eq!(
+            library(err::CubeProgrammerError::UnknownError).exit_code(),
+            17
+        );
+        assert_eq!(
+            CliError::Usage("unknown command".to_string()).exit_code(),
+            EXIT_USAGE
+        );
+        assert_eq!(
+            CliError::Library(err::Error::Timeout).exit_code(),
+            EXIT_SOFTWARE
+        );
+    }
+}
@@ -0,0 +1,509 @@
+use crate::err;
+use crate::{STLink, STM32CubeProg};
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct SpiConnectParameters {
+    pub baudrate: std::os::raw::c_int,
+    pub crc_polynomial: std::os::raw::c_int,
+    pub direction: std::os::raw::c_int,
+    pub cpha: std::os::raw::c_int,
+    pub cpol: std::os::raw::c_int,
+    pub crc: std::os::raw::c_int,
+    pub first_bit: std::os::raw::c_int,
+    pub frame_format: std::os::raw::c_int,
+    pub data_size: std::os::raw::c_int,
+    pub mode: std::os::raw::c_int,
+    pub nss: std::os::raw::c_int,
+    pub nss_pulse: std::os::raw::c_int,
+    pub delay: std::os::raw::c_int,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct I2cConnectParameters {
+    pub address: std::os::raw::c_int,
+    pub baudrate: std::os::raw::c_int,
+    pub speed_mode: std::os::raw::c_int,
+    pub address_mode: std::os::raw::c_int,
+    pub analog_filter: std::os::raw::c_int,
+    pub digital_filter: std::os::raw::c_int,
+    pub digital_noise_filter: std::os::raw::c_int,
+    pub rise_time: std::os::raw::c_int,
+    pub fall_time: std::os::raw::c_int,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct CanConnectParameters {
+    pub bitrate: std::os::raw::c_int,
+    pub mode: std::os::raw::c_int,
+    pub identifier: std::os::raw::c_int,
+    pub remote_frame: std::os::raw::c_int,
+    pub fifo: std::os::raw::c_int,
+    pub filter_mode: std::os::raw::c_int,
+    pub filter_scale: std::os::raw::c_int,
+    pub filter_enable: std::os::raw::c_int,
+    pub filter_bank: std::os::raw::c_int,
+}
+
+/// SPI clock polarity and phase, numbered as the usual SPI modes 0 to 3.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SpiMode {
+    Mode0,
+    Mode1,
+    Mode2,
+    Mode3,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SpiNss {
+    Software = 0,
+    Hardware = 1,
+}
+
+#[derive(Debug, Clone)]
+pub struct SpiBootloader {
+    spi_connect_parameters: SpiConnectParameters,
+}
+
+impl Default for SpiBootloader {
+    fn default() -> Self {
+        SpiBootloader {
+            spi_connect_parameters: SpiConnectParameters {
+                baudrate: 375,
+                crc_polynomial: 7,
+                direction: 0,
+                cpha: 0,
+                cpol: 0,
+                crc: 0,
+                first_bit: 1,
+                frame_format: 0,
+                data_size: 1,
+                mode: 1,
+                nss: SpiNss::Hardware as i32,
+                nss_pulse: 1,
+                delay: 1,
+            },
+        }
+    }
+}
+
+impl SpiBootloader {
+    pub fn baudrate(&self) -> i32 {
+        self.spi_connect_parameters.baudrate
+    }
+
+    pub fn mode(&self) -> SpiMode {
+        match (
+            self.spi_connect_parameters.cpol,
+            self.spi_connect_parameters.cpha,
+        ) {
+            (0, 0) => SpiMode::Mode0,
+            (0, _) => SpiMode::Mode1,
+            (_, 0) => SpiMode::Mode2,
+            _ => SpiMode::Mode3,
+        }
+    }
+
+    pub fn nss(&self) -> SpiNss {
+        if self.spi_connect_parameters.nss == 0 {
+            SpiNss::Software
+        } else {
+            SpiNss::Hardware
+        }
+    }
+
+    pub fn nss_pulse(&self) -> bool {
+        self.spi_connect_parameters.nss_pulse == 1
+    }
+
+    pub fn delay(&self) -> bool {
+        self.spi_connect_parameters.delay == 1
+    }
+
+    pub fn crc_polynomial(&self) -> Option<i32> {
+        if self.spi_connect_parameters.crc == 1 {
+            Some(self.spi_connect_parameters.crc_polynomial)
+        } else {
+            None
+        }
+    }
+
+    /// Baudrate in kHz: 187, 375, 750, 1500, 3000, 6000 or 12000.
+    pub fn set_baudrate(&mut self, baudrate: i32) {
+        self.spi_connect_parameters.baudrate = baudrate;
+    }
+
+    pub fn set_mode(&mut self, mode: SpiMode) {
+        let (cpol, cpha) = match mode {
+            SpiMode::Mode0 => (0, 0),
+            SpiMode::Mode1 => (0, 1),
+            SpiMode::Mode2 => (1, 0),
+            SpiMode::Mode3 => (1, 1),
+        };
+        self.spi_connect_parameters.cpol = cpol;
+        self.spi_connect_parameters.cpha = cpha;
+    }
+
+    pub fn set_nss(&mut self, nss: SpiNss) {
+        self.spi_connect_parameters.nss = nss as i32;
+    }
+
+    pub fn set_nss_pulse(&mut self, nss_pulse: bool) {
+        self.spi_connect_parameters.nss_pulse = nss_pulse.into();
+    }
+
+    /// Insert a delay of a few microseconds between frames.
+    pub fn set_delay(&mut self, delay: bool) {
+        self.spi_connect_parameters.delay = delay.into();
+    }
+
+    pub fn set_crc_polynomial(&mut self, crc_polynomial: Option<i32>) {
+        self.spi_connect_parameters.crc = crc_polynomial.is_some().into();
+        self.spi_connect_parameters.crc_polynomial = crc_polynomial.unwrap_or(7);
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum I2cSpeed {
+    Standard = 0,
+    Fast = 1,
+}
+
+#[derive(Debug, Clone)]
+pub struct I2cBootloader {
+    i2c_connect_parameters: I2cConnectParameters,
+}
+
+impl I2cBootloader {
+    /// Parameters to reach the bootloader at the 7-bit `address`, which
+    /// depends on the device (see AN2606).
+    pub fn new(address: u8) -> Self {
+        I2cBootloader {
+            i2c_connect_parameters: I2cConnectParameters {
+                address: address.into(),
+                baudrate: 400,
+                speed_mode: I2cSpeed::Fast as i32,
+                address_mode: 0,
+                analog_filter: 1,
+                digital_filter: 0,
+                digital_noise_filter: 0,
+                rise_time: 0,
+                fall_time: 0,
+            },
+        }
+    }
+
+    pub fn address(&self) -> i32 {
+        self.i2c_connect_parameters.address
+    }
+
+    pub fn baudrate(&self) -> i32 {
+        self.i2c_connect_parameters.baudrate
+    }
+
+    pub fn speed(&self) -> I2cSpeed {
+        if self.i2c_connect_parameters.speed_mode == 0 {
+            I2cSpeed::Standard
+        } else {
+            I2cSpeed::Fast
+        }
+    }
+
+    pub fn analog_filter(&self) -> bool {
+        self.i2c_connect_parameters.analog_filter == 1
+    }
+
+    pub fn digital_filter(&self) -> Option<i32> {
+        if self.i2c_connect_parameters.digital_filter == 1 {
+            Some(self.i2c_connect_parameters.digital_noise_filter)
+        } else {
+            None
+        }
+    }
+
+    pub fn set_address(&mut self, address: u8) {
+        self.i2c_connect_parameters.address = address.into();
+    }
+
+    /// Baudrate in kHz, up to 100 in standard mode and 400 in fast mode.
+    pub fn set_baudrate(&mut self, baudrate: i32) {
+        self.i2c_connect_parameters.baudrate = baudrate;
+    }
+
+    pub fn set_speed(&mut self, speed: I2cSpeed) {
+        self.i2c_connect_parameters.speed_mode = speed as i32;
+    }
+
+    pub fn set_analog_filter(&mut self, analog_filter: bool) {
+        self.i2c_connect_parameters.analog_filter = analog_filter.into();
+    }
+
+    /// Enable the digital filter with a noise filter length between 0 and 15.
+    pub fn set_digital_filter(&mut self, digital_noise_filter: Option<i32>) {
+        self.i2c_connect_parameters.digital_filter = digital_noise_filter.is_some().into();
+        self.i2c_connect_parameters.digital_noise_filter = digital_noise_filter.unwrap_or(0);
+    }
+
+    /// Rise and fall times in ns, 0 to let the library use its defaults.
+    pub fn set_timings(&mut self, rise_time: i32, fall_time: i32) {
+        self.i2c_connect_parameters.rise_time = rise_time;
+        self.i2c_connect_parameters.fall_time = fall_time;
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CanMode {
+    Normal = 0,
+    Loopback = 1,
+    Silent = 2,
+    SilentLoopback = 3,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CanIdentifier {
+    Standard = 0,
+    Extended = 1,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CanFilterMode {
+    Mask = 0,
+    List = 1,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CanFilterScale {
+    Bits16 = 0,
+    Bits32 = 1,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CanFilter {
+    pub mode: CanFilterMode,
+    pub scale: CanFilterScale,
+    pub bank: u8,
+    pub fifo: u8,
+}
+
+impl Default for CanFilter {
+    fn default() -> Self {
+        CanFilter {
+            mode: CanFilterMode::Mask,
+            scale: CanFilterScale::Bits32,
+            bank: 0,
+            fifo: 0,
+        }
+    }
+}
+
+/// Parameters of the classic CAN bootloader.
+///
+/// The API has no connection call for FDCAN, so the bootloaders of the
+/// STM32G4, STM32H7, STM32L5 and STM32U5 lines are not reachable this way.
+#[derive(Debug, Clone)]
+pub struct CanBootloader {
+    can_connect_parameters: CanConnectParameters,
+}
+
+impl Default for CanBootloader {
+    fn default() -> Self {
+        let mut can = CanBootloader {
+            can_connect_parameters: CanConnectParameters {
+                bitrate: 125,
+                mode: CanMode::Normal as i32,
+                identifier: CanIdentifier::Standard as i32,
+                remote_frame: 0,
+                fifo: 0,
+                filter_mode: 0,
+                filter_scale: 0,
+                filter_enable: 0,
+                filter_bank: 0,
+            },
+        };
+        can.set_filter(Some(CanFilter::default()));
+        can
+    }
+}
+
+impl CanBootloader {
+    pub fn bitrate(&self) -> i32 {
+        self.can_connect_parameters.bitrate
+    }
+
+    pub fn mode(&self) -> CanMode {
+        match self.can_connect_parameters.mode {
+            1 => CanMode::Loopback,
+            2 => CanMode::Silent,
+            3 => CanMode::SilentLoopback,
+            _ => CanMode::Normal,
+        }
+    }
+
+    pub fn identifier(&self) -> CanIdentifier {
+        if self.can_connect_parameters.identifier == 0 {
+            CanIdentifier::Standard
+        } else {
+            CanIdentifier::Extended
+        }
+    }
+
+    pub fn remote_frame(&self) -> bool {
+        self.can_connect_parameters.remote_frame == 1
+    }
+
+    pub fn filter(&self) -> Option<CanFilter> {
+        if self.can_connect_parameters.filter_enable == 0 {
+            return None;
+        }
+
+        Some(CanFilter {
+            mode: if self.can_connect_parameters.filter_mode == 0 {
+                CanFilterMode::Mask
+            } else {
+                CanFilterMode::List
+            },
+            scale: if self.can_connect_parameters.filter_scale == 0 {
+                CanFilterScale::Bits16
+            } else {
+                CanFilterScale::Bits32
+            },
+            bank: self.can_connect_parameters.filter_bank as u8,
+            fifo: self.can_connect_parameters.fifo as u8,
+        })
+    }
+
+    /// Bitrate in kbps: 125, 250, 500 or 1000.
+    pub fn set_bitrate(&mut self, bitrate: i32) {
+        self.can_connect_parameters.bitrate = bitrate;
+    }
+
+    pub fn set_mode(&mut self, mode: CanMode) {
+        self.can_connect_parameters.mode = mode as i32;
+    }
+
+    pub fn set_identifier(&mut self, identifier: CanIdentifier) {
+        self.can_connect_parameters.identifier = identifier as i32;
+    }
+
+    pub fn set_remote_frame(&mut self, remote_frame: bool) {
+        self.can_connect_parameters.remote_frame = remote_frame.into();
+    }
+
+    pub fn set_filter(&mut self, filter: Option<CanFilter>) {
+        self.can_connect_parameters.filter_enable = filter.is_some().into();
+        let filter = filter.unwrap_or_default();
+        self.can_connect_parameters.filter_mode = filter.mode as i32;
+        self.can_connect_parameters.filter_scale = filter.scale as i32;
+        self.can_connect_parameters.filter_bank = filter.bank.into();
+        self.can_connect_parameters.fifo = filter.fifo.into();
+    }
+}
+
+impl STM32CubeProg {
+    /// ST-Links able to bridge SPI, I2C and CAN to the system bootloader.
+    pub fn discover_bridges(&self) -> Result<Vec<STLink>, err::Error> {
+        let bridges: Vec<STLink> = self
+            .discover()?
+            .into_iter()
+            .filter(|stlink| stlink.bridge())
+            .collect();
+
+        if bridges.is_empty() {
+            Err(err::CubeProgrammerError::NoDeviceFound.into())
+        } else {
+            Ok(bridges)
+        }
+    }
+
+    pub fn connect_spi(&self, spi: &SpiBootloader) -> Result<(), err::Error> {
+        self.stlink.replace(None);
+        let error = unsafe { (self.vtable.connect_spi_bootloader)(spi.spi_connect_parameters) };
+        if error == 0 {
+            Ok(())
+        } else {
+            Err(err::CubeProgrammerError::from(error).into())
+        }
+    }
+
+    pub fn connect_i2c(&self, i2c: &I2cBootloader) -> Result<(), err::Error> {
+        self.stlink.replace(None);
+        let error = unsafe { (self.vtable.connect_i2c_bootloader)(i2c.i2c_connect_parameters) };
+        if error == 0 {
+            Ok(())
+        } else {
+            Err(err::CubeProgrammerError::from(error).into())
+        }
+    }
+
+    /// Connect to a classic CAN bootloader, FDCAN is not supported.
+    pub fn connect_can(&self, can: &CanBootloader) -> Result<(), err::Error> {
+        self.stlink.replace(None);
+        let error = unsafe { (self.vtable.connect_can_bootloader)(can.can_connect_parameters) };
+        if error == 0 {
+            Ok(())
+        } else {
+            Err(err::CubeProgrammerError::from(error).into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spi_defaults() {
+        let mut spi = SpiBootloader::default();
+        assert_eq!(spi.baudrate(), 375);
+        assert_eq!(spi.mode(), SpiMode::Mode0);
+        assert_eq!(spi.nss(), SpiNss::Hardware);
+        assert!(spi.nss_pulse());
+        assert!(spi.delay());
+        assert_eq!(spi.crc_polynomial(), None);
+
+        spi.set_mode(SpiMode::Mode2);
+        assert_eq!(
+            (
+                spi.spi_connect_parameters.cpol,
+                spi.spi_connect_parameters.cpha
+            ),
+            (1, 0)
+        );
+        assert_eq!(spi.mode(), SpiMode::Mode2);
+        spi.set_crc_polynomial(Some(0x1021));
+        assert_eq!(spi.crc_polynomial(), Some(0x1021));
+        spi.set_crc_polynomial(None);
+        assert_eq!(spi.spi_connect_parameters.crc_polynomial, 7);
+    }
+
+    #[test]
+    fn i2c_defaults() {
+        let mut i2c = I2cBootloader::new(0x38);
+        assert_eq!(i2c.address(), 0x38);
+        assert_eq!(i2c.baudrate(), 400);
+        assert_eq!(i2c.speed(), I2cSpeed::Fast);
+        assert!(i2c.analog_filter());
+        assert_eq!(i2c.digital_filter(), None);
+
+        i2c.set_digital_filter(Some(3));
+        assert_eq!(i2c.digital_filter(), Some(3));
+        i2c.set_speed(I2cSpeed::Standard);
+        assert_eq!(i2c.speed(), I2cSpeed::Standard);
+    }
+
+    #[test]
+    fn can_defaults() {
+        let mut can = CanBootloader::default();
+        assert_eq!(can.bitrate(), 125);
+        assert_eq!(can.mode(), CanMode::Normal);
+        assert_eq!(can.identifier(), CanIdentifier::Standard);
+        assert!(!can.remote_frame());
+        assert_eq!(can.filter(), Some(CanFilter::default()));
+
+        can.set_filter(None);
+        assert_eq!(can.filter(), None);
+        assert_eq!(can.can_connect_parameters.filter_enable, 0);
+    }
+}
@@ -0,0 +1,343 @@
+use crate::err;
+use crate::{Register, STM32CubeProg};
+
+const READ_BACK_CHUNK: u32 = 0x400;
+
+/// Thumb routine computing the CRC of `r1` words starting at `r0` with the
+/// CRC peripheral at `r2`, leaving the result in `r0` and halting on `bkpt`.
+const CRC_ROUTINE: [u16; 11] = [
+    0x2301, // movs r3, #1
+    0x6093, // str  r3, [r2, #8]   ; CRC_CR = RESET
+    0x2900, // loop: cmp r1, #0
+    0xD004, // beq  done
+    0x6803, // ldr  r3, [r0]
+    0x6013, // str  r3, [r2]       ; CRC_DR = word
+    0x3004, // adds r0, #4
+    0x3901, // subs r1, #1
+    0xE7F8, // b    loop
+    0x6810, // done: ldr r0, [r2]
+    0xBE00, // bkpt #0
+];
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB88320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+const fn stm32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = (i as u32) << 24;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x80000000 != 0 {
+                (crc << 1) ^ 0x04C11DB7
+            } else {
+                crc << 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+static CRC32_TABLE: [u32; 256] = crc32_table();
+static STM32_TABLE: [u32; 256] = stm32_table();
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ChecksumAlgorithm {
+    /// Standard CRC-32 (IEEE 802.3, as used by zlib and most host tools).
+    Crc32,
+    /// Default configuration of the STM32 CRC peripheral: polynomial
+    /// 0x04C11DB7, initial value 0xFFFFFFFF, fed with little-endian words.
+    Stm32,
+}
+
+/// Incremental host side checksum computation.
+#[derive(Debug, Clone)]
+pub struct Checksum {
+    algorithm: ChecksumAlgorithm,
+    value: u32,
+    pending: [u8; 4],
+    pending_len: usize,
+}
+
+impl Checksum {
+    pub fn new(algorithm: ChecksumAlgorithm) -> Self {
+        Checksum {
+            algorithm,
+            value: 0xFFFFFFFF,
+            pending: [0; 4],
+            pending_len: 0,
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        match self.algorithm {
+            ChecksumAlgorithm::Crc32 => {
+                for &byte in data {
+                    let index = ((self.value ^ u32::from(byte)) & 0xFF) as usize;
+                    self.value = (self.value >> 8) ^ CRC32_TABLE[index];
+                }
+            }
+            ChecksumAlgorithm::Stm32 => {
+                for &byte in data {
+                    self.pending[self.pending_len] = byte;
+                    self.pending_len += 1;
+                    if self.pending_len == 4 {
+                        let word = u32::from_le_bytes(self.pending);
+                        for byte in word.to_be_bytes().iter() {
+                            let index = ((self.value >> 24) ^ u32::from(*byte)) as usize;
+                            self.value = (self.value << 8) ^ STM32_TABLE[index];
+                        }
+                        self.pending_len = 0;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Checksum of the data so far. `ChecksumAlgorithm::Stm32` only accepts
+    /// whole words and fails with `PartialWord` otherwise.
+    pub fn finalize(&self) -> Result<u32, err::Error> {
+        match self.algorithm {
+            ChecksumAlgorithm::Crc32 => Ok(!self.value),
+            ChecksumAlgorithm::Stm32 if self.pending_len != 0 => {
+                Err(err::Error::PartialWord(self.pending_len))
+            }
+            ChecksumAlgorithm::Stm32 => Ok(self.value),
+        }
+    }
+
+    pub fn compute(algorithm: ChecksumAlgorithm, data: &[u8]) -> Result<u32, err::Error> {
+        let mut checksum = Checksum::new(algorithm);
+        checksum.update(data);
+        checksum.finalize()
+    }
+}
+
+/// Parameters of the RAM-resident routine driving the target CRC peripheral.
+///
+/// Running the routine overwrites `ram_address` (a few dozen bytes), the core
+/// registers R0 to R3 and PC, and leaves the core halted.
+#[derive(Debug, Copy, Clone)]
+pub struct OnTargetCrc {
+    pub crc_base: u32,
+    pub clock_enable_register: u32,
+    pub clock_enable_bit: u32,
+    pub ram_address: u32,
+    pub timeout: std::time::Duration,
+}
+
+impl OnTargetCrc {
+    /// Known CRC peripheral and clock locations for the given device id.
+    pub fn for_device_id(device_id: i32) -> Option<Self> {
+        let (crc_base, clock_enable_register, clock_enable_bit) = match device_id {
+            // STM32F0, STM32F1, STM32F3
+            0x440 | 0x442 | 0x444 | 0x445 | 0x448 | 0x410 | 0x412 | 0x414 | 0x418 | 0x420
+            | 0x428 | 0x430 | 0x422 | 0x432 | 0x438 | 0x439 | 0x446 => (0x40023000, 0x40021014, 6),
+            // STM32F2, STM32F4, STM32F7
+            0x411 | 0x413 | 0x419 | 0x421 | 0x423 | 0x431 | 0x433 | 0x434 | 0x441 | 0x458
+            | 0x463 | 0x449 | 0x451 | 0x452 => (0x40023000, 0x40023830, 12),
+            // STM32L4, STM32G4
+            0x415 | 0x435 | 0x461 | 0x462 | 0x464 | 0x470 | 0x471 | 0x468 | 0x469 | 0x479 => {
+                (0x40023000, 0x40021048, 12)
+            }
+            // STM32G0
+            0x456 | 0x460 | 0x466 | 0x467 => (0x40023000, 0x40021038, 12),
+            // STM32L0
+            0x417 | 0x425 | 0x447 | 0x457 => (0x40023000, 0x40021030, 12),
+            // STM32H7
+            0x450 | 0x480 | 0x483 => (0x58024C00, 0x580244E0, 19),
+            _ => return None,
+        };
+
+        Some(OnTargetCrc {
+            crc_base,
+            clock_enable_register,
+            clock_enable_bit,
+            ram_address: 0x20000000,
+            timeout: std::time::Duration::from_secs(5),
+        })
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum ChecksumMethod {
+    /// Read the memory back through the probe and compute the checksum on the host.
+    ReadBack,
+    /// Let the target CRC peripheral compute the checksum (`ChecksumAlgorithm::Stm32` only).
+    OnTarget(OnTargetCrc),
+}
+
+impl STM32CubeProg {
+    pub fn checksum(
+        &self,
+        address: u32,
+        size: u32,
+        algorithm: ChecksumAlgorithm,
+        method: ChecksumMethod,
+    ) -> Result<u32, err::Error> {
+        match method {
+            ChecksumMethod::ReadBack => self.checksum_read_back(address, size, algorithm),
+            ChecksumMethod::OnTarget(_) if algorithm != ChecksumAlgorithm::Stm32 => {
+                Err(err::CubeProgrammerError::UnsupportedOperation.into())
+            }
+            ChecksumMethod::OnTarget(parameters) => {
+                self.checksum_on_target(address, size, &parameters)
+            }
+        }
+    }
+
+    /// Compare the checksum of the memory at `address` with the one of `image`.
+    pub fn verify_checksum(
+        &self,
+        address: u32,
+        image: &[u8],
+        algorithm: ChecksumAlgorithm,
+        method: ChecksumMethod,
+    ) -> Result<u32, err::Error> {
+        let size: u32 = std::convert::TryInto::try_into(image.len())?;
+        let expected = Checksum::compute(algorithm, image)
+            .map_err(|_| err::Error::UnalignedAccess { address, size })?;
+        let actual = self.checksum(address, size, algorithm, method)?;
+
+        if expected == actual {
+            Ok(actual)
+        } else {
+            Err(err::Error::ChecksumMismatch {
+                address,
+                expected,
+                actual,
+            })
+        }
+    }
+
+    fn checksum_read_back(
+        &self,
+        address: u32,
+        size: u32,
+        algorithm: ChecksumAlgorithm,
+    ) -> Result<u32, err::Error> {
+        let mut checksum = Checksum::new(algorithm);
+        let mut offset = 0;
+        while offset < size {
+            let chunk = std::cmp::min(READ_BACK_CHUNK, size - offset);
+            checksum.update(&self.read_memory8(address + offset, chunk)?);
+            offset += chunk;
+        }
+
+        checksum
+            .finalize()
+            .map_err(|_| err::Error::UnalignedAccess { address, size })
+    }
+
+    fn checksum_on_target(
+        &self,
+        address: u32,
+        size: u32,
+        parameters: &OnTargetCrc,
+    ) -> Result<u32, err::Error> {
+        if !address.is_multiple_of(4) || !size.is_multiple_of(4) {
+            return Err(err::Error::UnalignedAccess { address, size });
+        }
+
+        // The routine overwrites RAM and core registers, reading back gives
+        // the same checksum without touching the target
+        if self.planned(crate::dry_run::PlannedOperation::OnTargetChecksum {
+            address,
+            size,
+            ram_address: parameters.ram_address,
+        }) {
+            return self.checksum_read_back(address, size, ChecksumAlgorithm::Stm32);
+        }
+
+        self.halt()?;
+
+        let clock = self.read_memory32(parameters.clock_enable_register, 1)?[0];
+        self.write_memory32(
+            parameters.clock_enable_register,
+            vec![clock | (1 << parameters.clock_enable_bit)],
+        )?;
+
+        let routine = CRC_ROUTINE
+            .iter()
+            .flat_map(|instruction| instruction.to_le_bytes().to_vec())
+            .collect();
+        self.write_memory8(parameters.ram_address, routine)?;
+
+        self.write_core_register(Register::R0, address)?;
+        self.write_core_register(Register::R1, size / 4)?;
+        self.write_core_register(Register::R2, parameters.crc_base)?;
+        self.write_core_register(Register::PC, parameters.ram_address)?;
+
+        self.resume()?;
+
+        let start = std::time::Instant::now();
+        while !self.is_halted()? {
+            if start.elapsed() > parameters.timeout {
+                self.halt()?;
+                return Err(err::Error::Timeout);
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+
+        self.read_core_register(Register::R0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(
+            Checksum::compute(ChecksumAlgorithm::Crc32, b"123456789").unwrap(),
+            0xCBF43926
+        );
+    }
+
+    #[test]
+    fn stm32_crc_of_one_word() {
+        let data = 0x12345678u32.to_le_bytes();
+        assert_eq!(
+            Checksum::compute(ChecksumAlgorithm::Stm32, &data).unwrap(),
+            0xDF8A8A2B
+        );
+    }
+
+    #[test]
+    fn stm32_crc_is_computed_incrementally() {
+        let mut checksum = Checksum::new(ChecksumAlgorithm::Stm32);
+        checksum.update(&[0x78, 0x56]);
+        checksum.update(&[0x34, 0x12]);
+        assert_eq!(checksum.finalize().unwrap(), 0xDF8A8A2B);
+    }
+
+    #[test]
+    fn stm32_crc_rejects_partial_words() {
+        match Checksum::compute(ChecksumAlgorithm::Stm32, b"12345") {
+            Err(err::Error::PartialWord(1)) => {}
+            result => panic!("unexpected {:?}", result),
+        }
+    }
+}
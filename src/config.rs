@@ -0,0 +1,558 @@
+use crate::err;
+use crate::{DebugConnectMode, DebugPort, DebugResetMode, DebugSpeed, STLink};
+
+impl std::fmt::Display for DebugPort {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DebugPort::Jtag => write!(f, "jtag"),
+            DebugPort::Swd => write!(f, "swd"),
+        }
+    }
+}
+
+impl std::str::FromStr for DebugPort {
+    type Err = err::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "jtag" => Ok(DebugPort::Jtag),
+            "swd" => Ok(DebugPort::Swd),
+            _ => Err(err::Error::InvalidConfig(format!(
+                "unknown debug port '{}'",
+                s
+            ))),
+        }
+    }
+}
+
+impl std::fmt::Display for DebugConnectMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DebugConnectMode::Normal => write!(f, "normal"),
+            DebugConnectMode::HotPlug => write!(f, "hot-plug"),
+            DebugConnectMode::UnderReset => write!(f, "under-reset"),
+            DebugConnectMode::PowerDown => write!(f, "power-down"),
+            DebugConnectMode::PreReset => write!(f, "pre-reset"),
+        }
+    }
+}
+
+impl std::str::FromStr for DebugConnectMode {
+    type Err = err::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().replace('_', "-").as_str() {
+            "normal" => Ok(DebugConnectMode::Normal),
+            "hot-plug" | "hotplug" => Ok(DebugConnectMode::HotPlug),
+            "under-reset" => Ok(DebugConnectMode::UnderReset),
+            "power-down" => Ok(DebugConnectMode::PowerDown),
+            "pre-reset" => Ok(DebugConnectMode::PreReset),
+            _ => Err(err::Error::InvalidConfig(format!(
+                "unknown connection mode '{}'",
+                s
+            ))),
+        }
+    }
+}
+
+impl std::fmt::Display for DebugResetMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DebugResetMode::SoftwareReset => write!(f, "software"),
+            DebugResetMode::HardwareReset => write!(f, "hardware"),
+            DebugResetMode::CoreReset => write!(f, "core"),
+        }
+    }
+}
+
+impl std::str::FromStr for DebugResetMode {
+    type Err = err::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "software" | "sw" => Ok(DebugResetMode::SoftwareReset),
+            "hardware" | "hw" => Ok(DebugResetMode::HardwareReset),
+            "core" => Ok(DebugResetMode::CoreReset),
+            _ => Err(err::Error::InvalidConfig(format!(
+                "unknown reset mode '{}'",
+                s
+            ))),
+        }
+    }
+}
+
+impl std::fmt::Display for DebugSpeed {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DebugSpeed::Reliable => write!(f, "reliable"),
+            DebugSpeed::Fast => write!(f, "fast"),
+        }
+    }
+}
+
+impl std::str::FromStr for DebugSpeed {
+    type Err = err::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "reliable" => Ok(DebugSpeed::Reliable),
+            "fast" => Ok(DebugSpeed::Fast),
+            _ => Err(err::Error::InvalidConfig(format!("unknown speed '{}'", s))),
+        }
+    }
+}
+
+/// Connection parameters applied on top of a discovered `STLink`. Unset
+/// parameters keep the values reported by the probe.
+///
+/// The configuration is stored as `key = value` lines, a subset of TOML.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConnectionConfig {
+    debug_port: Option<DebugPort>,
+    frequency: Option<u32>,
+    connection_mode: Option<DebugConnectMode>,
+    reset_mode: Option<DebugResetMode>,
+    access_port: Option<i32>,
+    speed: Option<DebugSpeed>,
+    debug_sleep: Option<bool>,
+    shared: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionConfigBuilder {
+    config: ConnectionConfig,
+}
+
+impl ConnectionConfigBuilder {
+    pub fn debug_port(mut self, debug_port: DebugPort) -> Self {
+        self.config.debug_port = Some(debug_port);
+        self
+    }
+
+    /// Frequency in kHz, checked against the frequencies supported by the probe.
+    pub fn frequency(mut self, frequency: u32) -> Self {
+        self.config.frequency = Some(frequency);
+        self
+    }
+
+    pub fn connection_mode(mut self, connection_mode: DebugConnectMode) -> Self {
+        self.config.connection_mode = Some(connection_mode);
+        self
+    }
+
+    pub fn reset_mode(mut self, reset_mode: DebugResetMode) -> Self {
+        self.config.reset_mode = Some(reset_mode);
+        self
+    }
+
+    pub fn access_port(mut self, access_port: i32) -> Self {
+        self.config.access_port = Some(access_port);
+        self
+    }
+
+    pub fn speed(mut self, speed: DebugSpeed) -> Self {
+        self.config.speed = Some(speed);
+        self
+    }
+
+    pub fn debug_sleep(mut self, debug_sleep: bool) -> Self {
+        self.config.debug_sleep = Some(debug_sleep);
+        self
+    }
+
+    pub fn shared(mut self, shared: bool) -> Self {
+        self.config.shared = Some(shared);
+        self
+    }
+
+    pub fn build(self) -> Result<ConnectionConfig, err::Error> {
+        self.config.validate()?;
+        Ok(self.config)
+    }
+}
+
+impl ConnectionConfig {
+    pub fn builder() -> ConnectionConfigBuilder {
+        ConnectionConfigBuilder::default()
+    }
+
+    pub fn debug_port(&self) -> Option<DebugPort> {
+        self.debug_port
+    }
+
+    pub fn frequency(&self) -> Option<u32> {
+        self.frequency
+    }
+
+    pub fn connection_mode(&self) -> Option<DebugConnectMode> {
+        self.connection_mode
+    }
+
+    pub fn reset_mode(&self) -> Option<DebugResetMode> {
+        self.reset_mode
+    }
+
+    pub fn access_port(&self) -> Option<i32> {
+        self.access_port
+    }
+
+    pub fn speed(&self) -> Option<DebugSpeed> {
+        self.speed
+    }
+
+    pub fn debug_sleep(&self) -> Option<bool> {
+        self.debug_sleep
+    }
+
+    pub fn shared(&self) -> Option<bool> {
+        self.shared
+    }
+
+    /// Check the parameters that do not depend on the probe.
+    pub fn validate(&self) -> Result<(), err::Error> {
+        if let Some(frequency) = self.frequency {
+            if frequency == 0 || frequency > i32::MAX as u32 {
+                return Err(err::Error::InvalidConfig(format!(
+                    "invalid frequency {} kHz",
+                    frequency
+                )));
+            }
+        }
+
+        if let Some(access_port) = self.access_port {
+            if !(0..=255).contains(&access_port) {
+                return Err(err::Error::InvalidConfig(format!(
+                    "invalid access port {}",
+                    access_port
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check the parameters against the capabilities reported by `stlink`.
+    pub fn validate_for(&self, stlink: &STLink) -> Result<(), err::Error> {
+        self.validate()?;
+
+        let debug_port = self.debug_port.unwrap_or(stlink.debug_port());
+        if let Some(frequency) = self.frequency {
+            let supported = stlink.frequencies().frequencies(debug_port);
+            if !supported.is_empty() && !supported.contains(&frequency) {
+                return Err(err::Error::InvalidConfig(format!(
+                    "{} kHz is not supported in {}, expected one of {:?}",
+                    frequency, debug_port, supported
+                )));
+            }
+        }
+
+        if let Some(access_port) = self.access_port {
+            if stlink.access_port_count() > 0 && access_port >= stlink.access_port_count() {
+                return Err(err::Error::InvalidConfig(format!(
+                    "access port {} is out of the {} available",
+                    access_port,
+                    stlink.access_port_count()
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Validate the configuration and write it into `stlink`.
+    pub fn apply(&self, stlink: &mut STLink) -> Result<(), err::Error> {
+        self.validate_for(stlink)?;
+
+        if let Some(debug_port) = self.debug_port {
+            stlink.set_debug_port(debug_port);
+        }
+        if let Some(frequency) = self.frequency {
+            stlink.set_frequency(frequency as i32);
+        }
+        if let Some(connection_mode) = self.connection_mode {
+            stlink.set_connection_mode(connection_mode);
+        }
+        if let Some(reset_mode) = self.reset_mode {
+            stlink.set_reset_mode(reset_mode);
+        }
+        if let Some(access_port) = self.access_port {
+            stlink.set_access_port(access_port);
+        }
+        if let Some(speed) = self.speed {
+            stlink.set_speed(speed);
+        }
+        if let Some(debug_sleep) = self.debug_sleep {
+            stlink.set_debug_sleep(debug_sleep);
+        }
+        if let Some(shared) = self.shared {
+            stlink.set_shared(shared);
+        }
+
+        Ok(())
+    }
+
+    /// Set a parameter from its configuration file `key` and `value`.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), err::Error> {
+        let value = value.trim().trim_matches('"');
+        let invalid =
+            |_| err::Error::InvalidConfig(format!("invalid value '{}' for {}", value, key));
+
+        match key.trim() {
+            "debug_port" => self.debug_port = Some(value.parse()?),
+            "frequency" => self.frequency = Some(value.parse().map_err(invalid)?),
+            "connection_mode" => self.connection_mode = Some(value.parse()?),
+            "reset_mode" => self.reset_mode = Some(value.parse()?),
+            "access_port" => self.access_port = Some(value.parse().map_err(invalid)?),
+            "speed" => self.speed = Some(value.parse()?),
+            "debug_sleep" => self.debug_sleep = Some(parse_bool(value)?),
+            "shared" => self.shared = Some(parse_bool(value)?),
+            key => {
+                return Err(err::Error::InvalidConfig(format!(
+                    "unknown parameter '{}'",
+                    key
+                )))
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<std::path::Path>>(path: P) -> Result<Self, err::Error> {
+        std::fs::read_to_string(path)?.parse()
+    }
+
+    pub fn save<P: AsRef<std::path::Path>>(&self, path: P) -> Result<(), err::Error> {
+        Ok(std::fs::write(path, self.to_string())?)
+    }
+}
+
+fn parse_bool(value: &str) -> Result<bool, err::Error> {
+    match value {
+        "true" | "1" | "on" | "yes" => Ok(true),
+        "false" | "0" | "off" | "no" => Ok(false),
+        _ => Err(err::Error::InvalidConfig(format!(
+            "invalid boolean '{}'",
+            value
+        ))),
+    }
+}
+
+impl std::fmt::Display for ConnectionConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if let Some(debug_port) = self.debug_port {
+            writeln!(f, "debug_port = \"{}\"", debug_port)?;
+        }
+        if let Some(frequency) = self.frequency {
+            writeln!(f, "frequency = {}", frequency)?;
+        }
+        if let Some(connection_mode) = self.connection_mode {
+            writeln!(f, "connection_mode = \"{}\"", connection_mode)?;
+        }
+        if let Some(reset_mode) = self.reset_mode {
+            writeln!(f, "reset_mode = \"{}\"", reset_mode)?;
+        }
+        if let Some(access_port) = self.access_port {
+            writeln!(f, "access_port = {}", access_port)?;
+        }
+        if let Some(speed) = self.speed {
+            writeln!(f, "speed = \"{}\"", speed)?;
+        }
+        if let Some(debug_sleep) = self.debug_sleep {
+            writeln!(f, "debug_sleep = {}", debug_sleep)?;
+        }
+        if let Some(shared) = self.shared {
+            writeln!(f, "shared = {}", shared)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for ConnectionConfig {
+    type Err = err::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut config = ConnectionConfig::default();
+        for line in s.lines() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| err::Error::InvalidConfig(format!("invalid line '{}'", line)))?;
+            config.set(key, value)?;
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// SWD probe supporting 4000 and 1000 kHz, with two access ports.
+    fn stlink() -> STLink {
+        let mut parameters: crate::DebugConnectParameters = unsafe { std::mem::zeroed() };
+        parameters.debug_port = DebugPort::Swd;
+        parameters.frequencies.swd_freq[..2].copy_from_slice(&[4000, 1000]);
+        parameters.frequencies.swd_freq_count = 2;
+        parameters.frequencies.jtag_freq[0] = 9000;
+        parameters.frequencies.jtag_freq_count = 1;
+        parameters.access_port_count = 2;
+        STLink {
+            debug_connect_parameters: parameters,
+        }
+    }
+
+    #[test]
+    fn enums_round_trip_through_strings() {
+        for port in [DebugPort::Jtag, DebugPort::Swd].iter() {
+            assert_eq!(port.to_string().parse::<DebugPort>().unwrap(), *port);
+        }
+        for mode in [
+            DebugConnectMode::Normal,
+            DebugConnectMode::HotPlug,
+            DebugConnectMode::UnderReset,
+            DebugConnectMode::PowerDown,
+            DebugConnectMode::PreReset,
+        ]
+        .iter()
+        {
+            assert_eq!(mode.to_string().parse::<DebugConnectMode>().unwrap(), *mode);
+        }
+        for mode in [
+            DebugResetMode::SoftwareReset,
+            DebugResetMode::HardwareReset,
+            DebugResetMode::CoreReset,
+        ]
+        .iter()
+        {
+            assert_eq!(mode.to_string().parse::<DebugResetMode>().unwrap(), *mode);
+        }
+        for speed in [DebugSpeed::Reliable, DebugSpeed::Fast].iter() {
+            assert_eq!(speed.to_string().parse::<DebugSpeed>().unwrap(), *speed);
+        }
+    }
+
+    #[test]
+    fn enums_accept_aliases_and_reject_unknown_names() {
+        assert_eq!("SWD".parse::<DebugPort>().unwrap(), DebugPort::Swd);
+        assert_eq!(
+            "UNDER_RESET".parse::<DebugConnectMode>().unwrap(),
+            DebugConnectMode::UnderReset
+        );
+        assert_eq!(
+            "hotplug".parse::<DebugConnectMode>().unwrap(),
+            DebugConnectMode::HotPlug
+        );
+        assert_eq!(
+            "hw".parse::<DebugResetMode>().unwrap(),
+            DebugResetMode::HardwareReset
+        );
+        assert!("spi".parse::<DebugPort>().is_err());
+        assert!("sleep".parse::<DebugConnectMode>().is_err());
+        assert!("soft".parse::<DebugResetMode>().is_err());
+        assert!("slow".parse::<DebugSpeed>().is_err());
+    }
+
+    #[test]
+    fn file_round_trip() {
+        let config = ConnectionConfig::builder()
+            .debug_port(DebugPort::Swd)
+            .frequency(4000)
+            .connection_mode(DebugConnectMode::UnderReset)
+            .reset_mode(DebugResetMode::HardwareReset)
+            .access_port(1)
+            .speed(DebugSpeed::Fast)
+            .debug_sleep(false)
+            .shared(true)
+            .build()
+            .unwrap();
+
+        let text = config.to_string();
+        assert_eq!(
+            text,
+            "debug_port = \"swd\"\nfrequency = 4000\nconnection_mode = \"under-reset\"\n\
+             reset_mode = \"hardware\"\naccess_port = 1\nspeed = \"fast\"\n\
+             debug_sleep = false\nshared = true\n"
+        );
+        assert_eq!(text.parse::<ConnectionConfig>().unwrap(), config);
+        assert_eq!(
+            "".parse::<ConnectionConfig>().unwrap(),
+            ConnectionConfig::default()
+        );
+    }
+
+    #[test]
+    fn files_with_comments_and_errors() {
+        let config: ConnectionConfig = "# bench\n\nfrequency = 1000 # slow target\nshared = yes\n"
+            .parse()
+            .unwrap();
+        assert_eq!(config.frequency(), Some(1000));
+        assert_eq!(config.shared(), Some(true));
+
+        for text in [
+            "frequency",
+            "frequency = fast",
+            "frequency = 0",
+            "access_port = 256",
+            "shared = maybe",
+            "voltage = 3.3",
+        ]
+        .iter()
+        {
+            match text.parse::<ConnectionConfig>() {
+                Err(err::Error::InvalidConfig(_)) => {}
+                result => panic!("unexpected {:?} for {}", result, text),
+            }
+        }
+    }
+
+    #[test]
+    fn configuration_is_validated_for_the_probe() {
+        let stlink = stlink();
+        let valid = ConnectionConfig::builder()
+            .frequency(1000)
+            .access_port(1)
+            .build()
+            .unwrap();
+        assert!(valid.validate_for(&stlink).is_ok());
+
+        let unsupported = ConnectionConfig::builder().frequency(2000).build().unwrap();
+        assert!(unsupported.validate_for(&stlink).is_err());
+
+        // Frequencies are checked against the configured debug port
+        let jtag = ConnectionConfig::builder()
+            .debug_port(DebugPort::Jtag)
+            .frequency(9000)
+            .build()
+            .unwrap();
+        assert!(jtag.validate_for(&stlink).is_ok());
+        let swd = ConnectionConfig::builder().frequency(9000).build().unwrap();
+        assert!(swd.validate_for(&stlink).is_err());
+
+        let access_port = ConnectionConfig::builder().access_port(2).build().unwrap();
+        assert!(access_port.validate_for(&stlink).is_err());
+    }
+
+    #[test]
+    fn configuration_is_applied() {
+        let mut stlink = stlink();
+        let config = ConnectionConfig::builder()
+            .frequency(1000)
+            .connection_mode(DebugConnectMode::HotPlug)
+            .build()
+            .unwrap();
+        config.apply(&mut stlink).unwrap();
+        assert_eq!(stlink.frequency(), 1000);
+        assert_eq!(stlink.connection_mode(), DebugConnectMode::HotPlug);
+        assert_eq!(stlink.reset_mode(), DebugResetMode::SoftwareReset);
+
+        let invalid = ConnectionConfig::builder().frequency(2000).build().unwrap();
+        assert!(invalid.apply(&mut stlink).is_err());
+        assert_eq!(stlink.frequency(), 1000);
+    }
+}
@@ -0,0 +1,225 @@
+use crate::err;
+use crate::{DebugConnectMode, DebugResetMode, STLink, STM32CubeProg};
+
+/// Cortex-M CPUID register, read to check that a connection is usable.
+const CPUID: u32 = 0xE000ED00;
+
+/// Ordered connection attempts made by `connect_with_strategy`.
+#[derive(Debug, Clone)]
+pub struct ConnectStrategy {
+    /// Connection and reset modes, tried in order.
+    pub modes: Vec<(DebugConnectMode, DebugResetMode)>,
+    /// Additional attempts of each mode and frequency.
+    pub retries: u32,
+    /// Pause between two attempts.
+    pub delay: std::time::Duration,
+    /// Also try the supported frequencies below the configured one.
+    pub frequency_fallback: bool,
+}
+
+impl Default for ConnectStrategy {
+    fn default() -> Self {
+        ConnectStrategy {
+            modes: vec![
+                (DebugConnectMode::Normal, DebugResetMode::SoftwareReset),
+                (DebugConnectMode::UnderReset, DebugResetMode::HardwareReset),
+                (DebugConnectMode::PowerDown, DebugResetMode::HardwareReset),
+            ],
+            retries: 1,
+            delay: std::time::Duration::from_millis(100),
+            frequency_fallback: true,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ConnectAttempt {
+    pub connection_mode: DebugConnectMode,
+    pub reset_mode: DebugResetMode,
+    pub frequency: i32,
+    pub result: Result<(), err::Error>,
+}
+
+impl ConnectAttempt {
+    fn of(stlink: &STLink, result: Result<(), err::Error>) -> Self {
+        ConnectAttempt {
+            connection_mode: stlink.connection_mode(),
+            reset_mode: stlink.reset_mode(),
+            frequency: stlink.frequency(),
+            result,
+        }
+    }
+}
+
+impl std::fmt::Display for ConnectAttempt {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{:?}/{:?} at {} kHz: ",
+            self.connection_mode, self.reset_mode, self.frequency
+        )?;
+        match &self.result {
+            Ok(()) => write!(f, "connected"),
+            Err(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+/// Every attempt made by `connect_with_strategy`, the last one being the
+/// successful one if any.
+#[derive(Debug, Default)]
+pub struct ConnectReport {
+    pub attempts: Vec<ConnectAttempt>,
+}
+
+impl ConnectReport {
+    pub fn succeeded(&self) -> Option<&ConnectAttempt> {
+        self.attempts
+            .last()
+            .filter(|attempt| attempt.result.is_ok())
+    }
+}
+
+impl std::fmt::Display for ConnectReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for (index, attempt) in self.attempts.iter().enumerate() {
+            if index > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "Attempt {}: {}", index + 1, attempt)?;
+        }
+        Ok(())
+    }
+}
+
+impl STM32CubeProg {
+    fn check_connection(&self) -> Result<(), err::Error> {
+        self.device_info()?;
+        self.read::<u32>(CPUID)?;
+        Ok(())
+    }
+
+    fn connect_at(&self, stlink: &mut STLink, frequency: u32) -> Result<(), err::Error> {
+        stlink.set_frequency(std::convert::TryInto::try_into(frequency)?);
+        self.connect(stlink)?;
+        if let Err(error) = self.check_connection() {
+            self.disconnect();
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    /// Connect at the fastest supported frequency of the debug port, stepping
+    /// down through the frequency table until the connection and a first read
+    /// succeed. Returns the selected frequency in Hz.
+    pub fn connect_auto_frequency(&self, stlink: &mut STLink) -> Result<u32, err::Error> {
+        let frequencies = stlink.frequencies().frequencies(stlink.debug_port());
+        let mut last_error = err::Error::from(err::CubeProgrammerError::FrequencyError);
+
+        for frequency in frequencies {
+            match self.connect_at(stlink, frequency) {
+                Ok(()) => return Ok(frequency * 1000),
+                Err(error) => last_error = error,
+            }
+        }
+
+        Err(last_error)
+    }
+
+    /// Reconnect the current ST-Link at the next lower supported frequency,
+    /// typically after read failures (see `TransferOptions::step_down`).
+    /// Returns the new frequency in Hz, or `None` when already at the lowest
+    /// one.
+    ///
+    /// When no lower frequency works, the original one is restored and the
+    /// error of the last attempt returned. If the restore fails too, the
+    /// session is left disconnected and `ConnectionFailed` lists every attempt.
+    pub fn step_down_frequency(&self) -> Result<Option<u32>, err::Error> {
+        let mut stlink = self
+            .connected_stlink()
+            .ok_or(err::CubeProgrammerError::DeviceNotConnected)?;
+        let current = stlink.frequency();
+        let lower: Vec<u32> = stlink
+            .frequencies()
+            .frequencies(stlink.debug_port())
+            .into_iter()
+            .filter(|&frequency| i64::from(frequency) < i64::from(current))
+            .collect();
+
+        if lower.is_empty() {
+            return Ok(None);
+        }
+
+        let current = std::convert::TryInto::try_into(current)?;
+        self.disconnect();
+        let mut report = ConnectReport::default();
+        for frequency in lower {
+            match self.connect_at(&mut stlink, frequency) {
+                Ok(()) => return Ok(Some(frequency * 1000)),
+                Err(error) => report
+                    .attempts
+                    .push(ConnectAttempt::of(&stlink, Err(error))),
+            }
+        }
+
+        // Restore the connection that was working
+        match self.connect_at(&mut stlink, current) {
+            Ok(()) => Err(report
+                .attempts
+                .pop()
+                .and_then(|attempt| attempt.result.err())
+                .unwrap_or_else(|| err::CubeProgrammerError::FrequencyError.into())),
+            Err(error) => {
+                report
+                    .attempts
+                    .push(ConnectAttempt::of(&stlink, Err(error)));
+                Err(err::Error::ConnectionFailed(report))
+            }
+        }
+    }
+
+    /// Try each mode of `strategy` (and lower frequencies if enabled) until a
+    /// connection succeeds. `stlink` is left configured with the working
+    /// parameters.
+    pub fn connect_with_strategy(
+        &self,
+        stlink: &mut STLink,
+        strategy: &ConnectStrategy,
+    ) -> Result<ConnectReport, err::Error> {
+        let configured: u32 = std::convert::TryInto::try_into(stlink.frequency())?;
+        let mut frequencies = vec![configured];
+        if strategy.frequency_fallback {
+            frequencies.extend(
+                stlink
+                    .frequencies()
+                    .frequencies(stlink.debug_port())
+                    .into_iter()
+                    .filter(|&frequency| frequency < configured),
+            );
+        }
+
+        let mut report = ConnectReport::default();
+        for &(connection_mode, reset_mode) in strategy.modes.iter() {
+            stlink.set_connection_mode(connection_mode);
+            stlink.set_reset_mode(reset_mode);
+
+            for &frequency in frequencies.iter() {
+                for _ in 0..=strategy.retries {
+                    if !report.attempts.is_empty() {
+                        std::thread::sleep(strategy.delay);
+                    }
+
+                    let result = self.connect_at(stlink, frequency);
+                    let connected = result.is_ok();
+                    report.attempts.push(ConnectAttempt::of(stlink, result));
+
+                    if connected {
+                        return Ok(report);
+                    }
+                }
+            }
+        }
+
+        Err(err::Error::ConnectionFailed(report))
+    }
+}
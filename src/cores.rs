@@ -0,0 +1,251 @@
+use crate::err;
+use crate::{DebugConnectMode, Register, STM32CubeProg};
+
+const DHCSR: u32 = 0xE000EDF0;
+const DHCSR_HALT: u32 = 0xA05F0003;
+const DHCSR_RUN: u32 = 0xA05F0001;
+const DHCSR_S_HALT: u32 = 1 << 17;
+
+const REGISTERS: [Register; 16] = [
+    Register::R0,
+    Register::R1,
+    Register::R2,
+    Register::R3,
+    Register::R4,
+    Register::R5,
+    Register::R6,
+    Register::R7,
+    Register::R8,
+    Register::R9,
+    Register::R10,
+    Register::R11,
+    Register::R12,
+    Register::SP,
+    Register::LR,
+    Register::PC,
+];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Core {
+    CortexM7,
+    CortexM4,
+    CortexM0Plus,
+    CortexA7,
+}
+
+/// Access port through which `core` is debugged.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CoreAccessPort {
+    pub core: Core,
+    pub access_port: i32,
+}
+
+const STM32H7_DUAL_CORE: [CoreAccessPort; 2] = [
+    CoreAccessPort {
+        core: Core::CortexM7,
+        access_port: 0,
+    },
+    CoreAccessPort {
+        core: Core::CortexM4,
+        access_port: 3,
+    },
+];
+
+const STM32WL_DUAL_CORE: [CoreAccessPort; 2] = [
+    CoreAccessPort {
+        core: Core::CortexM4,
+        access_port: 0,
+    },
+    CoreAccessPort {
+        core: Core::CortexM0Plus,
+        access_port: 1,
+    },
+];
+
+const STM32MP1: [CoreAccessPort; 2] = [
+    CoreAccessPort {
+        core: Core::CortexA7,
+        access_port: 0,
+    },
+    CoreAccessPort {
+        core: Core::CortexM4,
+        access_port: 2,
+    },
+];
+
+/// Whether `text` names one of `lines`, ignoring case.
+fn mentions(text: &str, lines: &[&str]) -> bool {
+    let text = text.to_ascii_uppercase();
+    lines.iter().any(|line| text.contains(line))
+}
+
+/// Cores of the multi-core devices known by the crate, `None` otherwise.
+///
+/// STM32H7 and STM32WL single-core lines share their device id with the
+/// dual-core ones, which are told apart by the `cpu` and `name` reported by
+/// `device_info`.
+pub fn core_access_ports(
+    device_id: i32,
+    cpu: &str,
+    name: &str,
+) -> Option<&'static [CoreAccessPort]> {
+    match device_id {
+        0x450 if mentions(cpu, &["M4"]) || mentions(name, &["H745", "H747", "H755", "H757"]) => {
+            Some(&STM32H7_DUAL_CORE)
+        }
+        0x497 if mentions(cpu, &["M0"]) || mentions(name, &["WL54", "WL55", "WL5X"]) => {
+            Some(&STM32WL_DUAL_CORE)
+        }
+        0x500 => Some(&STM32MP1),
+        _ => None,
+    }
+}
+
+impl STM32CubeProg {
+    fn device_cores(&self) -> Result<(i32, Option<&'static [CoreAccessPort]>), err::Error> {
+        let info = self.device_info()?;
+        let device_id = info.device_id();
+        Ok((
+            device_id,
+            core_access_ports(device_id, &info.cpu()?, &info.name()?),
+        ))
+    }
+
+    fn core_access_port(&self, core: Core) -> Result<i32, err::Error> {
+        let (device_id, cores) = self.device_cores()?;
+        cores
+            .and_then(|cores| cores.iter().find(|entry| entry.core == core))
+            .map(|entry| entry.access_port)
+            .ok_or(err::Error::CoreUnavailable { core, device_id })
+    }
+
+    /// Cores of the connected device that can be selected.
+    pub fn cores(&self) -> Result<Vec<Core>, err::Error> {
+        Ok(self
+            .device_cores()?
+            .1
+            .map(|cores| cores.iter().map(|entry| entry.core).collect())
+            .unwrap_or_default())
+    }
+
+    /// Core behind the access port of the current connection.
+    pub fn current_core(&self) -> Result<Option<Core>, err::Error> {
+        let stlink = self
+            .connected_stlink()
+            .ok_or(err::CubeProgrammerError::DeviceNotConnected)?;
+        Ok(self.device_cores()?.1.and_then(|cores| {
+            cores
+                .iter()
+                .find(|entry| entry.access_port == stlink.access_port())
+                .map(|entry| entry.core)
+        }))
+    }
+
+    /// Reconnect through the access port of `core`, without resetting the
+    /// device. Does nothing if `core` is already selected.
+    pub fn select_core(&self, core: Core) -> Result<(), err::Error> {
+        let current = self
+            .connected_stlink()
+            .ok_or(err::CubeProgrammerError::DeviceNotConnected)?;
+        let access_port = self.core_access_port(core)?;
+        if current.access_port() == access_port {
+            return Ok(());
+        }
+
+        let mut stlink = current.clone();
+        stlink.set_access_port(access_port);
+        stlink.set_connection_mode(DebugConnectMode::HotPlug);
+
+        self.disconnect();
+        match self.connect(&stlink) {
+            Ok(()) => Ok(()),
+            Err(error) => {
+                let mut previous = current;
+                previous.set_connection_mode(DebugConnectMode::HotPlug);
+                self.connect(&previous)?;
+                Err(error)
+            }
+        }
+    }
+
+    /// Fail if the selected core has no DHCSR, as Cortex-A7 cores.
+    fn check_dhcsr(&self) -> Result<(), err::Error> {
+        if self.connected_stlink().is_some() && self.current_core()? == Some(Core::CortexA7) {
+            return Err(err::CubeProgrammerError::UnsupportedOperation.into());
+        }
+        Ok(())
+    }
+
+    /// Halt the currently selected core.
+    ///
+    /// DHCSR is written directly, it is neither recorded by dry runs nor
+    /// checked by the safety policy.
+    pub fn halt(&self) -> Result<(), err::Error> {
+        self.check_dhcsr()?;
+        self.write_chunk(DHCSR, &DHCSR_HALT.to_le_bytes())
+    }
+
+    /// Resume the currently selected core, see `halt`.
+    pub fn resume(&self) -> Result<(), err::Error> {
+        self.check_dhcsr()?;
+        self.write_chunk(DHCSR, &DHCSR_RUN.to_le_bytes())
+    }
+
+    pub fn is_halted(&self) -> Result<bool, err::Error> {
+        self.check_dhcsr()?;
+        let mut dhcsr = [0; 4];
+        self.read_chunk(DHCSR, &mut dhcsr)?;
+        Ok(u32::from_le_bytes(dhcsr) & DHCSR_S_HALT != 0)
+    }
+
+    pub fn halt_core(&self, core: Core) -> Result<(), err::Error> {
+        self.select_core(core)?;
+        self.halt()
+    }
+
+    pub fn resume_core(&self, core: Core) -> Result<(), err::Error> {
+        self.select_core(core)?;
+        self.resume()
+    }
+
+    /// Read R0 to PC of the currently selected core.
+    pub fn read_registers(&self) -> Result<Vec<(Register, u32)>, err::Error> {
+        REGISTERS
+            .iter()
+            .map(|&register| Ok((register, self.read_core_register(register)?)))
+            .collect()
+    }
+
+    /// Read R0 to PC of `core`, switching to its access port if needed.
+    pub fn read_core_registers(&self, core: Core) -> Result<Vec<(Register, u32)>, err::Error> {
+        self.select_core(core)?;
+        self.read_registers()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_core_lines_have_no_core_map() {
+        assert_eq!(core_access_ports(0x450, "Cortex-M7", "STM32H743/753"), None);
+        assert_eq!(core_access_ports(0x497, "Cortex-M4", "STM32WLE5"), None);
+        assert_eq!(
+            core_access_ports(0x469, "Cortex-M4", "STM32G47x/G48x"),
+            None
+        );
+    }
+
+    #[test]
+    fn dual_core_lines_map_both_cores() {
+        let cores = core_access_ports(0x450, "Cortex-M7", "STM32H745/755").unwrap();
+        assert_eq!(cores[1].core, Core::CortexM4);
+        assert_eq!(cores[1].access_port, 3);
+
+        let cores = core_access_ports(0x497, "Cortex-M4/M0+", "STM32WLxx").unwrap();
+        assert_eq!(cores[1].core, Core::CortexM0Plus);
+
+        assert!(core_access_ports(0x500, "Cortex-A7", "STM32MP15xx").is_some());
+    }
+}
@@ -0,0 +1,162 @@
+use crate::err;
+use crate::{DebugResetMode, Register, STM32CubeProg};
+
+/// Operation that would have modified the target, recorded in dry-run mode.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PlannedOperation {
+    MassErase,
+    SectorErase {
+        sectors: Vec<u32>,
+    },
+    Download {
+        file: std::path::PathBuf,
+        size: Option<u64>,
+        address: Option<u32>,
+        skip_erase: bool,
+        verify: bool,
+    },
+    Write {
+        address: u32,
+        size: u32,
+    },
+    WriteRegister {
+        register: Register,
+        value: u32,
+    },
+    OptionBytes {
+        command: String,
+    },
+    /// Checksum computed by a routine loaded at `ram_address`.
+    OnTargetChecksum {
+        address: u32,
+        size: u32,
+        ram_address: u32,
+    },
+    Reset {
+        mode: DebugResetMode,
+    },
+}
+
+impl std::fmt::Display for PlannedOperation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PlannedOperation::MassErase => write!(f, "mass erase"),
+            PlannedOperation::SectorErase { sectors } => write!(f, "erase sectors {:?}", sectors),
+            PlannedOperation::Download {
+                file,
+                size,
+                address,
+                skip_erase,
+                verify,
+            } => {
+                write!(f, "download {}", file.display())?;
+                if let Some(size) = size {
+                    write!(f, " ({} bytes)", size)?;
+                }
+                if let Some(address) = address {
+                    write!(f, " at 0x{:08X}", address)?;
+                }
+                if !skip_erase {
+                    write!(f, ", erase")?;
+                }
+                if *verify {
+                    write!(f, ", verify")?;
+                }
+                Ok(())
+            }
+            PlannedOperation::Write { address, size } => {
+                write!(f, "write {} bytes at 0x{:08X}", size, address)
+            }
+            PlannedOperation::WriteRegister { register, value } => {
+                write!(f, "write {:?} = 0x{:08X}", register, value)
+            }
+            PlannedOperation::OptionBytes { command } => write!(f, "option bytes {}", command),
+            PlannedOperation::OnTargetChecksum {
+                address,
+                size,
+                ram_address,
+            } => write!(
+                f,
+                "checksum {} bytes at 0x{:08X} on target (routine at 0x{:08X})",
+                size, address, ram_address
+            ),
+            PlannedOperation::Reset { mode } => write!(f, "reset ({:?})", mode),
+        }
+    }
+}
+
+/// Operations recorded between `start_dry_run` and `finish_dry_run`, in order.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Plan {
+    pub operations: Vec<PlannedOperation>,
+}
+
+impl Plan {
+    pub fn is_empty(&self) -> bool {
+        self.operations.is_empty()
+    }
+
+    /// Whether the plan erases or writes anything.
+    pub fn is_destructive(&self) -> bool {
+        self.operations.iter().any(|operation| {
+            !matches!(
+                operation,
+                PlannedOperation::Reset { .. } | PlannedOperation::WriteRegister { .. }
+            )
+        })
+    }
+}
+
+impl std::fmt::Display for Plan {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for (index, operation) in self.operations.iter().enumerate() {
+            if index > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}. {}", index + 1, operation)?;
+        }
+        Ok(())
+    }
+}
+
+impl STM32CubeProg {
+    /// Record erase, download, write, option byte, on-target checksum and
+    /// reset operations instead of performing them, until `finish_dry_run`.
+    /// Reads still reach the target.
+    pub fn start_dry_run(&self) {
+        self.plan.replace(Some(Plan::default()));
+    }
+
+    /// Leave the dry-run mode and return the recorded operations.
+    pub fn finish_dry_run(&self) -> Option<Plan> {
+        self.plan.replace(None)
+    }
+
+    pub fn is_dry_run(&self) -> bool {
+        self.plan.borrow().is_some()
+    }
+
+    /// Run `f` in dry-run mode and return the operations it would perform.
+    pub fn dry_run<F>(&self, f: F) -> Result<Plan, err::Error>
+    where
+        F: FnOnce(&Self) -> Result<(), err::Error>,
+    {
+        self.start_dry_run();
+        let result = f(self);
+        let plan = self.finish_dry_run().unwrap_or_default();
+        result.map(|()| plan)
+    }
+
+    /// Record `operation` if in dry-run mode, returning whether it was.
+    pub(crate) fn planned(&self, operation: PlannedOperation) -> bool {
+        match self.plan.borrow_mut().as_mut() {
+            Some(plan) => {
+                plan.operations.push(operation);
+                true
+            }
+            None => false,
+        }
+    }
+}
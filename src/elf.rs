@@ -0,0 +1,233 @@
+//! Minimal reader for the little-endian ELF32 files produced for Cortex-M.
+
+use crate::err;
+
+const PT_LOAD: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const STT_OBJECT: u8 = 1;
+const STT_FUNC: u8 = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ElfSegment {
+    /// Load address, where the content is programmed.
+    pub address: u32,
+    /// Run-time address.
+    pub virtual_address: u32,
+    /// Size in memory, larger than `data` for zero-initialized parts.
+    pub memory_size: u32,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SymbolKind {
+    Object,
+    Function,
+    Other,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ElfSymbol {
+    pub name: String,
+    /// Run-time address, without the Thumb bit for functions.
+    pub address: u32,
+    pub size: u32,
+    pub kind: SymbolKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ElfSection {
+    pub name: String,
+    pub kind: u32,
+    pub address: u32,
+    pub offset: u32,
+    pub size: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Elf {
+    pub entry: u32,
+    pub segments: Vec<ElfSegment>,
+    pub sections: Vec<ElfSection>,
+    pub symbols: Vec<ElfSymbol>,
+    data: Vec<u8>,
+}
+
+fn invalid(message: &str) -> err::Error {
+    err::Error::InvalidImage(message.to_string())
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn bytes(&self, offset: u32, size: u32) -> Result<&'a [u8], err::Error> {
+        let start = offset as usize;
+        start
+            .checked_add(size as usize)
+            .and_then(|end| self.0.get(start..end))
+            .ok_or_else(|| invalid("truncated ELF file"))
+    }
+
+    /// Entry `index` of the table of `size` bytes entries at `offset`.
+    fn entry(&self, offset: u32, index: u32, size: u32) -> Result<Reader<'a>, err::Error> {
+        let start = index
+            .checked_mul(size)
+            .and_then(|entry| offset.checked_add(entry))
+            .ok_or_else(|| invalid("truncated ELF file"))?;
+        Ok(Reader(self.bytes(start, size)?))
+    }
+
+    fn u8(&self, offset: u32) -> Result<u8, err::Error> {
+        Ok(self.bytes(offset, 1)?[0])
+    }
+
+    fn u16(&self, offset: u32) -> Result<u16, err::Error> {
+        let bytes = self.bytes(offset, 2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&self, offset: u32) -> Result<u32, err::Error> {
+        let bytes = self.bytes(offset, 4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// Null-terminated string at `offset` of the string table at `table`.
+    fn string(&self, table: &ElfSection, offset: u32) -> Result<String, err::Error> {
+        let strings = self.bytes(table.offset, table.size)?;
+        let start = std::cmp::min(offset as usize, strings.len());
+        let end = strings[start..]
+            .iter()
+            .position(|&byte| byte == 0)
+            .map_or(strings.len(), |position| start + position);
+        Ok(String::from_utf8_lossy(&strings[start..end]).into_owned())
+    }
+}
+
+impl Elf {
+    pub fn load<P: AsRef<std::path::Path>>(path: P) -> Result<Self, err::Error> {
+        Self::parse(std::fs::read(path)?)
+    }
+
+    pub fn parse(data: Vec<u8>) -> Result<Self, err::Error> {
+        let reader = Reader(&data);
+        if reader.bytes(0, 4)? != b"\x7fELF" {
+            return Err(invalid("not an ELF file"));
+        }
+        if reader.u8(4)? != 1 || reader.u8(5)? != 1 {
+            return Err(invalid("only little-endian ELF32 files are supported"));
+        }
+
+        let entry = reader.u32(0x18)?;
+        let program_headers = reader.u32(0x1C)?;
+        let section_headers = reader.u32(0x20)?;
+        let program_header_size = u32::from(reader.u16(0x2A)?);
+        let program_header_count = u32::from(reader.u16(0x2C)?);
+        let section_header_size = u32::from(reader.u16(0x2E)?);
+        let section_header_count = u32::from(reader.u16(0x30)?);
+        let section_names = u32::from(reader.u16(0x32)?);
+
+        let mut segments = Vec::new();
+        for index in 0..program_header_count {
+            let header = reader.entry(program_headers, index, program_header_size)?;
+            let file_size = header.u32(16)?;
+            if header.u32(0)? != PT_LOAD || file_size == 0 {
+                continue;
+            }
+            segments.push(ElfSegment {
+                address: header.u32(12)?,
+                virtual_address: header.u32(8)?,
+                memory_size: header.u32(20)?,
+                data: reader.bytes(header.u32(4)?, file_size)?.to_vec(),
+            });
+        }
+
+        let mut sections = Vec::new();
+        for index in 0..section_header_count {
+            let header = reader.entry(section_headers, index, section_header_size)?;
+            sections.push(ElfSection {
+                name: String::new(),
+                kind: header.u32(4)?,
+                address: header.u32(12)?,
+                offset: header.u32(16)?,
+                size: header.u32(20)?,
+            });
+        }
+        if let Some(names) = sections.get(section_names as usize).cloned() {
+            for (index, section) in sections.iter_mut().enumerate() {
+                let header = reader.entry(section_headers, index as u32, section_header_size)?;
+                section.name = reader.string(&names, header.u32(0)?)?;
+            }
+        }
+
+        let mut symbols = Vec::new();
+        for (index, table) in sections.iter().enumerate() {
+            if table.kind != SHT_SYMTAB {
+                continue;
+            }
+            let header = reader.entry(section_headers, index as u32, section_header_size)?;
+            let strings = sections
+                .get(header.u32(24)? as usize)
+                .ok_or_else(|| invalid("missing symbol names"))?;
+
+            // The first entry is the null symbol
+            let entries = Reader(reader.bytes(table.offset, table.size)?);
+            for index in 1..table.size / 16 {
+                let entry = entries.entry(0, index, 16)?;
+                let info = entry.u8(12)?;
+                let kind = match info & 0xF {
+                    STT_OBJECT => SymbolKind::Object,
+                    STT_FUNC => SymbolKind::Function,
+                    _ => SymbolKind::Other,
+                };
+                let mut address = entry.u32(4)?;
+                if kind == SymbolKind::Function {
+                    address &= !1;
+                }
+                let name = reader.string(strings, entry.u32(0)?)?;
+                if name.is_empty() {
+                    continue;
+                }
+                symbols.push(ElfSymbol {
+                    name,
+                    address,
+                    size: entry.u32(8)?,
+                    kind,
+                });
+            }
+        }
+
+        Ok(Elf {
+            entry,
+            segments,
+            sections,
+            symbols,
+            data,
+        })
+    }
+
+    pub fn symbol(&self, name: &str) -> Option<&ElfSymbol> {
+        self.symbols.iter().find(|symbol| symbol.name == name)
+    }
+
+    pub fn section(&self, name: &str) -> Option<&ElfSection> {
+        self.sections.iter().find(|section| section.name == name)
+    }
+
+    /// Content of the section called `name`, as stored in the file.
+    pub fn section_data(&self, name: &str) -> Option<&[u8]> {
+        let section = self.section(name)?;
+        Reader(&self.data).bytes(section.offset, section.size).ok()
+    }
+
+    /// Load address of the byte found at `address` at run time, for
+    /// initialized data copied from flash to RAM at startup.
+    pub fn load_address(&self, address: u32) -> Option<u32> {
+        self.segments
+            .iter()
+            .find(|segment| {
+                address >= segment.virtual_address
+                    && u64::from(address)
+                        < u64::from(segment.virtual_address) + segment.data.len() as u64
+            })
+            .map(|segment| segment.address + (address - segment.virtual_address))
+    }
+}
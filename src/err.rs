@@ -13,6 +13,59 @@ pub enum Error {
     IntConversionError(std::num::TryFromIntError),
     FloatConversionError(std::num::ParseFloatError),
     UnsupportedPlatform,
+    UnalignedAccess {
+        address: u32,
+        size: u32,
+    },
+    ChecksumMismatch {
+        address: u32,
+        expected: u32,
+        actual: u32,
+    },
+    Timeout,
+    OutOfRange {
+        address: u32,
+        size: u32,
+    },
+    CoreUnavailable {
+        core: crate::cores::Core,
+        device_id: i32,
+    },
+    ProbeBusy(String),
+    ConnectionFailed(crate::connect::ConnectReport),
+    TargetUnpowered(f32),
+    TargetUndervoltage {
+        voltage: f32,
+        minimum: f32,
+    },
+    InvalidConfig(String),
+    ProbeNotFound {
+        selector: String,
+        candidates: Vec<String>,
+    },
+    AmbiguousProbe {
+        selector: String,
+        candidates: Vec<String>,
+    },
+    GangWorker(String),
+    UnknownOptionByte(String),
+    InvalidRecipe(Vec<String>),
+    SafetyViolation {
+        rule: crate::safety::SafetyRule,
+        operation: String,
+    },
+    InvalidImage(String),
+    UnlockFailed(String),
+    RegressionFailed {
+        regression: Box<Error>,
+        reconnection: Box<Error>,
+    },
+    OtpProgrammed(u32),
+    OtpLocked(u32),
+    UnknownSymbol(String),
+    UnknownRegister(String),
+    InvalidSvd(String),
+    PartialWord(usize),
 }
 
 impl Display for Error {
@@ -30,6 +83,96 @@ impl Display for Error {
             self::Error::SliceConversionError(e) => write!(f, "Slice conversion error: {}", e),
             self::Error::IntConversionError(e) => write!(f, "Int conversion error: {}", e),
             self::Error::FloatConversionError(e) => write!(f, "Float conversion error: {}", e),
+            self::Error::UnalignedAccess { address, size } => write!(
+                f,
+                "Unaligned access: 0x{:08X} ({} bytes) must be word aligned",
+                address, size
+            ),
+            self::Error::ChecksumMismatch {
+                address,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Checksum mismatch at 0x{:08X}: expected 0x{:08X}, got 0x{:08X}",
+                address, expected, actual
+            ),
+            self::Error::Timeout => write!(f, "Operation timed out"),
+            self::Error::OutOfRange { address, size } => write!(
+                f,
+                "Range 0x{:08X} ({} bytes) is outside of the memory layout",
+                address, size
+            ),
+            self::Error::CoreUnavailable { core, device_id } => write!(
+                f,
+                "Core {:?} is not available on device 0x{:03X}",
+                core, device_id
+            ),
+            self::Error::ProbeBusy(serial_number) => write!(
+                f,
+                "ST-Link {} is held exclusively by another process, enable the shared mode",
+                serial_number
+            ),
+            self::Error::ConnectionFailed(report) => {
+                write!(f, "Every connection attempt failed:\n{}", report)
+            }
+            self::Error::TargetUnpowered(voltage) => {
+                write!(f, "Target is not powered ({:.2} V)", voltage)
+            }
+            self::Error::TargetUndervoltage { voltage, minimum } => write!(
+                f,
+                "Target voltage {:.2} V is below the {:.2} V minimum",
+                voltage, minimum
+            ),
+            self::Error::InvalidConfig(message) => write!(f, "Invalid configuration: {}", message),
+            self::Error::ProbeNotFound {
+                selector,
+                candidates,
+            } => {
+                write!(f, "No ST-Link matches {}", selector)?;
+                if candidates.is_empty() {
+                    write!(f, ", none is plugged")
+                } else {
+                    write!(f, ", candidates:\n{}", candidates.join("\n"))
+                }
+            }
+            self::Error::AmbiguousProbe {
+                selector,
+                candidates,
+            } => write!(
+                f,
+                "Several ST-Links match {}:\n{}",
+                selector,
+                candidates.join("\n")
+            ),
+            self::Error::GangWorker(message) => write!(f, "Gang worker failed: {}", message),
+            self::Error::UnknownOptionByte(name) => write!(f, "Unknown option byte {}", name),
+            self::Error::InvalidRecipe(issues) => {
+                write!(f, "Invalid recipe:\n{}", issues.join("\n"))
+            }
+            self::Error::SafetyViolation { rule, operation } => {
+                write!(f, "Safety policy refused the {}: {}", operation, rule)
+            }
+            self::Error::InvalidImage(message) => write!(f, "Invalid image: {}", message),
+            self::Error::UnlockFailed(message) => write!(f, "Unlock failed: {}", message),
+            self::Error::RegressionFailed {
+                regression,
+                reconnection,
+            } => write!(
+                f,
+                "Readout protection regression failed: {}, then reconnecting failed: {}",
+                regression, reconnection
+            ),
+            self::Error::OtpProgrammed(address) => {
+                write!(f, "OTP word at 0x{:08X} is already programmed", address)
+            }
+            self::Error::OtpLocked(block) => write!(f, "OTP block {} is locked", block),
+            self::Error::UnknownSymbol(name) => write!(f, "Unknown symbol {}", name),
+            self::Error::UnknownRegister(path) => write!(f, "Unknown register {}", path),
+            self::Error::InvalidSvd(message) => write!(f, "Invalid SVD file: {}", message),
+            self::Error::PartialWord(pending) => {
+                write!(f, "{} trailing bytes do not fill a 32-bit word", pending)
+            }
         }
     }
 }
@@ -167,4 +310,16 @@ impl From<i32> for CubeProgrammerError {
     }
 }
 
+impl CubeProgrammerError {
+    /// Whether retrying the same operation may succeed.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            CubeProgrammerError::MemoryReadError
+                | CubeProgrammerError::MemoryWriteError
+                | CubeProgrammerError::UnknownError
+        )
+    }
+}
+
 impl std::error::Error for CubeProgrammerError {}
@@ -0,0 +1,211 @@
+use crate::checksum::{Checksum, ChecksumAlgorithm, ChecksumMethod};
+use crate::err;
+use crate::STM32CubeProg;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Sector {
+    pub index: u32,
+    pub address: u32,
+    pub size: u32,
+}
+
+impl Sector {
+    pub fn end(&self) -> u64 {
+        u64::from(self.address) + u64::from(self.size)
+    }
+}
+
+/// Sector organization of a flash memory, as expected by `sector_erase`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlashLayout {
+    sectors: Vec<Sector>,
+}
+
+impl FlashLayout {
+    pub fn new(mut sectors: Vec<Sector>) -> Self {
+        sectors.sort_by_key(|sector| sector.address);
+        FlashLayout { sectors }
+    }
+
+    /// Layout made of `count` sectors of `sector_size` bytes starting at
+    /// `address`, numbered from `first_index`, the number of the sector at
+    /// `address` in its bank.
+    pub fn uniform(
+        address: u32,
+        first_index: u32,
+        sector_size: u32,
+        count: u32,
+    ) -> Result<Self, err::Error> {
+        let sectors = (0..count)
+            .map(|offset| {
+                let index = first_index.checked_add(offset);
+                let start = offset
+                    .checked_mul(sector_size)
+                    .and_then(|offset| address.checked_add(offset))
+                    .filter(|&start| {
+                        sector_size > 0 && u64::from(start) + u64::from(sector_size) <= 1 << 32
+                    });
+                match (index, start) {
+                    (Some(index), Some(start)) => Ok(Sector {
+                        index,
+                        address: start,
+                        size: sector_size,
+                    }),
+                    _ => Err(err::Error::InvalidConfig(format!(
+                        "{} sectors of {} bytes do not fit at 0x{:08X}",
+                        count, sector_size, address
+                    ))),
+                }
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(FlashLayout { sectors })
+    }
+
+    pub fn sectors(&self) -> &[Sector] {
+        &self.sectors
+    }
+
+    /// Sectors overlapping the `size` bytes starting at `address`.
+    pub fn overlapping(&self, address: u32, size: u32) -> Vec<Sector> {
+        let end = u64::from(address) + u64::from(size);
+        self.sectors
+            .iter()
+            .filter(|sector| u64::from(sector.address) < end && sector.end() > u64::from(address))
+            .cloned()
+            .collect()
+    }
+
+    /// Whether every byte of the range belongs to a sector of the layout.
+    pub fn covers(&self, address: u32, size: u32) -> bool {
+        let end = u64::from(address) + u64::from(size);
+        let mut cursor = u64::from(address);
+        for sector in self.overlapping(address, size) {
+            if u64::from(sector.address) > cursor {
+                return false;
+            }
+            cursor = std::cmp::max(cursor, sector.end());
+        }
+        cursor >= end
+    }
+}
+
+/// How `smart_download` decides whether a sector already holds the new content.
+#[derive(Debug, Copy, Clone)]
+pub enum SectorComparison {
+    /// Read the sector back and compare it byte by byte.
+    ReadBack,
+    /// Compare the STM32 CRC of the sector with the one of the image.
+    Checksum(ChecksumMethod),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SmartDownloadReport {
+    pub programmed: Vec<u32>,
+    pub skipped: Vec<u32>,
+}
+
+impl SmartDownloadReport {
+    pub fn programmed_count(&self) -> usize {
+        self.programmed.len()
+    }
+
+    pub fn skipped_count(&self) -> usize {
+        self.skipped.len()
+    }
+}
+
+impl std::fmt::Display for SmartDownloadReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "\
+Programmed Sectors: {:?},
+Skipped Sectors: {:?}",
+            self.programmed, self.skipped,
+        )
+    }
+}
+
+impl STM32CubeProg {
+    /// Program `image` at `address`, only erasing and rewriting the sectors
+    /// of `layout` whose content differs from the image.
+    ///
+    /// Bytes of partially covered sectors that are outside of the image are
+    /// preserved.
+    pub fn smart_download(
+        &self,
+        image: &[u8],
+        address: u32,
+        layout: &FlashLayout,
+        comparison: SectorComparison,
+    ) -> Result<SmartDownloadReport, err::Error> {
+        let size: u32 = std::convert::TryInto::try_into(image.len())?;
+        if !layout.covers(address, size) {
+            return Err(err::Error::OutOfRange { address, size });
+        }
+
+        let mut report = SmartDownloadReport::default();
+        let mut pending = Vec::new();
+
+        for sector in layout.overlapping(address, size) {
+            let start = std::cmp::max(sector.address, address);
+            let end = std::cmp::min(sector.end(), u64::from(address) + u64::from(size)) as u32;
+            let slice = &image[(start - address) as usize..(end - address) as usize];
+
+            let content = if start == sector.address && end - start == sector.size {
+                if self.sector_matches(&sector, slice, comparison)? {
+                    None
+                } else {
+                    Some(slice.to_vec())
+                }
+            } else {
+                let mut current = self.read_memory8(sector.address, sector.size)?;
+                let offset = (start - sector.address) as usize;
+                if current[offset..offset + slice.len()] == *slice {
+                    None
+                } else {
+                    current[offset..offset + slice.len()].copy_from_slice(slice);
+                    Some(current)
+                }
+            };
+
+            match content {
+                Some(content) => pending.push((sector, content)),
+                None => report.skipped.push(sector.index),
+            }
+        }
+
+        if pending.is_empty() {
+            return Ok(report);
+        }
+
+        let indexes: Vec<u32> = pending.iter().map(|(sector, _)| sector.index).collect();
+        self.sector_erase(&indexes)?;
+
+        for (sector, content) in pending {
+            self.write_memory8(sector.address, content)?;
+            report.programmed.push(sector.index);
+        }
+
+        Ok(report)
+    }
+
+    fn sector_matches(
+        &self,
+        sector: &Sector,
+        expected: &[u8],
+        comparison: SectorComparison,
+    ) -> Result<bool, err::Error> {
+        match comparison {
+            SectorComparison::ReadBack => {
+                Ok(self.read_memory8(sector.address, sector.size)? == expected)
+            }
+            SectorComparison::Checksum(method) => {
+                let algorithm = ChecksumAlgorithm::Stm32;
+                let expected = Checksum::compute(algorithm, expected)?;
+                Ok(self.checksum(sector.address, sector.size, algorithm, method)? == expected)
+            }
+        }
+    }
+}
@@ -0,0 +1,490 @@
+//! Programming several boards at once.
+//!
+//! The C library keeps a single global connection, so every probe is driven
+//! by its own worker process. Workers are started from the current executable
+//! with `STM32CUBEPROG_GANG_WORKER` set, which `run_worker_if_requested` must
+//! catch at the beginning of `main`.
+//!
+//! The parent writes the job to the worker stdin as `key = value` lines and
+//! the worker answers on stdout with lines prefixed by `@gang`. Any other
+//! output of the worker is kept as log.
+
+use crate::config::ConnectionConfig;
+use crate::err;
+use crate::probe::ProbeSelector;
+use crate::STM32CubeProg;
+
+use std::io::{BufRead, Write};
+
+const WORKER_VARIABLE: &str = "STM32CUBEPROG_GANG_WORKER";
+const PREFIX: &str = "@gang ";
+
+/// Image and options programmed on every board.
+#[derive(Debug, Clone)]
+pub struct GangJob {
+    pub image: std::path::PathBuf,
+    /// Load address of binary images.
+    pub address: Option<u32>,
+    pub erase: bool,
+    pub verify: bool,
+    /// Reset the target once programmed.
+    pub reset: bool,
+    pub config: ConnectionConfig,
+}
+
+impl GangJob {
+    pub fn new<P: AsRef<std::path::Path>>(image: P) -> Self {
+        GangJob {
+            image: image.as_ref().to_path_buf(),
+            address: None,
+            erase: true,
+            verify: true,
+            reset: true,
+            config: ConnectionConfig::default(),
+        }
+    }
+}
+
+/// Outcome of the job on one board.
+#[derive(Debug)]
+pub struct BoardResult {
+    pub serial_number: String,
+    pub result: Result<(), err::Error>,
+    pub logs: Vec<String>,
+    /// Duration of each step, in order.
+    pub timings: Vec<(String, std::time::Duration)>,
+    pub duration: std::time::Duration,
+}
+
+#[derive(Debug, Default)]
+pub struct GangReport {
+    pub boards: Vec<BoardResult>,
+}
+
+impl GangReport {
+    pub fn succeeded(&self) -> bool {
+        self.boards.iter().all(|board| board.result.is_ok())
+    }
+
+    pub fn failed(&self) -> Vec<&BoardResult> {
+        self.boards
+            .iter()
+            .filter(|board| board.result.is_err())
+            .collect()
+    }
+}
+
+impl std::fmt::Display for GangReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for (index, board) in self.boards.iter().enumerate() {
+            if index > 0 {
+                writeln!(f)?;
+            }
+            write!(
+                f,
+                "{}: {} ({:.1} s)",
+                board.serial_number,
+                match &board.result {
+                    Ok(()) => "ok".to_string(),
+                    Err(error) => error.to_string(),
+                },
+                board.duration.as_secs_f32()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs a `GangJob` on several probes in parallel.
+#[derive(Debug, Clone)]
+pub struct Gang {
+    path: std::path::PathBuf,
+    worker: std::path::PathBuf,
+}
+
+impl Gang {
+    /// `path` is the STM32CubeProgrammer installation loaded by each worker.
+    pub fn new<P: AsRef<std::path::Path>>(path: P) -> Result<Self, err::Error> {
+        Ok(Gang {
+            path: path.as_ref().to_path_buf(),
+            worker: std::env::current_exe()?,
+        })
+    }
+
+    /// Executable started for each probe, the current one by default. It must
+    /// call `run_worker_if_requested`.
+    pub fn worker<P: AsRef<std::path::Path>>(mut self, worker: P) -> Self {
+        self.worker = worker.as_ref().to_path_buf();
+        self
+    }
+
+    /// Program the probes of `serial_numbers` and wait for every worker.
+    ///
+    /// A worker that cannot be started is reported as a failed board, the
+    /// other ones still run.
+    pub fn program(
+        &self,
+        serial_numbers: &[String],
+        job: &GangJob,
+    ) -> Result<GangReport, err::Error> {
+        let request = self.request(job)?;
+
+        let mut workers = Vec::new();
+        for serial_number in serial_numbers {
+            let start = std::time::Instant::now();
+            let spawned = std::process::Command::new(&self.worker)
+                .env(WORKER_VARIABLE, "1")
+                .stdin(std::process::Stdio::piped())
+                .stdout(std::process::Stdio::piped())
+                .stderr(std::process::Stdio::piped())
+                .spawn();
+            let mut child = match spawned {
+                Ok(child) => child,
+                Err(error) => {
+                    workers.push(Err(BoardResult {
+                        serial_number: serial_number.clone(),
+                        result: Err(error.into()),
+                        logs: Vec::new(),
+                        timings: Vec::new(),
+                        duration: start.elapsed(),
+                    }));
+                    continue;
+                }
+            };
+
+            // A worker failing to read its job is reported by its collector.
+            if let Some(mut stdin) = child.stdin.take() {
+                let _ = write!(stdin, "serial = {}\n{}", serial_number, request);
+            }
+
+            let serial_number = serial_number.clone();
+            workers.push(Ok(std::thread::spawn(move || {
+                collect(serial_number, child, start)
+            })));
+        }
+
+        let mut report = GangReport::default();
+        for worker in workers {
+            report.boards.push(match worker {
+                Ok(collector) => collector
+                    .join()
+                    .map_err(|_| err::Error::GangWorker("collector panicked".to_string()))?,
+                Err(board) => board,
+            });
+        }
+        Ok(report)
+    }
+
+    fn request(&self, job: &GangJob) -> Result<String, err::Error> {
+        let path = std::fs::canonicalize(&self.path)?;
+        let image = std::fs::canonicalize(&job.image)?;
+
+        let mut request = format!(
+            "path = {}\nimage = {}\nerase = {}\nverify = {}\nreset = {}\n",
+            path.display(),
+            image.display(),
+            job.erase,
+            job.verify,
+            job.reset
+        );
+        if let Some(address) = job.address {
+            request.push_str(&format!("address = {:#010x}\n", address));
+        }
+        for line in job.config.to_string().lines() {
+            request.push_str(&format!("config.{}\n", line));
+        }
+        Ok(request)
+    }
+}
+
+fn collect(
+    serial_number: String,
+    mut child: std::process::Child,
+    start: std::time::Instant,
+) -> BoardResult {
+    let mut board = BoardResult {
+        serial_number,
+        result: Err(err::Error::GangWorker(
+            "worker exited without a result".to_string(),
+        )),
+        logs: Vec::new(),
+        timings: Vec::new(),
+        duration: std::time::Duration::default(),
+    };
+
+    let stderr = child.stderr.take().map(|stderr| {
+        std::thread::spawn(move || {
+            std::io::BufReader::new(stderr)
+                .lines()
+                .map_while(Result::ok)
+                .collect::<Vec<String>>()
+        })
+    });
+
+    if let Some(stdout) = child.stdout.take() {
+        for line in std::io::BufReader::new(stdout)
+            .lines()
+            .map_while(Result::ok)
+        {
+            match line.strip_prefix(PREFIX) {
+                Some(message) => parse_message(&mut board, message),
+                None => board.logs.push(line),
+            }
+        }
+    }
+
+    if let Some(Ok(lines)) = stderr.map(std::thread::JoinHandle::join) {
+        board.logs.extend(lines);
+    }
+
+    if let Err(error) = child.wait() {
+        board.result = Err(error.into());
+    }
+    board.duration = start.elapsed();
+    board
+}
+
+fn parse_message(board: &mut BoardResult, message: &str) {
+    let (kind, rest) = message.split_once(' ').unwrap_or((message, ""));
+    match kind {
+        "ok" => board.result = Ok(()),
+        "log" => board.logs.push(rest.to_string()),
+        "step" => {
+            if let Some((name, millis)) = rest.split_once(' ') {
+                if let Ok(millis) = millis.parse() {
+                    board
+                        .timings
+                        .push((name.to_string(), std::time::Duration::from_millis(millis)));
+                }
+            }
+        }
+        "error" => {
+            let (code, message) = rest.split_once(' ').unwrap_or((rest, ""));
+            board.result = Err(match code.parse::<i32>() {
+                Ok(code) if code < 0 => err::CubeProgrammerError::from(code).into(),
+                _ => err::Error::GangWorker(message.to_string()),
+            });
+        }
+        _ => board.logs.push(message.to_string()),
+    }
+}
+
+/// Run the gang worker and exit if this process was started by `Gang`,
+/// return otherwise.
+pub fn run_worker_if_requested() {
+    if std::env::var_os(WORKER_VARIABLE).is_none() {
+        return;
+    }
+
+    let code = match run_worker() {
+        Ok(()) => {
+            println!("{}ok", PREFIX);
+            0
+        }
+        Err(error) => {
+            let code = match &error {
+                err::Error::CubeProgrammerError(error) => *error as i32,
+                _ => 1,
+            };
+            println!(
+                "{}error {} {}",
+                PREFIX,
+                code,
+                error.to_string().replace('\n', " ")
+            );
+            1
+        }
+    };
+
+    let _ = std::io::stdout().flush();
+    std::process::exit(code);
+}
+
+fn step<T, F>(name: &str, f: F) -> Result<T, err::Error>
+where
+    F: FnOnce() -> Result<T, err::Error>,
+{
+    println!("{}log {}", PREFIX, name);
+    let start = std::time::Instant::now();
+    let result = f();
+    println!("{}step {} {}", PREFIX, name, start.elapsed().as_millis());
+    result
+}
+
+fn run_worker() -> Result<(), err::Error> {
+    let mut fields = std::collections::BTreeMap::new();
+    let mut config = ConnectionConfig::default();
+    for line in std::io::stdin().lock().lines() {
+        let line = line?;
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) => (key.trim().to_string(), value.trim().to_string()),
+            None => continue,
+        };
+        match key.strip_prefix("config.") {
+            Some(key) => config.set(key, &value)?,
+            None => {
+                fields.insert(key, value);
+            }
+        }
+    }
+
+    let field = |key: &str| {
+        fields
+            .get(key)
+            .cloned()
+            .ok_or_else(|| err::Error::GangWorker(format!("missing '{}' in job", key)))
+    };
+    let flag = |key: &str| Ok::<bool, err::Error>(field(key)? == "true");
+    let address = match fields.get("address") {
+        Some(address) => Some(
+            u32::from_str_radix(address.trim_start_matches("0x"), 16)
+                .map_err(|_| err::Error::GangWorker(format!("invalid address '{}'", address)))?,
+        ),
+        None => None,
+    };
+
+    let programmer = step("load", || STM32CubeProg::new(field("path")?))?;
+    let mut stlink = programmer.select_probe(&ProbeSelector::by_serial(&field("serial")?))?;
+    config.apply(&mut stlink)?;
+
+    step("connect", || programmer.connect(&stlink))?;
+    let result = step("download", || {
+        programmer.download(
+            field("image")?,
+            address,
+            Some(!flag("erase")?),
+            Some(flag("verify")?),
+        )
+    })
+    .and_then(|()| {
+        if flag("reset")? {
+            step("reset", || programmer.reset(&stlink))
+        } else {
+            Ok(())
+        }
+    });
+    programmer.disconnect();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn board() -> BoardResult {
+        BoardResult {
+            serial_number: "066DFF".to_string(),
+            result: Err(err::Error::GangWorker("none".to_string())),
+            logs: Vec::new(),
+            timings: Vec::new(),
+            duration: std::time::Duration::default(),
+        }
+    }
+
+    #[test]
+    fn ok_and_logs_are_parsed() {
+        let mut board = board();
+        parse_message(&mut board, "log connect");
+        parse_message(&mut board, "something else");
+        parse_message(&mut board, "ok");
+
+        assert!(board.result.is_ok());
+        assert_eq!(board.logs, vec!["connect", "something else"]);
+    }
+
+    #[test]
+    fn step_timings_are_parsed() {
+        let mut board = board();
+        parse_message(&mut board, "step connect 12");
+        parse_message(&mut board, "step download 3400");
+        parse_message(&mut board, "step reset");
+        parse_message(&mut board, "step reset soon");
+
+        assert_eq!(
+            board.timings,
+            vec![
+                ("connect".to_string(), std::time::Duration::from_millis(12)),
+                (
+                    "download".to_string(),
+                    std::time::Duration::from_millis(3400)
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn library_errors_keep_their_code() {
+        let mut board = board();
+        parse_message(&mut board, "error -3 Device connection error");
+
+        match board.result {
+            Err(err::Error::CubeProgrammerError(err::CubeProgrammerError::ConnectionError)) => {}
+            result => panic!("unexpected {:?}", result),
+        }
+    }
+
+    #[test]
+    fn other_errors_keep_their_message() {
+        let mut board = board();
+        parse_message(&mut board, "error 1 missing 'image' in job");
+
+        match board.result {
+            Err(err::Error::GangWorker(message)) => assert_eq!(message, "missing 'image' in job"),
+            result => panic!("unexpected {:?}", result),
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn worker_output_is_collected() {
+        let child = std::process::Command::new("sh")
+            .arg("-c")
+            .arg("echo '@gang step download 5'; echo progress; echo warning >&2; echo '@gang ok'")
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .spawn()
+            .unwrap();
+        let board = collect("066DFF".to_string(), child, std::time::Instant::now());
+
+        assert!(board.result.is_ok());
+        assert_eq!(board.logs, vec!["progress", "warning"]);
+        assert_eq!(board.timings.len(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn worker_without_result_fails() {
+        let child = std::process::Command::new("sh")
+            .arg("-c")
+            .arg("echo '@gang log connect'")
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .spawn()
+            .unwrap();
+        let board = collect("066DFF".to_string(), child, std::time::Instant::now());
+
+        assert!(board.result.is_err());
+        assert_eq!(board.logs, vec!["connect"]);
+    }
+
+    #[test]
+    fn workers_that_cannot_start_are_reported() {
+        let manifest = std::path::Path::new(env!("CARGO_MANIFEST_DIR"));
+        let gang = Gang::new(manifest)
+            .unwrap()
+            .worker(manifest.join("missing-worker"));
+        let serial_numbers = vec!["066DFF".to_string(), "0670FF".to_string()];
+
+        let report = gang
+            .program(&serial_numbers, &GangJob::new(manifest.join("Cargo.toml")))
+            .unwrap();
+
+        assert!(!report.succeeded());
+        assert_eq!(report.failed().len(), 2);
+        assert_eq!(report.boards[1].serial_number, "0670FF");
+        match &report.boards[0].result {
+            Err(err::Error::IoError(_)) => {}
+            result => panic!("unexpected {:?}", result),
+        }
+    }
+}
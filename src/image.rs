@@ -0,0 +1,314 @@
+use crate::elf::{Elf, ElfSymbol};
+use crate::err;
+use crate::STM32CubeProg;
+
+/// Default address of binary images, the start of the main flash.
+const FLASH_ADDRESS: u32 = 0x0800_0000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    pub address: u32,
+    pub data: Vec<u8>,
+}
+
+impl Segment {
+    pub fn end(&self) -> u64 {
+        u64::from(self.address) + self.data.len() as u64
+    }
+}
+
+/// Firmware image held in memory, loaded from a binary, Intel HEX, SREC or
+/// ELF file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Image {
+    segments: Vec<Segment>,
+    /// ELF symbols, at their load address.
+    symbols: Vec<ElfSymbol>,
+}
+
+fn invalid(message: String) -> err::Error {
+    err::Error::InvalidImage(message)
+}
+
+impl Image {
+    pub fn from_bin(address: u32, data: Vec<u8>) -> Self {
+        Image {
+            segments: vec![Segment { address, data }],
+            symbols: Vec::new(),
+        }
+    }
+
+    pub fn from_hex(text: &str) -> Result<Self, err::Error> {
+        let mut image = Image::default();
+        let mut base = 0u32;
+
+        for (number, line) in text.lines().map(str::trim).enumerate() {
+            if line.is_empty() {
+                continue;
+            }
+            let bytes = line
+                .strip_prefix(':')
+                .filter(|record| record.len() >= 10 && record.len() % 2 == 0)
+                .and_then(|record| {
+                    record
+                        .as_bytes()
+                        .chunks(2)
+                        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+                        .collect::<Option<Vec<u8>>>()
+                })
+                .filter(|bytes| bytes.len() == usize::from(bytes[0]) + 5)
+                .ok_or_else(|| invalid(format!("malformed record on line {}", number + 1)))?;
+            if bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) != 0 {
+                return Err(invalid(format!("bad checksum on line {}", number + 1)));
+            }
+
+            let data = &bytes[4..bytes.len() - 1];
+            let offset = u32::from(bytes[1]) << 8 | u32::from(bytes[2]);
+            match bytes[3] {
+                0x00 => image.insert(base.wrapping_add(offset), data),
+                0x01 => break,
+                0x02 if data.len() == 2 => {
+                    base = (u32::from(data[0]) << 8 | u32::from(data[1])) << 4
+                }
+                0x04 if data.len() == 2 => {
+                    base = (u32::from(data[0]) << 8 | u32::from(data[1])) << 16
+                }
+                0x03 | 0x05 => {}
+                kind => {
+                    return Err(invalid(format!(
+                        "unsupported record type {:02X} on line {}",
+                        kind,
+                        number + 1
+                    )))
+                }
+            }
+        }
+
+        Ok(image)
+    }
+
+    pub fn from_srec(text: &str) -> Result<Self, err::Error> {
+        let mut image = Image::default();
+
+        for (number, line) in text.lines().map(str::trim).enumerate() {
+            if line.is_empty() {
+                continue;
+            }
+            let malformed = || invalid(format!("malformed record on line {}", number + 1));
+            let (kind, record) = line
+                .strip_prefix('S')
+                .filter(|record| record.len() >= 7 && record.len() % 2 == 1)
+                .map(|record| record.split_at(1))
+                .ok_or_else(malformed)?;
+            let bytes = record
+                .as_bytes()
+                .chunks(2)
+                .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+                .collect::<Option<Vec<u8>>>()
+                .filter(|bytes| bytes.len() == usize::from(bytes[0]) + 1)
+                .ok_or_else(malformed)?;
+            if bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) != 0xFF {
+                return Err(invalid(format!("bad checksum on line {}", number + 1)));
+            }
+
+            let address_size = match kind {
+                "0" | "1" | "5" | "9" => 2,
+                "2" | "6" | "8" => 3,
+                "3" | "7" => 4,
+                kind => {
+                    return Err(invalid(format!(
+                        "unsupported record type S{} on line {}",
+                        kind,
+                        number + 1
+                    )))
+                }
+            };
+            if bytes.len() < address_size + 2 {
+                return Err(malformed());
+            }
+            let address = bytes[1..=address_size]
+                .iter()
+                .fold(0u32, |address, &byte| address << 8 | u32::from(byte));
+            let data = &bytes[address_size + 1..bytes.len() - 1];
+            match kind {
+                "1" | "2" | "3" => image.insert(address, data),
+                "7" | "8" | "9" => break,
+                _ => {}
+            }
+        }
+
+        Ok(image)
+    }
+
+    pub fn from_elf(elf: &Elf) -> Self {
+        let mut image = Image::default();
+        for segment in elf.segments.iter() {
+            image.insert(segment.address, &segment.data);
+        }
+        image.symbols = elf
+            .symbols
+            .iter()
+            .filter_map(|symbol| {
+                Some(ElfSymbol {
+                    address: elf.load_address(symbol.address)?,
+                    ..symbol.clone()
+                })
+            })
+            .collect();
+        image
+    }
+
+    /// Load `path` according to its content and extension. Binary files are
+    /// placed at `address`, the start of the main flash by default.
+    pub fn load<P: AsRef<std::path::Path>>(
+        path: P,
+        address: Option<u32>,
+    ) -> Result<Self, err::Error> {
+        let path = path.as_ref();
+        let data = std::fs::read(path)?;
+        if data.starts_with(b"\x7fELF") {
+            return Ok(Self::from_elf(&Elf::parse(data)?));
+        }
+
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("bin") => Ok(Self::from_bin(address.unwrap_or(FLASH_ADDRESS), data)),
+            Some("hex") => Self::from_hex(&String::from_utf8(data)?),
+            Some("srec" | "s19" | "s28" | "s37" | "mot") => {
+                Self::from_srec(&String::from_utf8(data)?)
+            }
+            _ => Err(invalid(format!("unsupported format of {}", path.display()))),
+        }
+    }
+
+    /// Add `data` at `address`, after the last segment or in a new one.
+    fn insert(&mut self, address: u32, data: &[u8]) {
+        match self.segments.last_mut() {
+            Some(last) if last.end() == u64::from(address) => last.data.extend_from_slice(data),
+            _ => self.segments.push(Segment {
+                address,
+                data: data.to_vec(),
+            }),
+        }
+    }
+
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    pub fn symbol(&self, name: &str) -> Option<&ElfSymbol> {
+        self.symbols.iter().find(|symbol| symbol.name == name)
+    }
+
+    /// Address and size of each segment.
+    pub fn ranges(&self) -> Vec<(u32, u32)> {
+        self.segments
+            .iter()
+            .map(|segment| (segment.address, segment.data.len() as u32))
+            .collect()
+    }
+
+    fn segment_mut(&mut self, address: u32, size: u32) -> Result<&mut Segment, err::Error> {
+        self.segments
+            .iter_mut()
+            .find(|segment| {
+                address >= segment.address && u64::from(address) + u64::from(size) <= segment.end()
+            })
+            .ok_or(err::Error::OutOfRange { address, size })
+    }
+
+    pub fn read(&self, address: u32, size: u32) -> Option<&[u8]> {
+        self.segments
+            .iter()
+            .find(|segment| {
+                address >= segment.address && u64::from(address) + u64::from(size) <= segment.end()
+            })
+            .map(|segment| {
+                let offset = (address - segment.address) as usize;
+                &segment.data[offset..offset + size as usize]
+            })
+    }
+
+    /// Overwrite the bytes at `address`, which must belong to a segment.
+    pub fn patch(&mut self, address: u32, data: &[u8]) -> Result<(), err::Error> {
+        let size = std::convert::TryInto::try_into(data.len())?;
+        let segment = self.segment_mut(address, size)?;
+        let offset = (address - segment.address) as usize;
+        segment.data[offset..offset + data.len()].copy_from_slice(data);
+        Ok(())
+    }
+
+    pub fn to_hex(&self) -> String {
+        let mut records = Vec::new();
+        let mut upper = None;
+
+        for segment in self.segments.iter() {
+            for (index, chunk) in segment.data.chunks(16).enumerate() {
+                let address = segment.address + index as u32 * 16;
+                // Records do not cross 64 KB boundaries
+                let split = std::cmp::min(chunk.len(), 0x10000 - (address & 0xFFFF) as usize);
+                for (address, chunk) in [
+                    (address, &chunk[..split]),
+                    (address + split as u32, &chunk[split..]),
+                ] {
+                    if chunk.is_empty() {
+                        continue;
+                    }
+                    if upper != Some(address >> 16) {
+                        upper = Some(address >> 16);
+                        records.push(hex_record(0, 0x04, &((address >> 16) as u16).to_be_bytes()));
+                    }
+                    records.push(hex_record(address as u16, 0x00, chunk));
+                }
+            }
+        }
+        records.push(hex_record(0, 0x01, &[]));
+
+        records.join("\n") + "\n"
+    }
+
+    pub fn save_hex<P: AsRef<std::path::Path>>(&self, path: P) -> Result<(), err::Error> {
+        std::fs::write(path, self.to_hex())?;
+        Ok(())
+    }
+}
+
+fn hex_record(address: u16, kind: u8, data: &[u8]) -> String {
+    let mut bytes = vec![data.len() as u8];
+    bytes.extend_from_slice(&address.to_be_bytes());
+    bytes.push(kind);
+    bytes.extend_from_slice(data);
+    let checksum = bytes
+        .iter()
+        .fold(0u8, |sum, &byte| sum.wrapping_add(byte))
+        .wrapping_neg();
+    bytes.push(checksum);
+
+    let digits: Vec<String> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+    format!(":{}", digits.concat())
+}
+
+impl STM32CubeProg {
+    /// Program `image` through `download`, using a temporary Intel HEX file.
+    pub fn download_image(
+        &self,
+        image: &Image,
+        skip_erase: Option<bool>,
+        verify: Option<bool>,
+    ) -> Result<(), err::Error> {
+        static COUNTER: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "stm32cubeprog-rs-{}-{}.hex",
+            std::process::id(),
+            COUNTER.fetch_add(1, std::sync::atomic::Ordering::SeqCst)
+        ));
+
+        image.save_hex(&path)?;
+        let result = self.download(&path, None, skip_erase, verify);
+        let _ = std::fs::remove_file(&path);
+        result
+    }
+}
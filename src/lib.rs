@@ -12,7 +12,7 @@
 //!
 //! The code below will discover connected STLinks on Linux, retrieve information, read and write memory, and finally program the attached device.
 //!
-//! ```
+//! ```no_run
 //! fn main() -> Result<(), Box<dyn std::error::Error>> {
 //!     // Generate path to STM32CubeProgrammer folder
 //!     let home_dir = std::env::var_os("HOME")
@@ -32,8 +32,8 @@
 //!         println!("{stlink}");
 //!         
 //!         // Configure the reset mode and the connection mode
-//!         stlink.reset_mode(stm32cubeprog_rs::DebugResetMode::HardwareReset);
-//!         stlink.connection_mode(stm32cubeprog_rs::DebugConnectMode::UnderReset);
+//!         stlink.set_reset_mode(stm32cubeprog_rs::DebugResetMode::HardwareReset);
+//!         stlink.set_connection_mode(stm32cubeprog_rs::DebugConnectMode::UnderReset);
 //!
 //!         // Connect the STlink
 //!         stm32prog.connect(stlink)?;
@@ -73,7 +73,32 @@
 //! }
 //! ```
 
+pub mod bootloader;
+pub mod checksum;
+pub mod connect;
+pub mod config;
+pub mod cores;
+pub mod dry_run;
+pub mod elf;
 pub mod err;
+pub mod flash;
+pub mod gang;
+pub mod image;
+pub mod memory;
+pub mod option_bytes;
+pub mod otp;
+pub mod probe;
+pub mod rdp;
+#[cfg(feature = "recipe")]
+pub mod recipe;
+pub mod safety;
+pub mod snapshot;
+pub mod stamp;
+#[cfg(feature = "svd")]
+pub mod svd;
+pub mod symbols;
+pub mod voltage;
+pub mod watch;
 
 #[cfg(unix)]
 #[allow(non_camel_case_types)]
@@ -105,14 +130,16 @@ pub enum Verbosity {
 }
 
 #[repr(C)]
-#[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum DebugPort {
     Jtag = 0,
     Swd = 1,
 }
 
 #[repr(C)]
-#[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum DebugConnectMode {
     Normal = 0,
     HotPlug = 1,
@@ -122,13 +149,61 @@ pub enum DebugConnectMode {
 }
 
 #[repr(C)]
-#[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum DebugResetMode {
     SoftwareReset = 0,
     HardwareReset = 1,
     CoreReset = 2,
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DebugSpeed {
+    Reliable = 0,
+    Fast = 1,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TargetInterface {
+    StLink = 0,
+    Usart = 1,
+    Usb = 2,
+    Spi = 3,
+    I2c = 4,
+    Can = 5,
+}
+
+impl TargetInterface {
+    /// Largest transfer the interface reliably handles in a single call.
+    pub fn max_transfer_size(&self) -> u32 {
+        match self {
+            TargetInterface::StLink => 0x1000,
+            TargetInterface::Usb => 0x400,
+            TargetInterface::Usart
+            | TargetInterface::Spi
+            | TargetInterface::I2c
+            | TargetInterface::Can => 0x100,
+        }
+    }
+}
+
+impl std::convert::TryFrom<i32> for TargetInterface {
+    type Error = err::Error;
+
+    fn try_from(value: i32) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(TargetInterface::StLink),
+            1 => Ok(TargetInterface::Usart),
+            2 => Ok(TargetInterface::Usb),
+            3 => Ok(TargetInterface::Spi),
+            4 => Ok(TargetInterface::I2c),
+            5 => Ok(TargetInterface::Can),
+            _ => Err(err::CubeProgrammerError::DeviceNotConnected.into()),
+        }
+    }
+}
+
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
 pub struct Frequencies {
@@ -148,6 +223,23 @@ impl Frequencies {
         let size = std::convert::TryInto::try_into(self.swd_freq_count).unwrap_or_default();
         self.swd_freq.to_vec()[0..size].to_vec()
     }
+
+    /// Supported frequencies in kHz for `debug_port`, fastest first.
+    pub fn frequencies(&self, debug_port: DebugPort) -> Vec<u32> {
+        let mut frequencies = match debug_port {
+            DebugPort::Jtag => self.jtag_frequencies(),
+            DebugPort::Swd => self.swd_frequencies(),
+        };
+        frequencies.sort_unstable_by(|a, b| b.cmp(a));
+        frequencies
+    }
+
+    /// Supported frequency in kHz for `debug_port` closest to `frequency` kHz.
+    pub fn nearest(&self, debug_port: DebugPort, frequency: u32) -> Option<u32> {
+        self.frequencies(debug_port)
+            .into_iter()
+            .min_by_key(|&supported| (supported.abs_diff(frequency), supported))
+    }
 }
 
 impl std::fmt::Display for Frequencies {
@@ -207,12 +299,30 @@ type GetStLinkList = unsafe extern "C" fn(
     debug_connect_parameters: *mut *mut DebugConnectParameters,
     shared: std::os::raw::c_int,
 ) -> std::os::raw::c_int;
+type GetStLinkEnumerationList = unsafe extern "C" fn(
+    debug_connect_parameters: *mut *mut DebugConnectParameters,
+    shared: std::os::raw::c_int,
+) -> std::os::raw::c_int;
 type ConnectStLink =
     unsafe extern "C" fn(debug_connect_parameters: DebugConnectParameters) -> std::os::raw::c_int;
+type ConnectSpiBootloader = unsafe extern "C" fn(
+    spi_connect_parameters: bootloader::SpiConnectParameters,
+) -> std::os::raw::c_int;
+type ConnectI2cBootloader = unsafe extern "C" fn(
+    i2c_connect_parameters: bootloader::I2cConnectParameters,
+) -> std::os::raw::c_int;
+type ConnectCanBootloader = unsafe extern "C" fn(
+    can_connect_parameters: bootloader::CanConnectParameters,
+) -> std::os::raw::c_int;
 type DeleteInterfaceList = unsafe extern "C" fn();
 type Disconnect = unsafe extern "C" fn();
 type Reset = unsafe extern "C" fn(reset_mode: DebugResetMode) -> std::os::raw::c_int;
 type MassErase = unsafe extern "C" fn() -> std::os::raw::c_int;
+type SectorErase = unsafe extern "C" fn(
+    sectors: *mut std::os::raw::c_uint,
+    sector_count: std::os::raw::c_uint,
+    flash_memory_name: *mut std::os::raw::c_char,
+) -> std::os::raw::c_int;
 type DownloadFile = unsafe extern "C" fn(
     file_path: *const wchar,
     address: std::os::raw::c_uint,
@@ -220,12 +330,14 @@ type DownloadFile = unsafe extern "C" fn(
     verify: std::os::raw::c_uint,
     path: *const wchar,
 ) -> std::os::raw::c_int;
+type GetTargetInterfaceType = unsafe extern "C" fn() -> std::os::raw::c_int;
 type GetDeviceGeneralInfo = unsafe extern "C" fn() -> *mut DeviceGeneralInfo;
 type ReadMemory = unsafe extern "C" fn(
     address: ::std::os::raw::c_uint,
     data: *mut *mut ::std::os::raw::c_uchar,
     size: ::std::os::raw::c_uint,
 ) -> ::std::os::raw::c_int;
+type FreeLibraryMemory = unsafe extern "C" fn(data: *mut std::os::raw::c_void);
 type WriteMemory = unsafe extern "C" fn(
     address: ::std::os::raw::c_uint,
     data: *mut ::std::os::raw::c_uchar,
@@ -239,6 +351,9 @@ type WriteCoreRegister = unsafe extern "C" fn(
     register: std::os::raw::c_uint,
     data: std::os::raw::c_uint,
 ) -> ::std::os::raw::c_int;
+type InitOptionBytesInterface = unsafe extern "C" fn() -> *mut option_bytes::Peripheral;
+type SendOptionBytesCmd =
+    unsafe extern "C" fn(command: *mut std::os::raw::c_char) -> std::os::raw::c_int;
 
 #[cfg(unix)]
 pub struct VTable {
@@ -246,17 +361,26 @@ pub struct VTable {
     set_display_callbacks: libloading::os::unix::Symbol<SetDisplayCallbacks>,
     set_verbosity_level: libloading::os::unix::Symbol<SetVerbosityLevel>,
     get_stlink_list: libloading::os::unix::Symbol<GetStLinkList>,
+    get_stlink_enumeration_list: libloading::os::unix::Symbol<GetStLinkEnumerationList>,
     connect_stlink: libloading::os::unix::Symbol<ConnectStLink>,
+    connect_spi_bootloader: libloading::os::unix::Symbol<ConnectSpiBootloader>,
+    connect_i2c_bootloader: libloading::os::unix::Symbol<ConnectI2cBootloader>,
+    connect_can_bootloader: libloading::os::unix::Symbol<ConnectCanBootloader>,
     delete_interface_list: libloading::os::unix::Symbol<DeleteInterfaceList>,
     disconnect: libloading::os::unix::Symbol<Disconnect>,
     reset: libloading::os::unix::Symbol<Reset>,
     mass_erase: libloading::os::unix::Symbol<MassErase>,
+    sector_erase: libloading::os::unix::Symbol<SectorErase>,
     download_file: libloading::os::unix::Symbol<DownloadFile>,
+    get_target_interface_type: libloading::os::unix::Symbol<GetTargetInterfaceType>,
     get_device_general_info: libloading::os::unix::Symbol<GetDeviceGeneralInfo>,
     read_memory: libloading::os::unix::Symbol<ReadMemory>,
+    free_library_memory: libloading::os::unix::Symbol<FreeLibraryMemory>,
     write_memory: libloading::os::unix::Symbol<WriteMemory>,
     read_core_register: libloading::os::unix::Symbol<ReadCoreRegister>,
     write_core_register: libloading::os::unix::Symbol<WriteCoreRegister>,
+    init_option_bytes_interface: libloading::os::unix::Symbol<InitOptionBytesInterface>,
+    send_option_bytes_cmd: libloading::os::unix::Symbol<SendOptionBytesCmd>,
 }
 
 #[cfg(windows)]
@@ -265,17 +389,26 @@ pub struct VTable {
     set_display_callbacks: libloading::os::windows::Symbol<SetDisplayCallbacks>,
     set_verbosity_level: libloading::os::windows::Symbol<SetVerbosityLevel>,
     get_stlink_list: libloading::os::windows::Symbol<GetStLinkList>,
+    get_stlink_enumeration_list: libloading::os::windows::Symbol<GetStLinkEnumerationList>,
     connect_stlink: libloading::os::windows::Symbol<ConnectStLink>,
+    connect_spi_bootloader: libloading::os::windows::Symbol<ConnectSpiBootloader>,
+    connect_i2c_bootloader: libloading::os::windows::Symbol<ConnectI2cBootloader>,
+    connect_can_bootloader: libloading::os::windows::Symbol<ConnectCanBootloader>,
     delete_interface_list: libloading::os::windows::Symbol<DeleteInterfaceList>,
     disconnect: libloading::os::windows::Symbol<Disconnect>,
     reset: libloading::os::windows::Symbol<Reset>,
     mass_erase: libloading::os::windows::Symbol<MassErase>,
+    sector_erase: libloading::os::windows::Symbol<SectorErase>,
     download_file: libloading::os::windows::Symbol<DownloadFile>,
+    get_target_interface_type: libloading::os::windows::Symbol<GetTargetInterfaceType>,
     get_device_general_info: libloading::os::windows::Symbol<GetDeviceGeneralInfo>,
     read_memory: libloading::os::windows::Symbol<ReadMemory>,
+    free_library_memory: libloading::os::windows::Symbol<FreeLibraryMemory>,
     write_memory: libloading::os::windows::Symbol<WriteMemory>,
     read_core_register: libloading::os::windows::Symbol<ReadCoreRegister>,
     write_core_register: libloading::os::windows::Symbol<WriteCoreRegister>,
+    init_option_bytes_interface: libloading::os::windows::Symbol<InitOptionBytesInterface>,
+    send_option_bytes_cmd: libloading::os::windows::Symbol<SendOptionBytesCmd>,
 }
 
 impl VTable {
@@ -296,10 +429,26 @@ impl VTable {
             unsafe { library.get(b"getStLinkList\0")? };
         let get_stlink_list = unsafe { get_stlink_list.into_raw() };
 
+        let get_stlink_enumeration_list: libloading::Symbol<GetStLinkEnumerationList> =
+            unsafe { library.get(b"getStLinkEnumerationList\0")? };
+        let get_stlink_enumeration_list = unsafe { get_stlink_enumeration_list.into_raw() };
+
         let connect_stlink: libloading::Symbol<ConnectStLink> =
             unsafe { library.get(b"connectStLink\0")? };
         let connect_stlink = unsafe { connect_stlink.into_raw() };
 
+        let connect_spi_bootloader: libloading::Symbol<ConnectSpiBootloader> =
+            unsafe { library.get(b"connectSpiBootloader\0")? };
+        let connect_spi_bootloader = unsafe { connect_spi_bootloader.into_raw() };
+
+        let connect_i2c_bootloader: libloading::Symbol<ConnectI2cBootloader> =
+            unsafe { library.get(b"connectI2cBootloader\0")? };
+        let connect_i2c_bootloader = unsafe { connect_i2c_bootloader.into_raw() };
+
+        let connect_can_bootloader: libloading::Symbol<ConnectCanBootloader> =
+            unsafe { library.get(b"connectCanBootloader\0")? };
+        let connect_can_bootloader = unsafe { connect_can_bootloader.into_raw() };
+
         let delete_interface_list: libloading::Symbol<DeleteInterfaceList> =
             unsafe { library.get(b"deleteInterfaceList\0")? };
         let delete_interface_list = unsafe { delete_interface_list.into_raw() };
@@ -313,10 +462,18 @@ impl VTable {
         let mass_erase: libloading::Symbol<MassErase> = unsafe { library.get(b"massErase\0")? };
         let mass_erase = unsafe { mass_erase.into_raw() };
 
+        let sector_erase: libloading::Symbol<SectorErase> =
+            unsafe { library.get(b"sectorErase\0")? };
+        let sector_erase = unsafe { sector_erase.into_raw() };
+
         let download_file: libloading::Symbol<DownloadFile> =
             unsafe { library.get(b"downloadFile\0")? };
         let download_file = unsafe { download_file.into_raw() };
 
+        let get_target_interface_type: libloading::Symbol<GetTargetInterfaceType> =
+            unsafe { library.get(b"getTargetInterfaceType\0")? };
+        let get_target_interface_type = unsafe { get_target_interface_type.into_raw() };
+
         let get_device_general_info: libloading::Symbol<GetDeviceGeneralInfo> =
             unsafe { library.get(b"getDeviceGeneralInf\0")? };
         let get_device_general_info = unsafe { get_device_general_info.into_raw() };
@@ -324,6 +481,10 @@ impl VTable {
         let read_memory: libloading::Symbol<ReadMemory> = unsafe { library.get(b"readMemory\0")? };
         let read_memory = unsafe { read_memory.into_raw() };
 
+        let free_library_memory: libloading::Symbol<FreeLibraryMemory> =
+            unsafe { library.get(b"freeLibraryMemory\0")? };
+        let free_library_memory = unsafe { free_library_memory.into_raw() };
+
         let write_memory: libloading::Symbol<WriteMemory> =
             unsafe { library.get(b"writeMemory\0")? };
         let write_memory = unsafe { write_memory.into_raw() };
@@ -334,26 +495,66 @@ impl VTable {
             unsafe { library.get(b"writeCortexRegistres\0")? };
         let write_core_register = unsafe { write_core_register.into_raw() };
 
+        let init_option_bytes_interface: libloading::Symbol<InitOptionBytesInterface> =
+            unsafe { library.get(b"initOptionBytesInterface\0")? };
+        let init_option_bytes_interface = unsafe { init_option_bytes_interface.into_raw() };
+
+        let send_option_bytes_cmd: libloading::Symbol<SendOptionBytesCmd> =
+            unsafe { library.get(b"sendOptionBytesCmd\0")? };
+        let send_option_bytes_cmd = unsafe { send_option_bytes_cmd.into_raw() };
+
         Ok(VTable {
             set_loaders_path,
             set_display_callbacks,
             set_verbosity_level,
             get_stlink_list,
+            get_stlink_enumeration_list,
             connect_stlink,
+            connect_spi_bootloader,
+            connect_i2c_bootloader,
+            connect_can_bootloader,
             delete_interface_list,
             disconnect,
             reset,
             mass_erase,
+            sector_erase,
             download_file,
+            get_target_interface_type,
             get_device_general_info,
             read_memory,
+            free_library_memory,
             write_memory,
             read_core_register,
-            write_core_register
+            write_core_register,
+            init_option_bytes_interface,
+            send_option_bytes_cmd,
         })
     }
 }
 
+/// Buffer allocated by the library, released with `freeLibraryMemory` on drop.
+struct LibraryBuffer {
+    data: *mut std::os::raw::c_uchar,
+    free: FreeLibraryMemory,
+}
+
+impl LibraryBuffer {
+    fn new(free: FreeLibraryMemory) -> Self {
+        LibraryBuffer {
+            data: std::ptr::null_mut(),
+            free,
+        }
+    }
+}
+
+impl Drop for LibraryBuffer {
+    fn drop(&mut self) {
+        if !self.data.is_null() {
+            unsafe { (self.free)(self.data as *mut std::os::raw::c_void) };
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct STLink {
     debug_connect_parameters: DebugConnectParameters,
@@ -460,6 +661,22 @@ impl STLink {
         .to_owned())
     }
 
+    pub fn set_debug_port(&mut self, debug_port: DebugPort) {
+        self.debug_connect_parameters.debug_port = debug_port;
+    }
+
+    pub fn set_debug_sleep(&mut self, debug_sleep: bool) {
+        self.debug_connect_parameters.debug_sleep = debug_sleep.into();
+    }
+
+    pub fn set_speed(&mut self, speed: DebugSpeed) {
+        self.debug_connect_parameters.speed = speed as i32;
+    }
+
+    pub fn set_shared(&mut self, shared: bool) {
+        self.debug_connect_parameters.shared = shared.into();
+    }
+
     pub fn set_access_port(&mut self, access_port: i32) {
         self.debug_connect_parameters.access_port = access_port;
     }
@@ -468,6 +685,17 @@ impl STLink {
         self.debug_connect_parameters.frequency = frequency;
     }
 
+    /// Select the supported frequency of the current debug port closest to
+    /// `frequency` Hz and return it in Hz.
+    pub fn set_frequency_hz(&mut self, frequency: u32) -> Result<u32, err::Error> {
+        let khz = self
+            .frequencies()
+            .nearest(self.debug_port(), frequency / 1000)
+            .unwrap_or(frequency / 1000);
+        self.set_frequency(std::convert::TryInto::try_into(khz)?);
+        Ok(khz * 1000)
+    }
+
     pub fn set_reset_mode(&mut self, reset_mode: DebugResetMode) {
         self.debug_connect_parameters.reset_mode = reset_mode;
     }
@@ -652,6 +880,8 @@ Bootloader Version: 0x{:X}",
     }
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Register {
     R0,
     R1,
@@ -694,10 +924,96 @@ impl From<Register> for u32 {
     }
 }
 
+pub struct STM32CubeProgBuilder {
+    path: std::path::PathBuf,
+    shared: bool,
+    verbosity: Verbosity,
+    voltage_thresholds: Option<voltage::VoltageThresholds>,
+    safety_policy: safety::SafetyPolicy,
+}
+
+impl STM32CubeProgBuilder {
+    /// Discover and connect the ST-Links in shared mode, letting other tools
+    /// such as STM32CubeIDE or the ST-Link server use them at the same time.
+    pub fn shared(mut self, shared: bool) -> Self {
+        self.shared = shared;
+        self
+    }
+
+    pub fn verbosity(mut self, verbosity: Verbosity) -> Self {
+        self.verbosity = verbosity;
+        self
+    }
+
+    /// Check the target voltage before each connection.
+    pub fn voltage_thresholds(mut self, voltage_thresholds: voltage::VoltageThresholds) -> Self {
+        self.voltage_thresholds = Some(voltage_thresholds);
+        self
+    }
+
+    pub fn safety_policy(mut self, safety_policy: safety::SafetyPolicy) -> Self {
+        self.safety_policy = safety_policy;
+        self
+    }
+
+    pub fn build(self) -> Result<STM32CubeProg, err::Error> {
+        let library_path = STM32CubeProg::library_path(&self.path);
+        let library = STM32CubeProg::load_library(library_path.as_ref())?;
+        let vtable = VTable::new(&library)?;
+
+        unsafe {
+            (vtable.set_loaders_path)(
+                STM32CubeProg::flashloader_path(&self.path)
+                    .as_os_str()
+                    .as_encoded_bytes()
+                    .as_ptr() as *const i8,
+            )
+        };
+
+        let cb: DisplayCallbacks = DisplayCallbacks {
+            init_progress_bar,
+            log_message,
+            load_bar,
+        };
+
+        unsafe { (vtable.set_display_callbacks)(cb) };
+
+        unsafe { (vtable.set_verbosity_level)(self.verbosity) };
+
+        Ok(STM32CubeProg {
+            library,
+            vtable,
+            shared: self.shared,
+            voltage_thresholds: self.voltage_thresholds,
+            stlink: std::cell::RefCell::new(None),
+            interface_list: std::cell::Cell::new(false),
+            plan: std::cell::RefCell::new(None),
+            safety: std::cell::RefCell::new(self.safety_policy),
+            symbols: std::cell::RefCell::new(None),
+            #[cfg(feature = "svd")]
+            svd: std::cell::RefCell::new(None),
+        })
+    }
+}
+
+/// Session with the STM32CubeProgrammer API library.
+///
+/// The session keeps the state of the current connection in `RefCell`s, so it
+/// is `Send` but not `Sync`. Share it between threads behind a `Mutex`.
 pub struct STM32CubeProg {
     #[allow(dead_code)]
     library: libloading::Library,
     vtable: VTable,
+    shared: bool,
+    voltage_thresholds: Option<voltage::VoltageThresholds>,
+    stlink: std::cell::RefCell<Option<STLink>>,
+    /// Interface list enumerated during a connection, released at disconnect.
+    interface_list: std::cell::Cell<bool>,
+    plan: std::cell::RefCell<Option<dry_run::Plan>>,
+    safety: std::cell::RefCell<safety::SafetyPolicy>,
+    symbols: std::cell::RefCell<Option<symbols::Symbols>>,
+    #[cfg(feature = "svd")]
+    svd: std::cell::RefCell<Option<svd::Device>>,
 }
 
 impl STM32CubeProg {
@@ -743,39 +1059,41 @@ impl STM32CubeProg {
     }
 
     pub fn new<P: AsRef<std::path::Path>>(path: P) -> Result<Self, err::Error> {
-        let library_path = Self::library_path(path.as_ref());
-        let library = Self::load_library(library_path.as_ref())?;
-        let vtable = VTable::new(&library)?;
-
-        unsafe {
-            (vtable.set_loaders_path)(
-                Self::flashloader_path(path.as_ref())
-                    .as_os_str()
-                    .as_encoded_bytes()
-                    .as_ptr() as *const i8,
-            )
-        };
-
-        let cb: DisplayCallbacks = DisplayCallbacks {
-            init_progress_bar: init_progress_bar,
-            log_message: log_message,
-            load_bar: load_bar,
-        };
+        Self::builder(path).build()
+    }
 
-        unsafe { (vtable.set_display_callbacks)(cb) };
+    pub fn builder<P: AsRef<std::path::Path>>(path: P) -> STM32CubeProgBuilder {
+        STM32CubeProgBuilder {
+            path: path.as_ref().to_path_buf(),
+            shared: false,
+            verbosity: Verbosity::Level0,
+            voltage_thresholds: None,
+            safety_policy: safety::SafetyPolicy::default(),
+        }
+    }
 
-        unsafe { (vtable.set_verbosity_level)(Verbosity::Level0) };
+    pub fn shared(&self) -> bool {
+        self.shared
+    }
 
-        Ok(STM32CubeProg { library, vtable })
+    /// `deleteInterfaceList` also tears down the open connection, so the list
+    /// is only released once disconnected.
+    fn delete_interface_list(&self) {
+        if self.stlink.borrow().is_some() {
+            self.interface_list.set(true);
+        } else {
+            self.interface_list.set(false);
+            unsafe { (self.vtable.delete_interface_list)() };
+        }
     }
 
-    pub fn discover(&self) -> Result<Vec<STLink>, err::Error> {
+    fn stlink_list(&self, list: GetStLinkList, shared: bool) -> Result<Vec<STLink>, err::Error> {
         let mut debug_connect_parameters = std::ptr::null_mut();
-        let stlink_count =
-            unsafe { (self.vtable.get_stlink_list)(&mut debug_connect_parameters, 0) };
+        let stlink_count = unsafe { list(&mut debug_connect_parameters, shared.into()) };
 
-        if debug_connect_parameters.is_null() || stlink_count == 0 {
-            return Err(err::CubeProgrammerError::NoDeviceFound.into());
+        if debug_connect_parameters.is_null() || stlink_count <= 0 {
+            self.delete_interface_list();
+            return Ok(Vec::new());
         }
 
         let params_slice =
@@ -783,33 +1101,102 @@ impl STM32CubeProg {
 
         let slice = params_slice
             .iter()
-            .map(|param| -> Result<STLink, err::Error> {
-                let debug_connect_parameters = param.clone();
-                Ok(STLink {
-                    debug_connect_parameters,
-                })
+            .map(|param| {
+                let mut stlink = STLink {
+                    debug_connect_parameters: *param,
+                };
+                stlink.set_shared(self.shared);
+                stlink
             })
-            .collect::<Result<Vec<STLink>, err::Error>>();
+            .collect();
+
+        self.delete_interface_list();
+
+        Ok(slice)
+    }
+
+    pub fn discover(&self) -> Result<Vec<STLink>, err::Error> {
+        let stlinks = self.stlink_list(*self.vtable.get_stlink_list, self.shared)?;
+
+        if stlinks.is_empty() {
+            Err(err::CubeProgrammerError::NoDeviceFound.into())
+        } else {
+            Ok(stlinks)
+        }
+    }
 
-        unsafe { (self.vtable.delete_interface_list)() };
+    /// Serial numbers of the plugged ST-Links that cannot be opened, because
+    /// another process holds them exclusively.
+    pub fn busy_probes(&self) -> Result<Vec<String>, err::Error> {
+        let available = self
+            .stlink_list(*self.vtable.get_stlink_list, self.shared)?
+            .iter()
+            .map(|stlink| stlink.serial_number())
+            .collect::<Result<Vec<String>, err::Error>>()?;
 
-        slice
+        self.stlink_list(*self.vtable.get_stlink_enumeration_list, self.shared)?
+            .iter()
+            .map(|stlink| stlink.serial_number())
+            .filter(|serial_number| match serial_number {
+                Ok(serial_number) => !available.contains(serial_number),
+                Err(_) => true,
+            })
+            .collect()
     }
 
     pub fn connect(&self, stlink: &STLink) -> Result<(), err::Error> {
+        let mut stlink = stlink.clone();
+        if self.shared {
+            stlink.set_shared(true);
+        }
+
+        if let Some(voltage_thresholds) = self.voltage_thresholds {
+            voltage_thresholds.check(self.read_target_voltage(&stlink)?)?;
+        }
+
         let error = unsafe { (self.vtable.connect_stlink)(stlink.debug_connect_parameters) };
         if error == 0 {
-            Ok(())
-        } else {
-            Err(err::CubeProgrammerError::from(error).into())
+            self.stlink.replace(Some(stlink));
+            return Ok(());
         }
+
+        self.stlink.replace(None);
+        if !stlink.shared() {
+            // Failing to tell whether the probe is busy must not hide the
+            // connection error
+            if let Ok(serial_number) = stlink.serial_number() {
+                if self
+                    .busy_probes()
+                    .unwrap_or_default()
+                    .contains(&serial_number)
+                {
+                    return Err(err::Error::ProbeBusy(serial_number));
+                }
+            }
+        }
+        Err(err::CubeProgrammerError::from(error).into())
+    }
+
+    /// The ST-Link of the current debug connection, if any.
+    pub fn connected_stlink(&self) -> Option<STLink> {
+        self.stlink.borrow().clone()
     }
 
     pub fn disconnect(&self) {
+        self.stlink.replace(None);
         unsafe { (self.vtable.disconnect)() };
+        if self.interface_list.get() {
+            self.delete_interface_list();
+        }
     }
 
     pub fn reset(&self, stlink: &STLink) -> Result<(), err::Error> {
+        if self.planned(dry_run::PlannedOperation::Reset {
+            mode: stlink.reset_mode(),
+        }) {
+            return Ok(());
+        }
+
         let error = unsafe { (self.vtable.reset)(stlink.debug_connect_parameters.reset_mode) };
         if error == 0 {
             Ok(())
@@ -819,6 +1206,11 @@ impl STM32CubeProg {
     }
 
     pub fn mass_erase(&self) -> Result<(), err::Error> {
+        self.check_erase(None)?;
+        if self.planned(dry_run::PlannedOperation::MassErase) {
+            return Ok(());
+        }
+
         let error = unsafe { (self.vtable.mass_erase)() };
         if error == 0 {
             Ok(())
@@ -827,6 +1219,27 @@ impl STM32CubeProg {
         }
     }
 
+    pub fn sector_erase(&self, sectors: &[u32]) -> Result<(), err::Error> {
+        self.check_erase(Some(sectors))?;
+        if self.planned(dry_run::PlannedOperation::SectorErase {
+            sectors: sectors.to_vec(),
+        }) {
+            return Ok(());
+        }
+
+        let mut sectors = sectors.to_vec();
+        let count: u32 = std::convert::TryInto::try_into(sectors.len())?;
+
+        let error = unsafe {
+            (self.vtable.sector_erase)(sectors.as_mut_ptr(), count, std::ptr::null_mut())
+        };
+        if error == 0 {
+            Ok(())
+        } else {
+            Err(err::CubeProgrammerError::from(error).into())
+        }
+    }
+
     pub fn download<P: AsRef<std::path::Path>>(
         &self,
         path: P,
@@ -834,9 +1247,19 @@ impl STM32CubeProg {
         skip_erase: Option<bool>,
         verify: Option<bool>,
     ) -> Result<(), err::Error> {
-        let c_path = widestring::WideCString::from_os_str(
-            std::fs::canonicalize(path.as_ref())?.as_os_str(),
-        )?;
+        let path = std::fs::canonicalize(path.as_ref())?;
+        self.check_download(&path, address)?;
+        if self.planned(dry_run::PlannedOperation::Download {
+            size: std::fs::metadata(&path).ok().map(|metadata| metadata.len()),
+            file: path.clone(),
+            address,
+            skip_erase: skip_erase.unwrap_or(true),
+            verify: verify.unwrap_or(true),
+        }) {
+            return Ok(());
+        }
+
+        let c_path = widestring::WideCString::from_os_str(path.as_os_str())?;
 
         let error = unsafe {
             (self.vtable.download_file)(
@@ -854,12 +1277,17 @@ impl STM32CubeProg {
         }
     }
 
+    pub fn target_interface(&self) -> Result<TargetInterface, err::Error> {
+        let interface = unsafe { (self.vtable.get_target_interface_type)() };
+        std::convert::TryInto::try_into(interface)
+    }
+
     pub fn device_info(&self) -> Result<DeviceInfo, err::Error> {
         let device_general_info = unsafe { (self.vtable.get_device_general_info)().as_ref() };
 
         match device_general_info {
             Some(value) => Ok(DeviceInfo {
-                device_general_info: value.clone(),
+                device_general_info: *value,
             }),
             None => Err(err::CubeProgrammerError::NoDeviceFound.into()),
         }
@@ -876,6 +1304,13 @@ impl STM32CubeProg {
     }
 
     pub fn write_core_register(&self, register: Register, data: u32) -> Result<(), err::Error> {
+        if self.planned(dry_run::PlannedOperation::WriteRegister {
+            register,
+            value: data,
+        }) {
+            return Ok(());
+        }
+
         let error = unsafe { (self.vtable.write_core_register)(register.into(), data) };
         if error == 0 {
             Ok(())
@@ -884,48 +1319,30 @@ impl STM32CubeProg {
         }
     }
 
-    pub fn read_memory8(&self, address: u32, size: u32) -> Result<Vec<u8>, err::Error> {
-        let mut data = std::ptr::null_mut();
-        let error = unsafe { (self.vtable.read_memory)(address, &mut data, size) };
+    fn read_chunk(&self, address: u32, buffer: &mut [u8]) -> Result<(), err::Error> {
+        let size: u32 = std::convert::TryInto::try_into(buffer.len())?;
+        let mut data = LibraryBuffer::new(*self.vtable.free_library_memory);
+        let error = unsafe { (self.vtable.read_memory)(address, &mut data.data, size) };
 
-        if data.is_null() {
-            return Err(err::CubeProgrammerError::MemoryReadError.into());
+        if error != 0 {
+            return Err(err::CubeProgrammerError::from(error).into());
         }
 
-        if error == 0 {
-            let data: &mut [u8] = unsafe { core::slice::from_raw_parts_mut(data, size as usize) };
-            Ok(data.to_vec())
-        } else {
-            Err(err::CubeProgrammerError::from(error).into())
-        }
-    }
-
-    pub fn read_memory32(&self, address: u32, size: u32) -> Result<Vec<u32>, err::Error> {
-        let mut data = std::ptr::null_mut();
-        let error = unsafe { (self.vtable.read_memory)(address, &mut data, size * 4) };
-
-        if data.is_null() {
+        if data.data.is_null() {
             return Err(err::CubeProgrammerError::MemoryReadError.into());
         }
 
-        if error == 0 {
-            let data: &mut [u8] =
-                unsafe { core::slice::from_raw_parts_mut(data, (size * 4) as usize) };
-            data.chunks(4)
-                .map(|chunk| -> Result<u32, err::Error> {
-                    let chunk = std::convert::TryInto::try_into(chunk)?;
-                    Ok(u32::from_le_bytes(chunk))
-                })
-                .collect::<Result<Vec<u32>, err::Error>>()
-        } else {
-            Err(err::CubeProgrammerError::from(error).into())
-        }
+        let data: &[u8] = unsafe { core::slice::from_raw_parts(data.data, buffer.len()) };
+        buffer.copy_from_slice(data);
+        Ok(())
     }
 
-    pub fn write_memory8(&self, address: u32, data: Vec<u8>) -> Result<(), err::Error> {
+    fn write_chunk(&self, address: u32, data: &[u8]) -> Result<(), err::Error> {
         let size: u32 = std::convert::TryInto::try_into(data.len())?;
 
-        let error = unsafe { (self.vtable.write_memory)(address, data.clone().as_mut_ptr(), size) };
+        // writeMemory takes a mutable buffer, hand it a copy of the data
+        let mut buffer = data.to_vec();
+        let error = unsafe { (self.vtable.write_memory)(address, buffer.as_mut_ptr(), size) };
         if error == 0 {
             Ok(())
         } else {
@@ -933,18 +1350,23 @@ impl STM32CubeProg {
         }
     }
 
-    pub fn write_memory32(&self, address: u32, data_u32: Vec<u32>) -> Result<(), err::Error> {
-        let size: u32 = std::convert::TryInto::try_into(data_u32.len())?;
-        let mut data_u8: Vec<u8> = Vec::new();
-        for &num in &data_u32 {
-            data_u8.extend_from_slice(&num.to_le_bytes());
-        }
+    pub fn read_memory8(&self, address: u32, size: u32) -> Result<Vec<u8>, err::Error> {
+        let mut data = vec![0; size as usize];
+        self.read_raw(address, &mut data)?;
+        Ok(data)
+    }
 
-        let error = unsafe { (self.vtable.write_memory)(address, data_u8.as_mut_ptr(), size * 4) };
-        if error == 0 {
-            Ok(())
-        } else {
-            Err(err::CubeProgrammerError::from(error).into())
-        }
+    pub fn read_memory32(&self, address: u32, size: u32) -> Result<Vec<u32>, err::Error> {
+        let mut data = vec![0; size as usize];
+        self.read_slice(address, &mut data)?;
+        Ok(data)
+    }
+
+    pub fn write_memory8(&self, address: u32, data: Vec<u8>) -> Result<(), err::Error> {
+        self.write_raw(address, &data)
+    }
+
+    pub fn write_memory32(&self, address: u32, data_u32: Vec<u32>) -> Result<(), err::Error> {
+        self.write_slice(address, &data_u32)
     }
 }
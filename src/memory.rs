@@ -0,0 +1,424 @@
+use crate::err;
+use crate::STM32CubeProg;
+
+/// Plain old data that can be copied to and from the target memory.
+///
+/// Values are transferred in the target byte order (little-endian). Integer
+/// and float implementations of `target_order` swap between the host and the
+/// target byte order, which is the identity on little-endian hosts.
+///
+/// # Safety
+///
+/// Implementors must be `#[repr(C)]` (or primitive) types without padding,
+/// for which every bit pattern is a valid value.
+pub unsafe trait Pod: Copy + 'static {
+    fn target_order(self) -> Self {
+        self
+    }
+}
+
+macro_rules! impl_pod_integer {
+    ($($ty:ty),*) => {
+        $(
+            unsafe impl Pod for $ty {
+                fn target_order(self) -> Self {
+                    self.to_le()
+                }
+            }
+        )*
+    };
+}
+
+impl_pod_integer!(u8, i8, u16, i16, u32, i32, u64, i64);
+
+unsafe impl Pod for f32 {
+    fn target_order(self) -> Self {
+        f32::from_bits(self.to_bits().target_order())
+    }
+}
+
+unsafe impl Pod for f64 {
+    fn target_order(self) -> Self {
+        f64::from_bits(self.to_bits().target_order())
+    }
+}
+
+unsafe impl<T: Pod, const N: usize> Pod for [T; N] {
+    fn target_order(self) -> Self {
+        self.map(Pod::target_order)
+    }
+}
+
+fn as_bytes<T: Pod>(data: &[T]) -> &[u8] {
+    unsafe { std::slice::from_raw_parts(data.as_ptr() as *const u8, std::mem::size_of_val(data)) }
+}
+
+fn as_bytes_mut<T: Pod>(data: &mut [T]) -> &mut [u8] {
+    unsafe {
+        std::slice::from_raw_parts_mut(data.as_mut_ptr() as *mut u8, std::mem::size_of_val(data))
+    }
+}
+
+/// Splitting and retry policy of `read_chunked` and `write_chunked`.
+#[derive(Debug, Copy, Clone)]
+pub struct TransferOptions {
+    /// Largest single transfer, defaults to `TargetInterface::max_transfer_size`.
+    pub chunk_size: Option<u32>,
+    /// Address and size granularity of the transfers sent to the library.
+    /// Reads of unaligned heads and tails fetch the whole enclosing unit.
+    pub alignment: u32,
+    /// Merge unaligned heads and tails of writes with the current memory
+    /// content, rewriting the neighbouring bytes. Such writes fail with
+    /// `UnalignedAccess` otherwise.
+    pub read_modify_write: bool,
+    /// Number of retries of a chunk failing with a transient error.
+    pub retries: u32,
+    pub retry_delay: std::time::Duration,
+    /// Reconnect at the next lower frequency with `step_down_frequency` when a
+    /// chunk still fails after its retries, and try again.
+    pub step_down: bool,
+}
+
+impl Default for TransferOptions {
+    fn default() -> Self {
+        TransferOptions {
+            chunk_size: None,
+            alignment: 1,
+            read_modify_write: false,
+            retries: 2,
+            retry_delay: std::time::Duration::from_millis(10),
+            step_down: false,
+        }
+    }
+}
+
+struct Span {
+    address: u32,
+    size: u32,
+    partial: bool,
+}
+
+impl Span {
+    fn end(&self) -> u64 {
+        u64::from(self.address) + u64::from(self.size)
+    }
+}
+
+/// Split `size` bytes at `address` into aligned transfers of at most `chunk_size`
+/// bytes. Unaligned head and tail words are `partial` spans.
+fn spans(
+    address: u32,
+    size: usize,
+    alignment: u32,
+    chunk_size: u32,
+) -> Result<Vec<Span>, err::Error> {
+    let alignment = u64::from(std::cmp::max(alignment, 1));
+    let chunk_size = std::cmp::max(u64::from(chunk_size) / alignment, 1) * alignment;
+    let start = u64::from(address);
+    let end = start + size as u64;
+    let aligned_start = start.div_ceil(alignment) * alignment;
+    let aligned_end = end / alignment * alignment;
+
+    let mut spans = Vec::new();
+    if size == 0 {
+        return Ok(spans);
+    }
+    if end > 1 << 32 {
+        return Err(err::Error::OutOfRange {
+            address,
+            size: std::convert::TryInto::try_into(size).unwrap_or(u32::MAX),
+        });
+    }
+
+    if start != aligned_start || aligned_start > aligned_end {
+        spans.push(Span {
+            address: (start / alignment * alignment) as u32,
+            size: alignment as u32,
+            partial: true,
+        });
+    }
+
+    let mut cursor = aligned_start;
+    while cursor < aligned_end {
+        let chunk = std::cmp::min(chunk_size, aligned_end - cursor);
+        spans.push(Span {
+            address: cursor as u32,
+            size: chunk as u32,
+            partial: false,
+        });
+        cursor += chunk;
+    }
+
+    if end != aligned_end && aligned_end >= aligned_start {
+        spans.push(Span {
+            address: aligned_end as u32,
+            size: alignment as u32,
+            partial: true,
+        });
+    }
+
+    Ok(spans)
+}
+
+impl STM32CubeProg {
+    fn with_retries<F>(&self, options: &TransferOptions, mut transfer: F) -> Result<(), err::Error>
+    where
+        F: FnMut() -> Result<(), err::Error>,
+    {
+        let mut attempt = 0;
+        loop {
+            match transfer() {
+                Err(err::Error::CubeProgrammerError(error))
+                    if error.is_transient() && attempt < options.retries =>
+                {
+                    attempt += 1;
+                    std::thread::sleep(options.retry_delay);
+                }
+                Err(err::Error::CubeProgrammerError(error))
+                    if error.is_transient() && options.step_down =>
+                {
+                    match self.step_down_frequency()? {
+                        Some(_) => attempt = 0,
+                        None => return Err(error.into()),
+                    }
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn chunk_size(&self, options: &TransferOptions) -> u32 {
+        options.chunk_size.unwrap_or_else(|| {
+            self.target_interface()
+                .map(|interface| interface.max_transfer_size())
+                .unwrap_or(0x400)
+        })
+    }
+
+    /// Read `buffer.len()` bytes at `address`, calling `progress` with the number
+    /// of bytes done and the total after each chunk.
+    pub fn read_chunked<F>(
+        &self,
+        address: u32,
+        buffer: &mut [u8],
+        options: &TransferOptions,
+        mut progress: F,
+    ) -> Result<(), err::Error>
+    where
+        F: FnMut(usize, usize),
+    {
+        let start = u64::from(address);
+        let total = buffer.len();
+        let mut done = 0;
+
+        for span in spans(address, total, options.alignment, self.chunk_size(options))? {
+            let from = std::cmp::max(u64::from(span.address), start);
+            let to = std::cmp::min(span.end(), start + total as u64);
+            let offset = (from - start) as usize;
+            let length = (to - from) as usize;
+
+            if span.partial {
+                let mut word = vec![0; span.size as usize];
+                self.with_retries(options, || self.read_chunk(span.address, &mut word))?;
+                let skip = (from - u64::from(span.address)) as usize;
+                buffer[offset..offset + length].copy_from_slice(&word[skip..skip + length]);
+            } else {
+                let chunk = &mut buffer[offset..offset + length];
+                self.with_retries(options, || self.read_chunk(span.address, chunk))?;
+            }
+
+            done += length;
+            progress(done, total);
+        }
+
+        Ok(())
+    }
+
+    /// Write `data` at `address`, calling `progress` with the number of bytes
+    /// done and the total after each chunk.
+    ///
+    /// Unaligned heads and tails are only written with
+    /// `TransferOptions::read_modify_write`.
+    pub fn write_chunked<F>(
+        &self,
+        address: u32,
+        data: &[u8],
+        options: &TransferOptions,
+        progress: F,
+    ) -> Result<(), err::Error>
+    where
+        F: FnMut(usize, usize),
+    {
+        self.check_write(address, std::convert::TryInto::try_into(data.len())?)?;
+        self.write_unchecked(address, data, options, progress)
+    }
+
+    /// `write_chunked` without the safety policy checks.
+    pub(crate) fn write_unchecked<F>(
+        &self,
+        address: u32,
+        data: &[u8],
+        options: &TransferOptions,
+        mut progress: F,
+    ) -> Result<(), err::Error>
+    where
+        F: FnMut(usize, usize),
+    {
+        let start = u64::from(address);
+        let total = data.len();
+        let mut done = 0;
+
+        let size = std::convert::TryInto::try_into(total)?;
+        let spans = spans(address, total, options.alignment, self.chunk_size(options))?;
+        if !options.read_modify_write && spans.iter().any(|span| span.partial) {
+            return Err(err::Error::UnalignedAccess { address, size });
+        }
+        if self.planned(crate::dry_run::PlannedOperation::Write { address, size }) {
+            progress(total, total);
+            return Ok(());
+        }
+
+        for span in spans {
+            let from = std::cmp::max(u64::from(span.address), start);
+            let to = std::cmp::min(span.end(), start + total as u64);
+            let offset = (from - start) as usize;
+            let length = (to - from) as usize;
+
+            if span.partial {
+                let mut word = vec![0; span.size as usize];
+                self.with_retries(options, || self.read_chunk(span.address, &mut word))?;
+                let skip = (from - u64::from(span.address)) as usize;
+                word[skip..skip + length].copy_from_slice(&data[offset..offset + length]);
+                self.with_retries(options, || self.write_chunk(span.address, &word))?;
+            } else {
+                let chunk = &data[offset..offset + length];
+                self.with_retries(options, || self.write_chunk(span.address, chunk))?;
+            }
+
+            done += length;
+            progress(done, total);
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn read_raw(&self, address: u32, buffer: &mut [u8]) -> Result<(), err::Error> {
+        self.read_chunked(address, buffer, &TransferOptions::default(), |_, _| {})
+    }
+
+    pub(crate) fn write_raw(&self, address: u32, data: &[u8]) -> Result<(), err::Error> {
+        self.write_chunked(address, data, &TransferOptions::default(), |_, _| {})
+    }
+
+    pub fn read<T: Pod>(&self, address: u32) -> Result<T, err::Error> {
+        let mut value: T = unsafe { std::mem::zeroed() };
+        self.read_slice(address, std::slice::from_mut(&mut value))?;
+        Ok(value)
+    }
+
+    /// Fill `buffer` with consecutive values read from `address`.
+    pub fn read_slice<T: Pod>(&self, address: u32, buffer: &mut [T]) -> Result<(), err::Error> {
+        self.read_raw(address, as_bytes_mut(buffer))?;
+        for value in buffer.iter_mut() {
+            *value = value.target_order();
+        }
+        Ok(())
+    }
+
+    pub fn write<T: Pod>(&self, address: u32, value: &T) -> Result<(), err::Error> {
+        self.write_slice(address, std::slice::from_ref(value))
+    }
+
+    pub fn write_slice<T: Pod>(&self, address: u32, data: &[T]) -> Result<(), err::Error> {
+        if cfg!(target_endian = "little") {
+            self.write_raw(address, as_bytes(data))
+        } else {
+            let data: Vec<T> = data.iter().map(|value| value.target_order()).collect();
+            self.write_raw(address, as_bytes(&data))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layout(split: &[Span]) -> Vec<(u32, u32, bool)> {
+        split
+            .iter()
+            .map(|span| (span.address, span.size, span.partial))
+            .collect()
+    }
+
+    #[test]
+    fn byte_alignment_only_splits_into_chunks() {
+        let split = spans(0x20000001, 0x401, 1, 0x200).unwrap();
+        assert_eq!(
+            layout(&split),
+            vec![
+                (0x20000001, 0x200, false),
+                (0x20000201, 0x200, false),
+                (0x20000401, 1, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn unaligned_head_and_tail_are_partial() {
+        let split = spans(0x20000002, 12, 4, 0x400).unwrap();
+        assert_eq!(
+            layout(&split),
+            vec![
+                (0x20000000, 4, true),
+                (0x20000004, 8, false),
+                (0x2000000C, 4, true),
+            ]
+        );
+    }
+
+    #[test]
+    fn sub_word_transfers_use_one_partial_word() {
+        let split = spans(0x20000001, 2, 4, 0x400).unwrap();
+        assert_eq!(layout(&split), vec![(0x20000000, 4, true)]);
+
+        let split = spans(0x20000002, 4, 4, 0x400).unwrap();
+        assert_eq!(
+            layout(&split),
+            vec![(0x20000000, 4, true), (0x20000004, 4, true)]
+        );
+    }
+
+    #[test]
+    fn chunks_are_at_least_one_alignment_unit() {
+        let split = spans(0x20000000, 8, 4, 2).unwrap();
+        assert_eq!(
+            layout(&split),
+            vec![(0x20000000, 4, false), (0x20000004, 4, false)]
+        );
+    }
+
+    #[test]
+    fn transfers_end_at_the_top_of_the_address_space() {
+        let split = spans(0xFFFFFFF0, 16, 4, 8).unwrap();
+        assert_eq!(
+            layout(&split),
+            vec![(0xFFFFFFF0, 8, false), (0xFFFFFFF8, 8, false)]
+        );
+
+        let split = spans(0xFFFFFFFE, 2, 4, 8).unwrap();
+        assert_eq!(layout(&split), vec![(0xFFFFFFFC, 4, true)]);
+
+        match spans(0xFFFFFFFC, 8, 4, 8) {
+            Err(err::Error::OutOfRange {
+                address: 0xFFFFFFFC,
+                size: 8,
+            }) => {}
+            result => panic!("unexpected {:?}", result.map(|split| layout(&split))),
+        }
+    }
+
+    #[test]
+    fn empty_transfers_have_no_spans() {
+        assert!(spans(0x20000001, 0, 4, 8).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,214 @@
+use crate::err;
+use crate::STM32CubeProg;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct BitCoefficient {
+    pub multiplier: std::os::raw::c_uint,
+    pub offset: std::os::raw::c_uint,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct BitValue {
+    pub value: std::os::raw::c_uint,
+    pub description: [std::os::raw::c_char; 200usize],
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct Bit {
+    pub name: [std::os::raw::c_char; 32usize],
+    pub description: [std::os::raw::c_char; 300usize],
+    pub word_offset: std::os::raw::c_uint,
+    pub bit_offset: std::os::raw::c_uint,
+    pub bit_width: std::os::raw::c_uint,
+    pub access: std::os::raw::c_uchar,
+    pub values_count: std::os::raw::c_uint,
+    pub values: *mut *mut BitValue,
+    pub equation: BitCoefficient,
+    pub reference: *mut std::os::raw::c_uchar,
+    pub bit_value: std::os::raw::c_uint,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct Category {
+    pub name: [std::os::raw::c_char; 100usize],
+    pub bits_count: std::os::raw::c_uint,
+    pub bits: *mut *mut Bit,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct Bank {
+    pub size: std::os::raw::c_uint,
+    pub address: std::os::raw::c_uint,
+    pub access: std::os::raw::c_uchar,
+    pub categories_count: std::os::raw::c_uint,
+    pub categories: *mut *mut Category,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct Peripheral {
+    pub name: [std::os::raw::c_char; 32usize],
+    pub description: [std::os::raw::c_char; 200usize],
+    pub banks_count: std::os::raw::c_uint,
+    pub banks: *mut *mut Bank,
+}
+
+fn c_string(chars: &[std::os::raw::c_char]) -> String {
+    let bytes: Vec<u8> = chars
+        .iter()
+        .map(|&c| c as u8)
+        .take_while(|&c| c != 0)
+        .collect();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Entries of a null-safe C array of pointers.
+unsafe fn entries<'a, T>(array: *mut *mut T, count: std::os::raw::c_uint) -> Vec<&'a T> {
+    if array.is_null() {
+        return Vec::new();
+    }
+    std::slice::from_raw_parts(array, count as usize)
+        .iter()
+        .filter_map(|&entry| entry.as_ref())
+        .collect()
+}
+
+/// Option byte field as described by the device database of the library.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OptionByte {
+    pub name: String,
+    pub description: String,
+    pub category: String,
+    /// Address of the option byte bank.
+    pub bank_address: u32,
+    pub word_offset: u32,
+    pub bit_offset: u32,
+    pub bit_width: u32,
+    /// Access code reported by the library.
+    pub access: u8,
+    pub value: u32,
+    /// Documented values and their meaning.
+    pub values: Vec<(u32, String)>,
+}
+
+impl OptionByte {
+    /// Largest value the field can hold.
+    pub fn max_value(&self) -> u32 {
+        if self.bit_width >= 32 {
+            u32::MAX
+        } else {
+            (1 << self.bit_width) - 1
+        }
+    }
+
+    /// Meaning of the current value, when documented.
+    pub fn value_description(&self) -> Option<&str> {
+        self.values
+            .iter()
+            .find(|(value, _)| *value == self.value)
+            .map(|(_, description)| description.as_str())
+    }
+}
+
+impl std::fmt::Display for OptionByte {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}: {:#x}", self.name, self.value)?;
+        if let Some(description) = self.value_description() {
+            write!(f, " ({})", description)?;
+        }
+        Ok(())
+    }
+}
+
+impl STM32CubeProg {
+    /// Option bytes of the connected device, read from the target.
+    pub fn option_bytes(&self) -> Result<Vec<OptionByte>, err::Error> {
+        let peripheral = unsafe { (self.vtable.init_option_bytes_interface)().as_ref() }
+            .ok_or(err::CubeProgrammerError::UnsupportedOperation)?;
+
+        let mut option_bytes = Vec::new();
+        unsafe {
+            for bank in entries(peripheral.banks, peripheral.banks_count) {
+                for category in entries(bank.categories, bank.categories_count) {
+                    for bit in entries(category.bits, category.bits_count) {
+                        option_bytes.push(OptionByte {
+                            name: c_string(&bit.name),
+                            description: c_string(&bit.description),
+                            category: c_string(&category.name),
+                            bank_address: bank.address,
+                            word_offset: bit.word_offset,
+                            bit_offset: bit.bit_offset,
+                            bit_width: bit.bit_width,
+                            access: bit.access,
+                            value: bit.bit_value,
+                            values: entries(bit.values, bit.values_count)
+                                .iter()
+                                .map(|value| (value.value, c_string(&value.description)))
+                                .collect(),
+                        });
+                    }
+                }
+            }
+        }
+
+        Ok(option_bytes)
+    }
+
+    /// The option byte called `name`, ignoring case.
+    pub fn option_byte(&self, name: &str) -> Result<OptionByte, err::Error> {
+        self.option_bytes()?
+            .into_iter()
+            .find(|option_byte| option_byte.name.eq_ignore_ascii_case(name))
+            .ok_or_else(|| err::Error::UnknownOptionByte(name.to_string()))
+    }
+
+    /// Program the option bytes of `values` at once, each name being checked
+    /// against the device and each value against the field width.
+    pub fn set_option_bytes(&self, values: &[(&str, u32)]) -> Result<(), err::Error> {
+        let option_bytes = self.option_bytes()?;
+
+        let mut command = String::from("-ob");
+        for &(name, value) in values {
+            let option_byte = option_bytes
+                .iter()
+                .find(|option_byte| option_byte.name.eq_ignore_ascii_case(name))
+                .ok_or_else(|| err::Error::UnknownOptionByte(name.to_string()))?;
+            if value > option_byte.max_value() {
+                return Err(err::Error::InvalidConfig(format!(
+                    "{:#x} does not fit the {} bits of {}",
+                    value, option_byte.bit_width, option_byte.name
+                )));
+            }
+            command.push_str(&format!(" {}={:#x}", option_byte.name, value));
+        }
+
+        let rdp = option_bytes
+            .iter()
+            .find(|option_byte| option_byte.name.eq_ignore_ascii_case("RDP"))
+            .map(|option_byte| option_byte.value);
+        self.check_option_bytes(values, rdp)?;
+
+        if self.planned(crate::dry_run::PlannedOperation::OptionBytes {
+            command: command.clone(),
+        }) {
+            return Ok(());
+        }
+
+        let mut command = std::ffi::CString::new(command)
+            .map_err(|error| err::Error::InvalidConfig(error.to_string()))?
+            .into_bytes_with_nul();
+        let error = unsafe {
+            (self.vtable.send_option_bytes_cmd)(command.as_mut_ptr() as *mut std::os::raw::c_char)
+        };
+        if error == 0 {
+            Ok(())
+        } else {
+            Err(err::CubeProgrammerError::from(error).into())
+        }
+    }
+}
@@ -0,0 +1,292 @@
+use crate::err;
+use crate::memory::TransferOptions;
+use crate::STM32CubeProg;
+
+/// One-time programmable area of a family.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct OtpLayout {
+    pub address: u32,
+    pub block_size: u32,
+    pub block_count: u32,
+    /// Programming granularity in bytes. A word holding anything but the
+    /// erased value can no longer be changed.
+    pub word_size: u32,
+    /// One lock byte per block, `None` when blocks cannot be locked.
+    pub lock_address: Option<u32>,
+}
+
+const STM32F2_F4: OtpLayout = OtpLayout {
+    address: 0x1FFF7800,
+    block_size: 32,
+    block_count: 16,
+    word_size: 1,
+    lock_address: Some(0x1FFF7A00),
+};
+
+const STM32F7: OtpLayout = OtpLayout {
+    address: 0x1FF0F000,
+    block_size: 64,
+    block_count: 16,
+    word_size: 1,
+    lock_address: Some(0x1FF0F400),
+};
+
+const STM32L4_G4_G0_WB: OtpLayout = OtpLayout {
+    address: 0x1FFF7000,
+    block_size: 0x400,
+    block_count: 1,
+    word_size: 8,
+    lock_address: None,
+};
+
+const STM32U5: OtpLayout = OtpLayout {
+    address: 0x0BFA0000,
+    block_size: 0x200,
+    block_count: 1,
+    word_size: 16,
+    lock_address: None,
+};
+
+pub(crate) const LAYOUTS: [OtpLayout; 4] = [STM32F2_F4, STM32F7, STM32L4_G4_G0_WB, STM32U5];
+
+impl OtpLayout {
+    pub fn for_device_id(device_id: i32) -> Option<Self> {
+        match device_id {
+            // STM32F2, STM32F4
+            0x411 | 0x413 | 0x419 | 0x421 | 0x423 | 0x431 | 0x433 | 0x434 | 0x441 | 0x458
+            | 0x463 => Some(STM32F2_F4),
+            // STM32F7
+            0x449 | 0x451 | 0x452 => Some(STM32F7),
+            // STM32L4, STM32G4, STM32G0, STM32WB
+            0x415 | 0x435 | 0x461 | 0x462 | 0x464 | 0x470 | 0x471 | 0x468 | 0x469 | 0x479
+            | 0x456 | 0x460 | 0x466 | 0x467 | 0x494 | 0x495 | 0x496 => Some(STM32L4_G4_G0_WB),
+            // STM32U5
+            0x455 | 0x476 | 0x481 | 0x482 => Some(STM32U5),
+            _ => None,
+        }
+    }
+
+    pub fn size(&self) -> u32 {
+        self.block_size * self.block_count
+    }
+
+    pub fn contains(&self, address: u32, size: u32) -> bool {
+        address >= self.address
+            && u64::from(address) + u64::from(size)
+                <= u64::from(self.address) + u64::from(self.size())
+    }
+
+    /// Whether the range overlaps the data or the lock bytes of the area.
+    pub(crate) fn overlaps(&self, address: u32, size: u32) -> bool {
+        let end = u64::from(address) + u64::from(size);
+        let overlaps = |start: u32, length: u32| {
+            u64::from(start) < end && u64::from(address) < u64::from(start) + u64::from(length)
+        };
+        overlaps(self.address, self.size())
+            || self
+                .lock_address
+                .is_some_and(|lock_address| overlaps(lock_address, self.block_count))
+    }
+
+    /// Block holding `address`, `None` outside of the area.
+    pub fn block_of(&self, address: u32) -> Option<u32> {
+        if self.contains(address, 1) {
+            Some((address - self.address) / self.block_size)
+        } else {
+            None
+        }
+    }
+}
+
+/// Content of the OTP area read from the target.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OtpState {
+    pub layout: OtpLayout,
+    pub data: Vec<u8>,
+    pub locked: Vec<bool>,
+}
+
+impl OtpState {
+    /// Whether the word at `address` holds anything but the erased value,
+    /// `None` outside of the area.
+    pub fn is_programmed(&self, address: u32) -> Option<bool> {
+        if !self.layout.contains(address, 1) {
+            return None;
+        }
+        let offset = ((address - self.layout.address) / self.layout.word_size
+            * self.layout.word_size) as usize;
+        self.data
+            .get(offset..offset + self.layout.word_size as usize)
+            .map(|word| word.iter().any(|&byte| byte != 0xFF))
+    }
+
+    pub fn is_locked(&self, block: u32) -> bool {
+        self.locked.get(block as usize).cloned().unwrap_or(false)
+    }
+}
+
+/// Writes an OTP request resolves to, reviewed before `program_otp`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OtpPreview {
+    pub address: u32,
+    pub data: Vec<u8>,
+    pub lock: bool,
+    /// Contiguous runs of words to program.
+    pub writes: Vec<(u32, Vec<u8>)>,
+    /// Words already holding the requested content.
+    pub unchanged: Vec<u32>,
+    /// Blocks to lock.
+    pub locks: Vec<u32>,
+}
+
+impl std::fmt::Display for OtpPreview {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let mut lines = Vec::new();
+        for (address, data) in self.writes.iter() {
+            lines.push(format!("write 0x{:08X}: {:02X?}", address, data));
+        }
+        if !self.unchanged.is_empty() {
+            lines.push(format!("unchanged: {} words", self.unchanged.len()));
+        }
+        for block in self.locks.iter() {
+            lines.push(format!("lock block {}", block));
+        }
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+impl STM32CubeProg {
+    pub fn otp_layout(&self) -> Result<OtpLayout, err::Error> {
+        OtpLayout::for_device_id(self.device_info()?.device_id())
+            .ok_or_else(|| err::CubeProgrammerError::UnsupportedOperation.into())
+    }
+
+    pub fn read_otp(&self) -> Result<OtpState, err::Error> {
+        let layout = self.otp_layout()?;
+        let data = self.read_memory8(layout.address, layout.size())?;
+        let locked = match layout.lock_address {
+            Some(lock_address) => self
+                .read_memory8(lock_address, layout.block_count)?
+                .into_iter()
+                .map(|byte| byte != 0xFF)
+                .collect(),
+            None => vec![false; layout.block_count as usize],
+        };
+
+        Ok(OtpState {
+            layout,
+            data,
+            locked,
+        })
+    }
+
+    /// Check that `data` can be programmed at `address` and list the words
+    /// to write, locking the blocks they cover if `lock` is set.
+    ///
+    /// Programmed words must already hold the requested content.
+    pub fn preview_otp(
+        &self,
+        address: u32,
+        data: &[u8],
+        lock: bool,
+    ) -> Result<OtpPreview, err::Error> {
+        let state = self.read_otp()?;
+        let layout = state.layout;
+        let size: u32 = std::convert::TryInto::try_into(data.len())?;
+
+        let out_of_range = || err::Error::OutOfRange { address, size };
+        if !layout.contains(address, size) {
+            return Err(out_of_range());
+        }
+        if !(address - layout.address).is_multiple_of(layout.word_size)
+            || !size.is_multiple_of(layout.word_size)
+        {
+            return Err(err::Error::UnalignedAccess { address, size });
+        }
+        if lock && layout.lock_address.is_none() {
+            return Err(err::CubeProgrammerError::UnsupportedOperation.into());
+        }
+
+        let mut preview = OtpPreview {
+            address,
+            data: data.to_vec(),
+            lock,
+            writes: Vec::new(),
+            unchanged: Vec::new(),
+            locks: Vec::new(),
+        };
+
+        let mut run: Option<(u32, Vec<u8>)> = None;
+        for (index, word) in data.chunks(layout.word_size as usize).enumerate() {
+            let word_address = address + index as u32 * layout.word_size;
+            let offset = (word_address - layout.address) as usize;
+            let block = layout.block_of(word_address).ok_or_else(out_of_range)?;
+
+            if state.data[offset..offset + word.len()] == *word {
+                preview.unchanged.push(word_address);
+                preview.writes.extend(run.take());
+                continue;
+            }
+            if state.is_locked(block) {
+                return Err(err::Error::OtpLocked(block));
+            }
+            if state.is_programmed(word_address).ok_or_else(out_of_range)? {
+                return Err(err::Error::OtpProgrammed(word_address));
+            }
+
+            match run.as_mut() {
+                Some(run) => run.1.extend_from_slice(word),
+                None => run = Some((word_address, word.to_vec())),
+            }
+        }
+        preview.writes.extend(run);
+
+        if lock && size > 0 {
+            let first = layout.block_of(address).ok_or_else(out_of_range)?;
+            let last = layout
+                .block_of(address + size - 1)
+                .ok_or_else(out_of_range)?;
+            preview.locks = (first..=last)
+                .filter(|&block| !state.is_locked(block))
+                .collect();
+        }
+
+        Ok(preview)
+    }
+
+    /// Program the words and lock the blocks of `preview`, which must still
+    /// match the content of the target.
+    ///
+    /// Requires `confirm_irreversible(IrreversibleChange::OtpWrite)`.
+    pub fn program_otp(&self, preview: &OtpPreview) -> Result<(), err::Error> {
+        let current = self.preview_otp(preview.address, &preview.data, preview.lock)?;
+        if current != *preview {
+            return Err(err::Error::InvalidConfig(String::from(
+                "the OTP content changed since the preview",
+            )));
+        }
+
+        let layout = self.otp_layout()?;
+        let mut writes = preview.writes.clone();
+        if let Some(lock_address) = layout.lock_address {
+            for &block in preview.locks.iter() {
+                writes.push((lock_address + block, vec![0x00]));
+            }
+        }
+        if writes.is_empty() {
+            return Ok(());
+        }
+
+        let ranges: Vec<(u32, u32)> = writes
+            .iter()
+            .map(|(address, data)| (*address, data.len() as u32))
+            .collect();
+        self.check_otp_program(&ranges)?;
+
+        let options = TransferOptions::default();
+        for (address, data) in writes {
+            self.write_unchecked(address, &data, &options, |_, _| {})?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,405 @@
+use crate::config::ConnectionConfig;
+use crate::err;
+use crate::{STLink, STM32CubeProg};
+
+/// Pattern matched against a serial number or a board name.
+///
+/// Glob patterns accept `*` and `?` and ignore ASCII case.
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    Glob(String),
+    #[cfg(feature = "regex")]
+    Regex(regex::Regex),
+}
+
+impl Pattern {
+    pub fn is_match(&self, value: &str) -> bool {
+        match self {
+            Pattern::Glob(pattern) => glob_match(
+                pattern.to_ascii_uppercase().as_bytes(),
+                value.to_ascii_uppercase().as_bytes(),
+            ),
+            #[cfg(feature = "regex")]
+            Pattern::Regex(regex) => regex.is_match(value),
+        }
+    }
+}
+
+impl std::fmt::Display for Pattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Pattern::Glob(pattern) => write!(f, "{}", pattern),
+            #[cfg(feature = "regex")]
+            Pattern::Regex(regex) => write!(f, "/{}/", regex),
+        }
+    }
+}
+
+fn glob_match(pattern: &[u8], value: &[u8]) -> bool {
+    let (mut p, mut v) = (0, 0);
+    let mut backtrack = None;
+
+    while v < value.len() {
+        if p < pattern.len() && (pattern[p] == b'?' || pattern[p] == value[v]) {
+            p += 1;
+            v += 1;
+        } else if p < pattern.len() && pattern[p] == b'*' {
+            backtrack = Some((p, v));
+            p += 1;
+        } else if let Some((star, matched)) = backtrack {
+            p = star + 1;
+            v = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+/// Criteria used to pick ST-Links out of `discover`. Every criterion set must
+/// match.
+#[derive(Debug, Clone, Default)]
+pub struct ProbeSelector {
+    serial_number: Option<Pattern>,
+    board: Option<Pattern>,
+    index: Option<i32>,
+}
+
+impl ProbeSelector {
+    /// Selector matching every probe.
+    pub fn any() -> Self {
+        ProbeSelector::default()
+    }
+
+    pub fn by_serial(pattern: &str) -> Self {
+        ProbeSelector::any().serial(pattern)
+    }
+
+    pub fn by_board(pattern: &str) -> Self {
+        ProbeSelector::any().board(pattern)
+    }
+
+    pub fn by_index(index: i32) -> Self {
+        ProbeSelector::any().index(index)
+    }
+
+    pub fn serial(mut self, pattern: &str) -> Self {
+        self.serial_number = Some(Pattern::Glob(pattern.to_string()));
+        self
+    }
+
+    pub fn board(mut self, pattern: &str) -> Self {
+        self.board = Some(Pattern::Glob(pattern.to_string()));
+        self
+    }
+
+    #[cfg(feature = "regex")]
+    pub fn serial_regex(mut self, regex: &str) -> Result<Self, err::Error> {
+        self.serial_number = Some(Pattern::Regex(regex::Regex::new(regex).map_err(
+            |error| err::Error::InvalidConfig(format!("invalid serial regex: {}", error)),
+        )?));
+        Ok(self)
+    }
+
+    #[cfg(feature = "regex")]
+    pub fn board_regex(mut self, regex: &str) -> Result<Self, err::Error> {
+        self.board = Some(Pattern::Regex(regex::Regex::new(regex).map_err(
+            |error| err::Error::InvalidConfig(format!("invalid board regex: {}", error)),
+        )?));
+        Ok(self)
+    }
+
+    pub fn index(mut self, index: i32) -> Self {
+        self.index = Some(index);
+        self
+    }
+
+    pub fn matches(&self, stlink: &STLink) -> Result<bool, err::Error> {
+        if let Some(index) = self.index {
+            if stlink.index() != index {
+                return Ok(false);
+            }
+        }
+
+        if let Some(pattern) = &self.serial_number {
+            if !pattern.is_match(&stlink.serial_number()?) {
+                return Ok(false);
+            }
+        }
+
+        if let Some(pattern) = &self.board {
+            if !pattern.is_match(&stlink.board()?) {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Probes of `stlinks` matching the selector, in order.
+    pub fn filter(&self, stlinks: Vec<STLink>) -> Result<Vec<STLink>, err::Error> {
+        let mut selected = Vec::new();
+        for stlink in stlinks {
+            if self.matches(&stlink)? {
+                selected.push(stlink);
+            }
+        }
+        Ok(selected)
+    }
+
+    /// The single probe of `stlinks` matching the selector. Fails with the
+    /// list of candidates when none or several match.
+    pub fn select_one(&self, stlinks: Vec<STLink>) -> Result<STLink, err::Error> {
+        let candidates = describe(&stlinks);
+        let mut selected = self.filter(stlinks)?;
+
+        match selected.len() {
+            1 => Ok(selected.remove(0)),
+            0 => Err(err::Error::ProbeNotFound {
+                selector: self.to_string(),
+                candidates,
+            }),
+            _ => Err(err::Error::AmbiguousProbe {
+                selector: self.to_string(),
+                candidates: describe(&selected),
+            }),
+        }
+    }
+}
+
+fn describe(stlinks: &[STLink]) -> Vec<String> {
+    stlinks
+        .iter()
+        .map(|stlink| {
+            format!(
+                "#{} {} ({})",
+                stlink.index(),
+                stlink.serial_number().unwrap_or_default(),
+                stlink.board().unwrap_or_default()
+            )
+        })
+        .collect()
+}
+
+impl std::fmt::Display for ProbeSelector {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let mut criteria = Vec::new();
+        if let Some(pattern) = &self.serial_number {
+            criteria.push(format!("serial {}", pattern));
+        }
+        if let Some(pattern) = &self.board {
+            criteria.push(format!("board {}", pattern));
+        }
+        if let Some(index) = self.index {
+            criteria.push(format!("index {}", index));
+        }
+
+        if criteria.is_empty() {
+            write!(f, "any probe")
+        } else {
+            write!(f, "{}", criteria.join(", "))
+        }
+    }
+}
+
+/// Probe and connection parameters given as
+/// `stlink://SERIAL?freq=4000&mode=under-reset`.
+///
+/// The serial number may be a glob pattern, or empty to match any probe.
+/// Besides `board` and `index`, the query accepts the `ConnectionConfig`
+/// keys and the `freq`, `mode`, `reset`, `port`, `ap` and `sleep` shorthands.
+#[derive(Debug, Clone, Default)]
+pub struct ProbeUri {
+    pub selector: ProbeSelector,
+    pub config: ConnectionConfig,
+}
+
+const SCHEME: &str = "stlink://";
+
+impl std::str::FromStr for ProbeUri {
+    type Err = err::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let rest = s.trim().strip_prefix(SCHEME).ok_or_else(|| {
+            err::Error::InvalidConfig(format!("'{}' is not an {} URI", s, SCHEME))
+        })?;
+        let (serial_number, query) = match rest.split_once('?') {
+            Some((serial_number, query)) => (serial_number, query),
+            None => (rest, ""),
+        };
+
+        let mut uri = ProbeUri::default();
+        let serial_number = serial_number.trim_end_matches('/');
+        if !serial_number.is_empty() && serial_number != "*" {
+            uri.selector = uri.selector.serial(serial_number);
+        }
+
+        for parameter in query.split('&').filter(|parameter| !parameter.is_empty()) {
+            let (key, value) = parameter.split_once('=').ok_or_else(|| {
+                err::Error::InvalidConfig(format!("missing value for '{}'", parameter))
+            })?;
+
+            match key {
+                "board" => uri.selector = uri.selector.board(value),
+                "index" => {
+                    uri.selector = uri.selector.index(value.parse().map_err(|_| {
+                        err::Error::InvalidConfig(format!("invalid index '{}'", value))
+                    })?)
+                }
+                "freq" => uri.config.set("frequency", value)?,
+                "mode" => uri.config.set("connection_mode", value)?,
+                "reset" => uri.config.set("reset_mode", value)?,
+                "port" => uri.config.set("debug_port", value)?,
+                "ap" => uri.config.set("access_port", value)?,
+                "sleep" => uri.config.set("debug_sleep", value)?,
+                key => uri.config.set(key, value)?,
+            }
+        }
+
+        uri.config.validate()?;
+        Ok(uri)
+    }
+}
+
+impl std::fmt::Display for ProbeUri {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", SCHEME)?;
+        if let Some(pattern) = &self.selector.serial_number {
+            write!(f, "{}", pattern)?;
+        }
+
+        let mut query = Vec::new();
+        if let Some(pattern) = &self.selector.board {
+            query.push(format!("board={}", pattern));
+        }
+        if let Some(index) = self.selector.index {
+            query.push(format!("index={}", index));
+        }
+        for line in self.config.to_string().lines() {
+            if let Some((key, value)) = line.split_once('=') {
+                query.push(format!("{}={}", key.trim(), value.trim().trim_matches('"')));
+            }
+        }
+
+        if !query.is_empty() {
+            write!(f, "?{}", query.join("&"))?;
+        }
+        Ok(())
+    }
+}
+
+impl STM32CubeProg {
+    /// Discovered probes matching `selector`.
+    pub fn select_probes(&self, selector: &ProbeSelector) -> Result<Vec<STLink>, err::Error> {
+        selector.filter(self.discover()?)
+    }
+
+    /// The single discovered probe matching `selector`.
+    pub fn select_probe(&self, selector: &ProbeSelector) -> Result<STLink, err::Error> {
+        selector.select_one(self.discover()?)
+    }
+
+    /// The probe designated by `uri`, configured with its parameters.
+    pub fn probe_from_uri(&self, uri: &ProbeUri) -> Result<STLink, err::Error> {
+        let mut stlink = self.select_probe(&uri.selector)?;
+        uri.config.apply(&mut stlink)?;
+        Ok(stlink)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn glob(pattern: &str, value: &str) -> bool {
+        Pattern::Glob(pattern.to_string()).is_match(value)
+    }
+
+    #[test]
+    fn glob_matches_literals_ignoring_case() {
+        assert!(glob("066DFF", "066dff"));
+        assert!(!glob("066DFF", "066DFF0"));
+        assert!(!glob("066DFF0", "066DFF"));
+        assert!(glob("", ""));
+        assert!(!glob("", "0"));
+    }
+
+    #[test]
+    fn glob_matches_wildcards() {
+        assert!(glob("*", ""));
+        assert!(glob("*", "066DFF"));
+        assert!(glob("066*", "066DFF"));
+        assert!(glob("*FF", "066DFF"));
+        assert!(glob("0?6*F", "066DFF"));
+        assert!(glob("*6*F*", "066DFF"));
+        assert!(!glob("0?6", "06"));
+        assert!(!glob("*FE", "066DFF"));
+    }
+
+    #[test]
+    fn glob_backtracks_after_partial_matches() {
+        assert!(glob("*AB", "AAAB"));
+        assert!(glob("A*B*C", "AXBXBXC"));
+        assert!(!glob("A*B*C", "AXBXBX"));
+        assert!(glob("**B", "AB"));
+    }
+
+    #[test]
+    fn uri_is_parsed() {
+        let uri: ProbeUri = "stlink://066D*?board=NUCLEO-*&index=1&freq=4000&mode=under-reset"
+            .parse()
+            .unwrap();
+
+        assert_eq!(uri.selector.serial_number.unwrap().to_string(), "066D*");
+        assert_eq!(uri.selector.board.unwrap().to_string(), "NUCLEO-*");
+        assert_eq!(uri.selector.index, Some(1));
+
+        let mut config = ConnectionConfig::default();
+        config.set("frequency", "4000").unwrap();
+        config.set("connection_mode", "under-reset").unwrap();
+        assert_eq!(uri.config, config);
+    }
+
+    #[test]
+    fn uri_without_serial_number_matches_any_probe() {
+        for text in ["stlink://", "stlink://*", "stlink:///"].iter() {
+            let uri: ProbeUri = text.parse().unwrap();
+            assert!(uri.selector.serial_number.is_none());
+            assert_eq!(uri.config, ConnectionConfig::default());
+        }
+    }
+
+    #[test]
+    fn invalid_uris_are_rejected() {
+        for text in [
+            "066DFF",
+            "usb://066DFF",
+            "stlink://066DFF?freq",
+            "stlink://066DFF?index=first",
+            "stlink://066DFF?freq=0",
+            "stlink://066DFF?color=blue",
+        ]
+        .iter()
+        {
+            match text.parse::<ProbeUri>() {
+                Err(err::Error::InvalidConfig(_)) => {}
+                result => panic!("{} gave {:?}", text, result),
+            }
+        }
+    }
+
+    #[test]
+    fn uri_round_trips() {
+        let text = "stlink://066D*?board=NUCLEO-*&index=1&debug_port=swd&frequency=4000\
+                    &connection_mode=under-reset&access_port=1&debug_sleep=true&shared=true";
+        let uri: ProbeUri = text.parse().unwrap();
+        let printed = uri.to_string();
+        let reparsed: ProbeUri = printed.parse().unwrap();
+
+        assert_eq!(reparsed.config, uri.config);
+        assert_eq!(reparsed.to_string(), printed);
+        assert_eq!(ProbeUri::default().to_string(), "stlink://");
+    }
+}
@@ -0,0 +1,185 @@
+use crate::err;
+use crate::STM32CubeProg;
+
+/// Start of the main flash, checked for blankness after a regression.
+const FLASH_ADDRESS: u32 = 0x08000000;
+
+/// Readout protection level, decoded from the RDP option byte.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RdpLevel {
+    Level0,
+    Level1,
+    Level2,
+}
+
+impl RdpLevel {
+    pub fn from_value(value: u32) -> Self {
+        match value {
+            0xAA => RdpLevel::Level0,
+            0xCC => RdpLevel::Level2,
+            _ => RdpLevel::Level1,
+        }
+    }
+}
+
+impl std::fmt::Display for RdpLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RdpLevel::Level0 => write!(f, "level 0"),
+            RdpLevel::Level1 => write!(f, "level 1"),
+            RdpLevel::Level2 => write!(f, "level 2"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct UnlockOptions {
+    /// How long to keep trying to reconnect after the regression.
+    pub timeout: std::time::Duration,
+    /// Pause between two reconnection attempts.
+    pub delay: std::time::Duration,
+    /// Read the whole flash back to check it was erased. Skipped on
+    /// families whose erased value is unknown.
+    pub verify_blank: bool,
+}
+
+impl Default for UnlockOptions {
+    fn default() -> Self {
+        UnlockOptions {
+            timeout: std::time::Duration::from_secs(10),
+            delay: std::time::Duration::from_millis(200),
+            verify_blank: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnlockReport {
+    /// Level before the regression.
+    pub previous: RdpLevel,
+    /// Whether a regression was performed, false when already at level 0.
+    pub regressed: bool,
+    /// Whether the regression was only recorded, in dry-run mode.
+    pub planned: bool,
+    pub reconnect_attempts: u32,
+    /// Flash bytes checked to be erased.
+    pub verified_size: u32,
+}
+
+/// Value of erased flash bytes, `None` when the family is unknown.
+pub fn erased_value(device_id: i32) -> Option<u8> {
+    match device_id {
+        // STM32L0, STM32L1
+        0x417 | 0x425 | 0x447 | 0x457 | 0x416 | 0x429 | 0x427 | 0x436 | 0x437 => Some(0x00),
+        // STM32F0, STM32F1, STM32F3
+        0x440 | 0x442 | 0x444 | 0x445 | 0x448 | 0x410 | 0x412 | 0x414 | 0x418 | 0x420 | 0x428
+        | 0x430 | 0x422 | 0x432 | 0x438 | 0x439 | 0x446 => Some(0xFF),
+        // STM32F2, STM32F4, STM32F7
+        0x411 | 0x413 | 0x419 | 0x421 | 0x423 | 0x431 | 0x433 | 0x434 | 0x441 | 0x458 | 0x463
+        | 0x449 | 0x451 | 0x452 => Some(0xFF),
+        // STM32L4, STM32G4, STM32G0, STM32WB
+        0x415 | 0x435 | 0x461 | 0x462 | 0x464 | 0x470 | 0x471 | 0x468 | 0x469 | 0x479 | 0x456
+        | 0x460 | 0x466 | 0x467 | 0x494 | 0x495 | 0x496 => Some(0xFF),
+        // STM32H7, STM32U5
+        0x450 | 0x480 | 0x483 | 0x455 | 0x476 | 0x481 | 0x482 => Some(0xFF),
+        _ => None,
+    }
+}
+
+impl STM32CubeProg {
+    pub fn rdp_level(&self) -> Result<RdpLevel, err::Error> {
+        Ok(RdpLevel::from_value(self.option_byte("RDP")?.value))
+    }
+
+    /// Drop readout protection from level 1 to level 0, which mass-erases the
+    /// flash, then reconnect and check the device is readable and blank.
+    ///
+    /// When reconnecting fails after the regression failed too, both errors
+    /// are returned in `RegressionFailed`.
+    ///
+    /// The regression must be confirmed beforehand with
+    /// `confirm_irreversible(IrreversibleChange::ReadoutProtectionRegression)`.
+    pub fn unlock(&self, options: &UnlockOptions) -> Result<UnlockReport, err::Error> {
+        let stlink = self
+            .connected_stlink()
+            .ok_or(err::CubeProgrammerError::DeviceNotConnected)?;
+
+        let previous = self.rdp_level()?;
+        let mut report = UnlockReport {
+            previous,
+            regressed: false,
+            planned: false,
+            reconnect_attempts: 0,
+            verified_size: 0,
+        };
+        match previous {
+            RdpLevel::Level0 => return Ok(report),
+            RdpLevel::Level2 => return Err(err::CubeProgrammerError::RdpEnabledError.into()),
+            RdpLevel::Level1 => {}
+        }
+
+        // The target usually drops the connection while reloading the option
+        // bytes, so the outcome is judged after reconnecting
+        let regression = match self.set_option_bytes(&[("RDP", 0xAA)]) {
+            Err(error @ err::Error::SafetyViolation { .. }) => return Err(error),
+            result => result,
+        };
+        if self.is_dry_run() {
+            report.planned = true;
+            return Ok(report);
+        }
+        report.regressed = true;
+
+        self.disconnect();
+        let start = std::time::Instant::now();
+        loop {
+            std::thread::sleep(options.delay);
+            report.reconnect_attempts += 1;
+            match self.connect(&stlink) {
+                Ok(()) => break,
+                Err(error) if start.elapsed() >= options.timeout => {
+                    return Err(match regression {
+                        Ok(()) => error,
+                        Err(regression) => err::Error::RegressionFailed {
+                            regression: Box::new(regression),
+                            reconnection: Box::new(error),
+                        },
+                    })
+                }
+                Err(_) => {}
+            }
+        }
+
+        let level = self.rdp_level()?;
+        if level != RdpLevel::Level0 {
+            regression?;
+            return Err(err::Error::UnlockFailed(format!(
+                "readout protection is still at {}",
+                level
+            )));
+        }
+
+        if options.verify_blank {
+            let device_info = self.device_info()?;
+            if let Some(erased) = erased_value(device_info.device_id()) {
+                let size = device_info.flash_size().max(0) as u32;
+                let chunk_size = 0x1000;
+                let mut offset = 0;
+                while offset < size {
+                    let length = std::cmp::min(chunk_size, size - offset);
+                    let data = self.read_memory8(FLASH_ADDRESS + offset, length)?;
+                    if let Some(position) = data.iter().position(|&byte| byte != erased) {
+                        return Err(err::Error::UnlockFailed(format!(
+                            "flash is not blank at 0x{:08X}",
+                            FLASH_ADDRESS + offset + position as u32
+                        )));
+                    }
+                    offset += length;
+                }
+                report.verified_size = size;
+            }
+        }
+
+        Ok(report)
+    }
+}
@@ -0,0 +1,492 @@
+//! Declarative production jobs, written in TOML.
+//!
+//! ```toml
+//! probe = "stlink://0670FF*?mode=under-reset"
+//!
+//! [connection]
+//! frequency = 4000
+//!
+//! [[memory]]
+//! name = "flash"
+//! address = 0x08000000
+//! size = 0x100000
+//! sector_size = 0x20000
+//!
+//! [[steps]]
+//! action = "erase"
+//! sectors = [0, 1]
+//!
+//! [[steps]]
+//! action = "program"
+//! file = "bootloader.bin"
+//! address = 0x08000000
+//!
+//! [[steps]]
+//! action = "option-bytes"
+//! values = { nBOOT0 = 1 }
+//!
+//! [[steps]]
+//! action = "reset"
+//! ```
+//!
+//! Relative file paths are resolved from the directory of the recipe.
+
+use crate::checksum::{ChecksumAlgorithm, ChecksumMethod};
+use crate::config::ConnectionConfig;
+use crate::err;
+use crate::probe::{ProbeSelector, ProbeUri};
+use crate::{DeviceInfo, STLink, STM32CubeProg};
+
+/// Base address of the main flash on STM32 devices.
+const FLASH_ADDRESS: u32 = 0x0800_0000;
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct MemoryRegion {
+    pub name: String,
+    pub address: u32,
+    pub size: u32,
+    /// Size of the erase sectors, for regions of uniform sectors.
+    #[serde(default)]
+    pub sector_size: Option<u32>,
+}
+
+impl MemoryRegion {
+    pub fn contains(&self, address: u32, size: u32) -> bool {
+        address >= self.address
+            && u64::from(address) + u64::from(size)
+                <= u64::from(self.address) + u64::from(self.size)
+    }
+
+    pub fn sector_count(&self) -> Option<u32> {
+        self.sector_size
+            .filter(|&sector_size| sector_size > 0)
+            .map(|sector_size| self.size.div_ceil(sector_size))
+    }
+}
+
+/// Memory regions the steps of a recipe are checked against.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemoryMap {
+    pub regions: Vec<MemoryRegion>,
+}
+
+impl MemoryMap {
+    /// Main flash of `device_info`, at the usual STM32 address.
+    pub fn for_device(device_info: &DeviceInfo) -> Self {
+        MemoryMap {
+            regions: vec![MemoryRegion {
+                name: "flash".to_string(),
+                address: FLASH_ADDRESS,
+                size: device_info.flash_size().max(0) as u32,
+                sector_size: None,
+            }],
+        }
+    }
+
+    pub fn region(&self, address: u32, size: u32) -> Option<&MemoryRegion> {
+        self.regions
+            .iter()
+            .find(|region| region.contains(address, size))
+    }
+
+    fn flash(&self) -> Option<&MemoryRegion> {
+        self.regions
+            .iter()
+            .find(|region| region.name.eq_ignore_ascii_case("flash"))
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "action", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum Step {
+    /// Erase `sectors`, or the whole flash when missing.
+    Erase {
+        #[serde(default)]
+        sectors: Option<Vec<u32>>,
+    },
+    /// Program an ELF, HEX, SREC or binary file through the library.
+    Program {
+        file: std::path::PathBuf,
+        /// Load address, required by binary files.
+        #[serde(default)]
+        address: Option<u32>,
+        #[serde(default = "default_true")]
+        verify: bool,
+        #[serde(default)]
+        skip_erase: bool,
+    },
+    /// Write the content of a raw file to memory.
+    Write {
+        file: std::path::PathBuf,
+        address: u32,
+    },
+    OptionBytes {
+        values: std::collections::BTreeMap<String, u32>,
+    },
+    /// Compare memory with the content of a raw file.
+    Verify {
+        file: std::path::PathBuf,
+        address: u32,
+    },
+    // Braces so that `deny_unknown_fields` also applies to this step
+    Reset {},
+}
+
+impl std::fmt::Display for Step {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Step::Erase { sectors: None } => write!(f, "mass erase"),
+            Step::Erase {
+                sectors: Some(sectors),
+            } => write!(f, "erase sectors {:?}", sectors),
+            Step::Program { file, address, .. } => {
+                write!(f, "program {}", file.display())?;
+                if let Some(address) = address {
+                    write!(f, " at 0x{:08X}", address)?;
+                }
+                Ok(())
+            }
+            Step::Write { file, address } => {
+                write!(f, "write {} at 0x{:08X}", file.display(), address)
+            }
+            Step::OptionBytes { values } => {
+                write!(f, "option bytes")?;
+                for (name, value) in values {
+                    write!(f, " {}={:#x}", name, value)?;
+                }
+                Ok(())
+            }
+            Step::Verify { file, address } => {
+                write!(f, "verify {} at 0x{:08X}", file.display(), address)
+            }
+            Step::Reset {} => write!(f, "reset"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RecipeFile {
+    #[serde(default)]
+    probe: Option<String>,
+    #[serde(default)]
+    connection: toml::Table,
+    #[serde(default)]
+    memory: Vec<MemoryRegion>,
+    #[serde(default)]
+    steps: Vec<Step>,
+}
+
+/// Parsed recipe, see the module documentation for the format.
+#[derive(Debug, Clone, Default)]
+pub struct Recipe {
+    pub probe: Option<ProbeUri>,
+    pub connection: ConnectionConfig,
+    /// Memory map declared by the recipe, empty to use the device one.
+    pub memory: MemoryMap,
+    pub steps: Vec<Step>,
+    /// Directory relative file paths are resolved from.
+    pub base_directory: std::path::PathBuf,
+}
+
+impl std::str::FromStr for Recipe {
+    type Err = err::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let file: RecipeFile = toml::from_str(s)
+            .map_err(|error| err::Error::InvalidRecipe(vec![error.to_string()]))?;
+
+        let mut connection = ConnectionConfig::default();
+        for (key, value) in file.connection.iter() {
+            match value {
+                toml::Value::String(value) => connection.set(key, value)?,
+                value => connection.set(key, &value.to_string())?,
+            }
+        }
+        connection.validate()?;
+
+        Ok(Recipe {
+            probe: file.probe.map(|probe| probe.parse()).transpose()?,
+            connection,
+            memory: MemoryMap {
+                regions: file.memory,
+            },
+            steps: file.steps,
+            base_directory: std::path::PathBuf::new(),
+        })
+    }
+}
+
+/// Outcome of one step of `run_recipe`.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct StepRecord {
+    pub index: usize,
+    pub step: String,
+    pub duration_ms: u64,
+    /// Error message when the step failed.
+    pub error: Option<String>,
+}
+
+/// Steps run by `run_recipe`, the last one being the failing one if any.
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct RecipeLog {
+    pub probe: Option<String>,
+    pub steps: Vec<StepRecord>,
+}
+
+impl RecipeLog {
+    pub fn succeeded(&self) -> bool {
+        self.steps.iter().all(|step| step.error.is_none())
+    }
+}
+
+impl std::fmt::Display for RecipeLog {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for (index, record) in self.steps.iter().enumerate() {
+            if index > 0 {
+                writeln!(f)?;
+            }
+            write!(
+                f,
+                "[{}] {}: {} ({} ms)",
+                record.index + 1,
+                record.step,
+                record.error.as_deref().unwrap_or("ok"),
+                record.duration_ms
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Recipe {
+    pub fn load<P: AsRef<std::path::Path>>(path: P) -> Result<Self, err::Error> {
+        let mut recipe: Recipe = std::fs::read_to_string(path.as_ref())?.parse()?;
+        recipe.base_directory = path
+            .as_ref()
+            .parent()
+            .map(std::path::Path::to_path_buf)
+            .unwrap_or_default();
+        Ok(recipe)
+    }
+
+    fn path(&self, file: &std::path::Path) -> std::path::PathBuf {
+        self.base_directory.join(file)
+    }
+
+    /// Check the steps without touching the target: files must exist, raw
+    /// files and binary images must fit in a region of `memory`, and erased
+    /// sectors must exist. Every problem found is reported at once.
+    pub fn validate(&self, memory: &MemoryMap) -> Result<(), err::Error> {
+        let mut issues = Vec::new();
+
+        for (index, step) in self.steps.iter().enumerate() {
+            let mut issue = |message: String| {
+                issues.push(format!("step {} ({}): {}", index + 1, step, message))
+            };
+
+            let (file, address) = match step {
+                Step::Program { file, address, .. } => (Some(file), *address),
+                Step::Write { file, address } | Step::Verify { file, address } => {
+                    (Some(file), Some(*address))
+                }
+                Step::Erase {
+                    sectors: Some(sectors),
+                } => {
+                    match memory.flash().and_then(MemoryRegion::sector_count) {
+                        Some(count) => {
+                            for sector in sectors.iter().filter(|&&sector| sector >= count) {
+                                issue(format!(
+                                    "sector {} is beyond the {} sectors of the flash",
+                                    sector, count
+                                ));
+                            }
+                        }
+                        None if memory.flash().is_none() => issue("no flash region".to_string()),
+                        None => (),
+                    }
+                    (None, None)
+                }
+                Step::OptionBytes { values } if values.is_empty() => {
+                    issue("no option byte".to_string());
+                    (None, None)
+                }
+                _ => (None, None),
+            };
+
+            let file = match file {
+                Some(file) => self.path(file),
+                None => continue,
+            };
+            let size = match std::fs::metadata(&file) {
+                Ok(metadata) => metadata.len(),
+                Err(error) => {
+                    issue(format!("cannot read {}: {}", file.display(), error));
+                    continue;
+                }
+            };
+
+            let raw = match step {
+                Step::Program { .. } => file
+                    .extension()
+                    .is_some_and(|extension| extension.eq_ignore_ascii_case("bin")),
+                _ => true,
+            };
+            match (raw, address) {
+                (true, None) => issue("binary files need an address".to_string()),
+                (true, Some(address)) => {
+                    let fits = std::convert::TryInto::<u32>::try_into(size)
+                        .ok()
+                        .and_then(|size| memory.region(address, size))
+                        .is_some();
+                    if !fits {
+                        issue(format!(
+                            "{} bytes at 0x{:08X} do not fit in the memory map",
+                            size, address
+                        ));
+                    }
+                }
+                (false, _) => (),
+            }
+        }
+
+        if issues.is_empty() {
+            Ok(())
+        } else {
+            Err(err::Error::InvalidRecipe(issues))
+        }
+    }
+}
+
+impl STM32CubeProg {
+    fn recipe_probe(&self, recipe: &Recipe) -> Result<STLink, err::Error> {
+        let mut stlink = match &recipe.probe {
+            Some(uri) => self.probe_from_uri(uri)?,
+            None => self.select_probe(&ProbeSelector::any())?,
+        };
+        recipe.connection.apply(&mut stlink)?;
+        Ok(stlink)
+    }
+
+    fn recipe_memory(&self, recipe: &Recipe) -> Result<MemoryMap, err::Error> {
+        if recipe.memory.regions.is_empty() {
+            Ok(MemoryMap::for_device(&self.device_info()?))
+        } else {
+            Ok(recipe.memory.clone())
+        }
+    }
+
+    /// Connect to the probe of `recipe` and validate it against the memory
+    /// map of the recipe, or of the device when the recipe has none. Option
+    /// byte names are checked against the device.
+    pub fn validate_recipe(&self, recipe: &Recipe) -> Result<(), err::Error> {
+        let stlink = self.recipe_probe(recipe)?;
+        self.connect(&stlink)?;
+        let result = self.validate_connected(recipe);
+        self.disconnect();
+        result
+    }
+
+    fn validate_connected(&self, recipe: &Recipe) -> Result<(), err::Error> {
+        recipe.validate(&self.recipe_memory(recipe)?)?;
+
+        let option_bytes = self.option_bytes()?;
+        let unknown: Vec<String> = recipe
+            .steps
+            .iter()
+            .filter_map(|step| match step {
+                Step::OptionBytes { values } => Some(values.keys()),
+                _ => None,
+            })
+            .flatten()
+            .filter(|name| {
+                !option_bytes
+                    .iter()
+                    .any(|option_byte| option_byte.name.eq_ignore_ascii_case(name))
+            })
+            .map(|name| format!("unknown option byte {}", name))
+            .collect();
+
+        if unknown.is_empty() {
+            Ok(())
+        } else {
+            Err(err::Error::InvalidRecipe(unknown))
+        }
+    }
+
+    fn run_step(&self, recipe: &Recipe, stlink: &STLink, step: &Step) -> Result<(), err::Error> {
+        match step {
+            Step::Erase { sectors: None } => self.mass_erase(),
+            Step::Erase {
+                sectors: Some(sectors),
+            } => self.sector_erase(sectors),
+            Step::Program {
+                file,
+                address,
+                verify,
+                skip_erase,
+            } => self.download(
+                recipe.path(file),
+                *address,
+                Some(*skip_erase),
+                Some(*verify),
+            ),
+            Step::Write { file, address } => {
+                self.write_slice(*address, &std::fs::read(recipe.path(file))?)
+            }
+            Step::OptionBytes { values } => {
+                let values: Vec<(&str, u32)> = values
+                    .iter()
+                    .map(|(name, value)| (name.as_str(), *value))
+                    .collect();
+                self.set_option_bytes(&values)
+            }
+            Step::Verify { file, address } => self
+                .verify_checksum(
+                    *address,
+                    &std::fs::read(recipe.path(file))?,
+                    ChecksumAlgorithm::Crc32,
+                    ChecksumMethod::ReadBack,
+                )
+                .map(|_| ()),
+            Step::Reset {} => self.reset(stlink),
+        }
+    }
+
+    /// Validate `recipe` against the device, then run its steps in order,
+    /// stopping at the first failure. The log is returned in both cases,
+    /// errors are only returned when the recipe could not be started.
+    pub fn run_recipe(&self, recipe: &Recipe) -> Result<RecipeLog, err::Error> {
+        let stlink = self.recipe_probe(recipe)?;
+        self.connect(&stlink)?;
+        if let Err(error) = self.validate_connected(recipe) {
+            self.disconnect();
+            return Err(error);
+        }
+
+        let mut log = RecipeLog {
+            probe: stlink.serial_number().ok(),
+            steps: Vec::new(),
+        };
+        for (index, step) in recipe.steps.iter().enumerate() {
+            let start = std::time::Instant::now();
+            let result = self.run_step(recipe, &stlink, step);
+            log.steps.push(StepRecord {
+                index,
+                step: step.to_string(),
+                duration_ms: start.elapsed().as_millis() as u64,
+                error: result.as_ref().err().map(|error| error.to_string()),
+            });
+            if result.is_err() {
+                break;
+            }
+        }
+
+        self.disconnect();
+        Ok(log)
+    }
+}
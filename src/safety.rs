@@ -0,0 +1,326 @@
+use crate::err;
+use crate::flash::FlashLayout;
+use crate::image::Image;
+use crate::otp;
+use crate::rdp::RdpLevel;
+use crate::STM32CubeProg;
+
+/// Address range that must never be erased or written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProtectedRegion {
+    pub name: String,
+    pub address: u32,
+    pub size: u32,
+}
+
+impl ProtectedRegion {
+    pub fn overlaps(&self, address: u32, size: u32) -> bool {
+        u64::from(self.address) < u64::from(address) + u64::from(size)
+            && u64::from(address) < u64::from(self.address) + u64::from(self.size)
+    }
+}
+
+impl std::fmt::Display for ProtectedRegion {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} (0x{:08X}, {} bytes)",
+            self.name, self.address, self.size
+        )
+    }
+}
+
+/// Change that cannot be undone once made.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum IrreversibleChange {
+    /// Setting RDP to level 2, which permanently disables debug access.
+    ReadoutProtectionLevel2,
+    /// Changing write protection while RDP level 2 freezes it.
+    PermanentWriteProtection,
+    /// Dropping RDP from level 1 to level 0, which mass-erases the flash.
+    ReadoutProtectionRegression,
+    /// Writing to an OTP area.
+    OtpWrite,
+}
+
+impl std::fmt::Display for IrreversibleChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            IrreversibleChange::ReadoutProtectionLevel2 => write!(f, "readout protection level 2"),
+            IrreversibleChange::PermanentWriteProtection => write!(f, "permanent write protection"),
+            IrreversibleChange::ReadoutProtectionRegression => {
+                write!(f, "readout protection regression")
+            }
+            IrreversibleChange::OtpWrite => write!(f, "OTP write"),
+        }
+    }
+}
+
+/// Rule of the safety policy that blocked an operation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SafetyRule {
+    ProtectedRegion(ProtectedRegion),
+    /// The change was not confirmed with `confirm_irreversible`.
+    Unconfirmed(IrreversibleChange),
+    /// The addresses touched by the operation are unknown, so the protected
+    /// regions cannot be checked.
+    UnknownRange,
+}
+
+impl std::fmt::Display for SafetyRule {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SafetyRule::ProtectedRegion(region) => write!(f, "protected region {}", region),
+            SafetyRule::Unconfirmed(change) => write!(f, "unconfirmed {}", change),
+            SafetyRule::UnknownRange => write!(
+                f,
+                "protected regions cannot be checked against an unknown range"
+            ),
+        }
+    }
+}
+
+/// Checks made before each erase, write, download and option byte change.
+///
+/// Irreversible changes are always refused unless confirmed beforehand.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SafetyPolicy {
+    protected_regions: Vec<ProtectedRegion>,
+    flash_layout: Option<FlashLayout>,
+    confirmations: Vec<IrreversibleChange>,
+}
+
+impl SafetyPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Refuse any erase or write overlapping `size` bytes at `address`.
+    pub fn protect(mut self, name: &str, address: u32, size: u32) -> Self {
+        self.protected_regions.push(ProtectedRegion {
+            name: name.to_string(),
+            address,
+            size,
+        });
+        self
+    }
+
+    /// Sector organization used to check `sector_erase` and `mass_erase`.
+    /// Without it, erases are refused as soon as a region is protected.
+    pub fn flash_layout(mut self, flash_layout: FlashLayout) -> Self {
+        self.flash_layout = Some(flash_layout);
+        self
+    }
+
+    pub fn protected_regions(&self) -> &[ProtectedRegion] {
+        &self.protected_regions
+    }
+
+    pub fn check_range(&self, address: u32, size: u32) -> Result<(), SafetyRule> {
+        match self
+            .protected_regions
+            .iter()
+            .find(|region| region.overlaps(address, size))
+        {
+            Some(region) => Err(SafetyRule::ProtectedRegion(region.clone())),
+            None => Ok(()),
+        }
+    }
+
+    pub fn check_sectors(&self, sectors: &[u32]) -> Result<(), SafetyRule> {
+        if self.protected_regions.is_empty() {
+            return Ok(());
+        }
+        let layout = self.flash_layout.as_ref().ok_or(SafetyRule::UnknownRange)?;
+        for &index in sectors {
+            let sector = layout
+                .sectors()
+                .iter()
+                .find(|sector| sector.index == index)
+                .ok_or(SafetyRule::UnknownRange)?;
+            self.check_range(sector.address, sector.size)?;
+        }
+        Ok(())
+    }
+
+    pub fn check_mass_erase(&self) -> Result<(), SafetyRule> {
+        if self.protected_regions.is_empty() {
+            return Ok(());
+        }
+        let layout = self.flash_layout.as_ref().ok_or(SafetyRule::UnknownRange)?;
+        for sector in layout.sectors() {
+            self.check_range(sector.address, sector.size)?;
+        }
+        Ok(())
+    }
+
+    /// Check that `changes` were all confirmed, consuming the confirmations
+    /// if `consume` is set.
+    fn confirmed(
+        &mut self,
+        changes: &[IrreversibleChange],
+        consume: bool,
+    ) -> Result<(), SafetyRule> {
+        if let Some(&change) = changes
+            .iter()
+            .find(|change| !self.confirmations.contains(change))
+        {
+            return Err(SafetyRule::Unconfirmed(change));
+        }
+        if !consume {
+            return Ok(());
+        }
+        for change in changes {
+            if let Some(position) = self.confirmations.iter().position(|c| c == change) {
+                self.confirmations.remove(position);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_otp(address: u32, size: u32) -> bool {
+    otp::LAYOUTS
+        .iter()
+        .any(|layout| layout.overlaps(address, size))
+}
+
+impl STM32CubeProg {
+    pub fn set_safety_policy(&self, policy: SafetyPolicy) {
+        self.safety.replace(policy);
+    }
+
+    pub fn safety_policy(&self) -> SafetyPolicy {
+        self.safety.borrow().clone()
+    }
+
+    /// Allow the next operation making `change`, once. Operations recorded
+    /// by a dry run check the confirmations without consuming them.
+    pub fn confirm_irreversible(&self, change: IrreversibleChange) {
+        self.safety.borrow_mut().confirmations.push(change);
+    }
+
+    pub(crate) fn check_write(&self, address: u32, size: u32) -> Result<(), err::Error> {
+        let consume = !self.is_dry_run();
+        let mut policy = self.safety.borrow_mut();
+        let result = policy.check_range(address, size).and_then(|()| {
+            if is_otp(address, size) {
+                policy.confirmed(&[IrreversibleChange::OtpWrite], consume)
+            } else {
+                Ok(())
+            }
+        });
+        violation(result, || {
+            format!("write of {} bytes at 0x{:08X}", size, address)
+        })
+    }
+
+    /// Check the OTP writes of `program_otp`, confirmed once for all.
+    pub(crate) fn check_otp_program(&self, ranges: &[(u32, u32)]) -> Result<(), err::Error> {
+        let consume = !self.is_dry_run();
+        let mut policy = self.safety.borrow_mut();
+        let result = ranges
+            .iter()
+            .try_for_each(|&(address, size)| policy.check_range(address, size))
+            .and_then(|()| policy.confirmed(&[IrreversibleChange::OtpWrite], consume));
+        violation(result, || String::from("OTP programming"))
+    }
+
+    pub(crate) fn check_erase(&self, sectors: Option<&[u32]>) -> Result<(), err::Error> {
+        let policy = self.safety.borrow();
+        match sectors {
+            Some(sectors) => violation(policy.check_sectors(sectors), || {
+                format!("erase of sectors {:?}", sectors)
+            }),
+            None => violation(policy.check_mass_erase(), || String::from("mass erase")),
+        }
+    }
+
+    /// Check the ranges of the image at `path`. Images that cannot be parsed
+    /// are left to the library, unless regions are protected or `address`
+    /// is in an OTP area, in which case they are refused or need an OTP
+    /// write confirmation.
+    pub(crate) fn check_download(
+        &self,
+        path: &std::path::Path,
+        address: Option<u32>,
+    ) -> Result<(), err::Error> {
+        let ranges = match Image::load(path, address) {
+            Ok(image) => Some(image.ranges()),
+            Err(error @ err::Error::IoError(_)) => return Err(error),
+            Err(_) => None,
+        };
+
+        let consume = !self.is_dry_run();
+        let mut policy = self.safety.borrow_mut();
+        let result = match ranges {
+            Some(ranges) => ranges.iter().try_for_each(|&(address, size)| {
+                policy.check_range(address, size)?;
+                if is_otp(address, size) {
+                    policy.confirmed(&[IrreversibleChange::OtpWrite], consume)
+                } else {
+                    Ok(())
+                }
+            }),
+            None if !policy.protected_regions.is_empty() => Err(SafetyRule::UnknownRange),
+            None => match address {
+                Some(address) if is_otp(address, 1) => {
+                    policy.confirmed(&[IrreversibleChange::OtpWrite], consume)
+                }
+                _ => Ok(()),
+            },
+        };
+        violation(result, || format!("download of {}", path.display()))
+    }
+
+    /// Check the `(name, value)` option bytes about to be programmed, `rdp`
+    /// being the current value of the RDP option byte.
+    pub(crate) fn check_option_bytes(
+        &self,
+        values: &[(&str, u32)],
+        rdp: Option<u32>,
+    ) -> Result<(), err::Error> {
+        let level = values
+            .iter()
+            .find(|&&(name, _)| name.eq_ignore_ascii_case("RDP"))
+            .map(|&(_, value)| RdpLevel::from_value(value));
+        let rdp2 = level == Some(RdpLevel::Level2);
+        let regression = level == Some(RdpLevel::Level0)
+            && rdp.map(RdpLevel::from_value) == Some(RdpLevel::Level1);
+        let wrp = values.iter().any(|&(name, _)| {
+            let name = name.to_ascii_uppercase();
+            name.starts_with("WRP") || name.starts_with("NWRP")
+        });
+
+        let mut changes = Vec::new();
+        if rdp2 {
+            changes.push(IrreversibleChange::ReadoutProtectionLevel2);
+            if wrp {
+                changes.push(IrreversibleChange::PermanentWriteProtection);
+            }
+        }
+        if regression {
+            changes.push(IrreversibleChange::ReadoutProtectionRegression);
+        }
+
+        let consume = !self.is_dry_run();
+        let result = self.safety.borrow_mut().confirmed(&changes, consume);
+        violation(result, || {
+            let values: Vec<String> = values
+                .iter()
+                .map(|(name, value)| format!("{}={:#x}", name, value))
+                .collect();
+            format!("option bytes {}", values.join(" "))
+        })
+    }
+}
+
+fn violation<F>(result: Result<(), SafetyRule>, operation: F) -> Result<(), err::Error>
+where
+    F: FnOnce() -> String,
+{
+    result.map_err(|rule| err::Error::SafetyViolation {
+        rule,
+        operation: operation(),
+    })
+}
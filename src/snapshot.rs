@@ -0,0 +1,108 @@
+//! Plain copies of `STLink`, `DeviceInfo` and `Frequencies` with decoded
+//! fields, serializable with the `serde` feature.
+
+use crate::err;
+use crate::{
+    DebugConnectMode, DebugPort, DebugResetMode, DebugSpeed, DeviceInfo, Frequencies, STLink,
+};
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrequenciesSnapshot {
+    /// Supported JTAG frequencies in kHz.
+    pub jtag: Vec<u32>,
+    /// Supported SWD frequencies in kHz.
+    pub swd: Vec<u32>,
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct STLinkSnapshot {
+    pub board: String,
+    pub serial_number: String,
+    pub firmware_version: String,
+    pub index: i32,
+    pub debug_port: DebugPort,
+    pub connection_mode: DebugConnectMode,
+    pub reset_mode: DebugResetMode,
+    pub access_port_count: i32,
+    pub access_port: i32,
+    /// Frequency in kHz.
+    pub frequency: i32,
+    pub frequencies: FrequenciesSnapshot,
+    pub old_firmware: bool,
+    pub bridge: bool,
+    pub shared: bool,
+    pub debug_sleep: bool,
+    pub speed: DebugSpeed,
+    /// Target voltage in V, `None` when it cannot be parsed.
+    pub target_voltage: Option<f32>,
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceInfoSnapshot {
+    pub name: String,
+    pub series: String,
+    pub category: String,
+    pub cpu: String,
+    pub description: String,
+    pub revision_id: String,
+    pub board: String,
+    pub device_id: i32,
+    pub flash_size: i32,
+    pub bootloader_version: i32,
+}
+
+impl Frequencies {
+    pub fn snapshot(&self) -> FrequenciesSnapshot {
+        FrequenciesSnapshot {
+            jtag: self.jtag_frequencies(),
+            swd: self.swd_frequencies(),
+        }
+    }
+}
+
+impl STLink {
+    pub fn snapshot(&self) -> Result<STLinkSnapshot, err::Error> {
+        Ok(STLinkSnapshot {
+            board: self.board()?,
+            serial_number: self.serial_number()?,
+            firmware_version: self.firmware_version()?,
+            index: self.index(),
+            debug_port: self.debug_port(),
+            connection_mode: self.connection_mode(),
+            reset_mode: self.reset_mode(),
+            access_port_count: self.access_port_count(),
+            access_port: self.access_port(),
+            frequency: self.frequency(),
+            frequencies: self.frequencies().snapshot(),
+            old_firmware: self.old_firmware(),
+            bridge: self.bridge(),
+            shared: self.shared(),
+            debug_sleep: self.debug_sleep(),
+            speed: match self.speed() {
+                0 => DebugSpeed::Reliable,
+                _ => DebugSpeed::Fast,
+            },
+            target_voltage: self.target_voltage().ok(),
+        })
+    }
+}
+
+impl DeviceInfo {
+    pub fn snapshot(&self) -> Result<DeviceInfoSnapshot, err::Error> {
+        Ok(DeviceInfoSnapshot {
+            name: self.name()?,
+            series: self.series()?,
+            category: self.category()?,
+            cpu: self.cpu()?,
+            description: self.description()?,
+            revision_id: self.revision_id()?,
+            board: self.board()?,
+            device_id: self.device_id(),
+            flash_size: self.flash_size(),
+            bootloader_version: self.bootloader_version(),
+        })
+    }
+}
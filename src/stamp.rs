@@ -0,0 +1,359 @@
+use crate::checksum::{Checksum, ChecksumAlgorithm};
+use crate::err;
+use crate::image::Image;
+use crate::STM32CubeProg;
+
+/// Address of the 96-bit unique device ID for the given device id.
+pub fn uid_address(device_id: i32) -> Option<u32> {
+    match device_id {
+        // STM32F2, STM32F4
+        0x411 | 0x413 | 0x419 | 0x421 | 0x423 | 0x431 | 0x433 | 0x434 | 0x441 | 0x458 | 0x463 => {
+            Some(0x1FFF7A10)
+        }
+        // STM32F7
+        0x449 | 0x451 => Some(0x1FF0F420),
+        0x452 => Some(0x1FF07A10),
+        // STM32L4, STM32G4, STM32G0, STM32WB
+        0x415 | 0x435 | 0x461 | 0x462 | 0x464 | 0x470 | 0x471 | 0x468 | 0x469 | 0x479 | 0x456
+        | 0x460 | 0x466 | 0x467 | 0x494 | 0x495 | 0x496 => Some(0x1FFF7590),
+        // STM32F0, STM32F3
+        0x440 | 0x442 | 0x444 | 0x445 | 0x448 | 0x422 | 0x432 | 0x438 | 0x439 | 0x446 => {
+            Some(0x1FFFF7AC)
+        }
+        // STM32F1
+        0x410 | 0x412 | 0x414 | 0x418 | 0x420 | 0x428 | 0x430 => Some(0x1FFFF7E8),
+        // STM32H7
+        0x450 | 0x483 => Some(0x1FF1E800),
+        // STM32H7A3, STM32H7B0, STM32H7B3
+        0x480 => Some(0x08FFF800),
+        // STM32L0, STM32L1 category 1 and 2
+        0x417 | 0x425 | 0x447 | 0x457 | 0x416 | 0x429 => Some(0x1FF80050),
+        // STM32L1 category 3 to 6
+        0x427 | 0x436 | 0x437 => Some(0x1FF800D0),
+        // STM32U5
+        0x455 | 0x476 | 0x481 | 0x482 => Some(0x0BFA0700),
+        _ => None,
+    }
+}
+
+/// Where a stamp is written in the image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StampLocation {
+    Address(u32),
+    /// ELF symbol, the value must not be larger than the symbol.
+    Symbol(String),
+}
+
+impl std::fmt::Display for StampLocation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            StampLocation::Address(address) => write!(f, "0x{:08X}", address),
+            StampLocation::Symbol(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// Target information available to stamp callbacks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StampContext {
+    pub device_id: i32,
+    /// Unique device ID, `None` for unknown families.
+    pub uid: Option<[u8; 12]>,
+}
+
+pub type StampCallback = Box<dyn Fn(&StampContext) -> Result<Vec<u8>, err::Error>>;
+
+pub enum StampSource {
+    Bytes(Vec<u8>),
+    /// Decimal counter stored in a text file, written on `size` little-endian
+    /// bytes. `program_stamped` reserves the value before programming, so
+    /// concurrent programmers never use the same one and a failed programming
+    /// skips it.
+    Counter {
+        path: std::path::PathBuf,
+        size: usize,
+    },
+    /// Seconds since the Unix epoch on `size` little-endian bytes.
+    Timestamp {
+        size: usize,
+    },
+    /// The 12 bytes of the unique device ID.
+    Uid,
+    /// CRC-32 of the unique device ID, on 4 little-endian bytes.
+    UidCrc32,
+    Callback(StampCallback),
+}
+
+impl std::fmt::Debug for StampSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            StampSource::Bytes(bytes) => write!(f, "Bytes({:02X?})", bytes),
+            StampSource::Counter { path, size } => {
+                write!(f, "Counter({}, {} bytes)", path.display(), size)
+            }
+            StampSource::Timestamp { size } => write!(f, "Timestamp({} bytes)", size),
+            StampSource::Uid => write!(f, "Uid"),
+            StampSource::UidCrc32 => write!(f, "UidCrc32"),
+            StampSource::Callback(_) => write!(f, "Callback"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Stamp {
+    pub location: StampLocation,
+    pub source: StampSource,
+}
+
+impl Stamp {
+    pub fn new(location: StampLocation, source: StampSource) -> Self {
+        Stamp { location, source }
+    }
+}
+
+/// Value written by a stamp.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StampRecord {
+    pub location: String,
+    pub address: u32,
+    pub value: Vec<u8>,
+}
+
+impl std::fmt::Display for StampRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} at 0x{:08X}: {:02X?}",
+            self.location, self.address, self.value
+        )
+    }
+}
+
+fn read_counter(path: &std::path::Path) -> Result<u64, err::Error> {
+    std::fs::read_to_string(path)?
+        .trim()
+        .parse()
+        .map_err(|_| err::Error::InvalidConfig(format!("{} is not a counter", path.display())))
+}
+
+/// How long to wait for another process to release a counter.
+const COUNTER_LOCK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Lock file of a counter, removed when dropped.
+struct CounterLock {
+    path: std::path::PathBuf,
+}
+
+impl CounterLock {
+    fn acquire(counter: &std::path::Path) -> Result<Self, err::Error> {
+        let mut path = counter.as_os_str().to_owned();
+        path.push(".lock");
+        let path = std::path::PathBuf::from(path);
+
+        let start = std::time::Instant::now();
+        loop {
+            match std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(_) => return Ok(CounterLock { path }),
+                Err(error) if error.kind() == std::io::ErrorKind::AlreadyExists => {
+                    if start.elapsed() > COUNTER_LOCK_TIMEOUT {
+                        return Err(err::Error::InvalidConfig(format!(
+                            "{} is locked, remove {} if no programmer is running",
+                            counter.display(),
+                            path.display()
+                        )));
+                    }
+                    std::thread::sleep(std::time::Duration::from_millis(10));
+                }
+                Err(error) => return Err(error.into()),
+            }
+        }
+    }
+}
+
+impl Drop for CounterLock {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Take the current value of a counter and store the next one.
+fn reserve_counter(path: &std::path::Path) -> Result<u64, err::Error> {
+    let _lock = CounterLock::acquire(path)?;
+    let value = read_counter(path)?;
+    std::fs::write(path, format!("{}\n", value + 1))?;
+    Ok(value)
+}
+
+/// `value` on `size` little-endian bytes.
+fn little_endian(value: u64, size: usize) -> Result<Vec<u8>, err::Error> {
+    let bytes = value.to_le_bytes();
+    if size > bytes.len() || bytes[size..].iter().any(|&byte| byte != 0) {
+        return Err(err::Error::InvalidConfig(format!(
+            "{} does not fit in {} bytes",
+            value, size
+        )));
+    }
+    Ok(bytes[..size].to_vec())
+}
+
+impl STM32CubeProg {
+    pub fn unique_id(&self) -> Result<[u8; 12], err::Error> {
+        let address = uid_address(self.device_info()?.device_id())
+            .ok_or(err::CubeProgrammerError::UnsupportedOperation)?;
+        let mut uid = [0; 12];
+        self.read_raw(address, &mut uid)?;
+        Ok(uid)
+    }
+
+    fn stamp_context(&self) -> Result<StampContext, err::Error> {
+        let device_id = self.device_info()?.device_id();
+        let uid = match uid_address(device_id) {
+            Some(_) => Some(self.unique_id()?),
+            None => None,
+        };
+        Ok(StampContext { device_id, uid })
+    }
+
+    /// Patch the values of `stamps` into `image`. Counters are not
+    /// incremented.
+    pub fn stamp(
+        &self,
+        image: &mut Image,
+        stamps: &[Stamp],
+    ) -> Result<Vec<StampRecord>, err::Error> {
+        self.stamp_counters(image, stamps, false)
+    }
+
+    fn stamp_counters(
+        &self,
+        image: &mut Image,
+        stamps: &[Stamp],
+        reserve: bool,
+    ) -> Result<Vec<StampRecord>, err::Error> {
+        // Only read from the target when a stamp needs it
+        let needs_context = stamps.iter().any(|stamp| {
+            matches!(
+                stamp.source,
+                StampSource::Uid | StampSource::UidCrc32 | StampSource::Callback(_)
+            )
+        });
+        let context = if needs_context {
+            Some(self.stamp_context()?)
+        } else {
+            None
+        };
+        let mut records = Vec::new();
+
+        for stamp in stamps {
+            let value = match &stamp.source {
+                StampSource::Bytes(bytes) => bytes.clone(),
+                StampSource::Counter { path, size } if reserve => {
+                    little_endian(reserve_counter(path)?, *size)?
+                }
+                StampSource::Counter { path, size } => little_endian(read_counter(path)?, *size)?,
+                StampSource::Timestamp { size } => {
+                    let now = std::time::SystemTime::now()
+                        .duration_since(std::time::UNIX_EPOCH)
+                        .map_err(|error| err::Error::InvalidConfig(error.to_string()))?;
+                    little_endian(now.as_secs(), *size)?
+                }
+                StampSource::Uid | StampSource::UidCrc32 => {
+                    let uid = context
+                        .as_ref()
+                        .and_then(|context| context.uid)
+                        .ok_or(err::CubeProgrammerError::UnsupportedOperation)?;
+                    match stamp.source {
+                        StampSource::Uid => uid.to_vec(),
+                        _ => Checksum::compute(ChecksumAlgorithm::Crc32, &uid)?
+                            .to_le_bytes()
+                            .to_vec(),
+                    }
+                }
+                StampSource::Callback(callback) => {
+                    callback(context.as_ref().expect("context of callback stamps"))?
+                }
+            };
+
+            let address = match &stamp.location {
+                StampLocation::Address(address) => *address,
+                StampLocation::Symbol(name) => {
+                    let symbol = image
+                        .symbol(name)
+                        .ok_or_else(|| err::Error::UnknownSymbol(name.clone()))?;
+                    if symbol.size > 0 && value.len() as u64 > u64::from(symbol.size) {
+                        return Err(err::Error::OutOfRange {
+                            address: symbol.address,
+                            size: std::convert::TryInto::try_into(value.len())?,
+                        });
+                    }
+                    symbol.address
+                }
+            };
+
+            image.patch(address, &value)?;
+            records.push(StampRecord {
+                location: stamp.location.to_string(),
+                address,
+                value,
+            });
+        }
+
+        Ok(records)
+    }
+
+    /// Stamp `image` and program it. The values of the counters used are
+    /// reserved first, except in dry-run mode.
+    pub fn program_stamped(
+        &self,
+        image: &mut Image,
+        stamps: &[Stamp],
+        verify: bool,
+    ) -> Result<Vec<StampRecord>, err::Error> {
+        let records = self.stamp_counters(image, stamps, !self.is_dry_run())?;
+        self.download_image(image, Some(false), Some(verify))?;
+        Ok(records)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn h7_uid_addresses() {
+        assert_eq!(uid_address(0x450), Some(0x1FF1E800));
+        assert_eq!(uid_address(0x483), Some(0x1FF1E800));
+        assert_eq!(uid_address(0x480), Some(0x08FFF800));
+    }
+
+    #[test]
+    fn concurrent_reservations_get_distinct_values() {
+        let path = std::env::temp_dir().join(format!("stamp-reserve-{}", std::process::id()));
+        std::fs::write(&path, "100\n").unwrap();
+
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let path = path.clone();
+                std::thread::spawn(move || {
+                    (0..10)
+                        .map(|_| reserve_counter(&path).unwrap())
+                        .collect::<Vec<u64>>()
+                })
+            })
+            .collect();
+        let mut values: Vec<u64> = threads
+            .into_iter()
+            .flat_map(|thread| thread.join().unwrap())
+            .collect();
+        values.sort_unstable();
+
+        assert_eq!(values, (100..180).collect::<Vec<u64>>());
+        assert_eq!(read_counter(&path).unwrap(), 180);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
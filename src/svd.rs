@@ -0,0 +1,572 @@
+//! Peripheral registers described by a CMSIS-SVD file, accessed by paths
+//! such as `RCC.CR` or `RCC.CR.HSEON`.
+
+use crate::err;
+use crate::memory::TransferOptions;
+use crate::STM32CubeProg;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Field {
+    pub name: String,
+    pub description: String,
+    pub bit_offset: u32,
+    pub bit_width: u32,
+    /// Documented values and their name.
+    pub values: Vec<(u32, String)>,
+}
+
+impl Field {
+    pub fn mask(&self) -> u32 {
+        let bits = if self.bit_width >= 32 {
+            u32::MAX
+        } else {
+            (1 << self.bit_width) - 1
+        };
+        bits.checked_shl(self.bit_offset).unwrap_or(0)
+    }
+
+    /// First bit after the field.
+    fn end(&self) -> u64 {
+        u64::from(self.bit_offset) + u64::from(self.bit_width)
+    }
+
+    pub fn extract(&self, register: u32) -> u32 {
+        (register & self.mask()) >> self.bit_offset
+    }
+
+    pub fn value_name(&self, value: u32) -> Option<&str> {
+        self.values
+            .iter()
+            .find(|(candidate, _)| *candidate == value)
+            .map(|(_, name)| name.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Register {
+    pub name: String,
+    pub description: String,
+    pub address_offset: u32,
+    /// Size in bits.
+    pub size: u32,
+    pub access: Option<String>,
+    pub reset_value: u32,
+    /// Side effect of reading the register or one of its fields, such as
+    /// `clear` or `modify`.
+    pub read_action: Option<String>,
+    pub fields: Vec<Field>,
+}
+
+impl Register {
+    pub fn field(&self, name: &str) -> Option<&Field> {
+        self.fields
+            .iter()
+            .find(|field| field.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn is_readable(&self) -> bool {
+        self.access.as_deref() != Some("write-only")
+    }
+
+    /// Whether reading the register changes the state of the peripheral.
+    pub fn has_read_action(&self) -> bool {
+        self.read_action.is_some()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Peripheral {
+    pub name: String,
+    pub description: String,
+    pub base_address: u32,
+    pub registers: Vec<Register>,
+}
+
+impl Peripheral {
+    pub fn register(&self, name: &str) -> Option<&Register> {
+        self.registers
+            .iter()
+            .find(|register| register.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn register_address(&self, register: &Register) -> Result<u32, err::Error> {
+        self.base_address
+            .checked_add(register.address_offset)
+            .ok_or_else(|| {
+                invalid(format!(
+                    "{}.{} is outside of the address space",
+                    self.name, register.name
+                ))
+            })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Device {
+    pub name: String,
+    pub peripherals: Vec<Peripheral>,
+}
+
+fn invalid(message: String) -> err::Error {
+    err::Error::InvalidSvd(message)
+}
+
+fn child<'a, 'input>(
+    node: roxmltree::Node<'a, 'input>,
+    name: &str,
+) -> Option<roxmltree::Node<'a, 'input>> {
+    node.children().find(|child| child.has_tag_name(name))
+}
+
+fn text<'a>(node: roxmltree::Node<'a, '_>, name: &str) -> Option<&'a str> {
+    child(node, name)
+        .and_then(|child| child.text())
+        .map(str::trim)
+}
+
+/// Decimal, `0x` hexadecimal or `#` binary number.
+fn parse_number(text: &str) -> Option<u32> {
+    let text = text.trim();
+    if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        u32::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = text.strip_prefix('#') {
+        u32::from_str_radix(binary, 2).ok()
+    } else {
+        text.parse().ok()
+    }
+}
+
+fn number(node: roxmltree::Node, name: &str) -> Result<Option<u32>, err::Error> {
+    match text(node, name) {
+        Some(value) => parse_number(value)
+            .map(Some)
+            .ok_or_else(|| invalid(format!("invalid {} {}", name, value))),
+        None => Ok(None),
+    }
+}
+
+/// Names and address increments of an element, expanded when it is an
+/// array declared with `dim`.
+fn instances(node: roxmltree::Node) -> Result<Vec<(String, u32)>, err::Error> {
+    let name = text(node, "name").ok_or_else(|| invalid(String::from("missing name")))?;
+    let dim = match number(node, "dim")? {
+        Some(dim) => dim,
+        None => return Ok(vec![(name.to_string(), 0)]),
+    };
+    let increment = number(node, "dimIncrement")?.unwrap_or(0);
+
+    let indexes: Vec<String> = match text(node, "dimIndex") {
+        Some(range) if range.contains('-') && !range.contains(',') => {
+            let (start, end) = range.split_once('-').unwrap_or((range, range));
+            match (start.parse::<u32>(), end.parse::<u32>()) {
+                (Ok(start), Ok(end)) => (start..=end).map(|index| index.to_string()).collect(),
+                _ => return Err(invalid(format!("invalid dimIndex {}", range))),
+            }
+        }
+        Some(list) => list
+            .split(',')
+            .map(|index| index.trim().to_string())
+            .collect(),
+        None => (0..dim).map(|index| index.to_string()).collect(),
+    };
+
+    Ok(indexes
+        .iter()
+        .enumerate()
+        .map(|(position, index)| {
+            let name = name.replace("[%s]", index).replace("%s", index);
+            (name, position as u32 * increment)
+        })
+        .collect())
+}
+
+fn parse_field(node: roxmltree::Node) -> Result<Vec<Field>, err::Error> {
+    let (bit_offset, bit_width) = match (
+        number(node, "bitOffset")?,
+        number(node, "bitWidth")?,
+        number(node, "lsb")?,
+        number(node, "msb")?,
+        text(node, "bitRange"),
+    ) {
+        (Some(offset), width, _, _, _) => (offset, width.unwrap_or(1)),
+        (None, _, Some(lsb), Some(msb), _) if msb >= lsb => (lsb, msb - lsb + 1),
+        (None, _, _, _, Some(range)) => {
+            let bounds = range
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .split_once(':')
+                .and_then(|(msb, lsb)| Some((msb.parse::<u32>().ok()?, lsb.parse::<u32>().ok()?)))
+                .filter(|(msb, lsb)| msb >= lsb);
+            match bounds {
+                Some((msb, lsb)) => (lsb, msb - lsb + 1),
+                None => return Err(invalid(format!("invalid bitRange {}", range))),
+            }
+        }
+        _ => return Err(invalid(String::from("field without bit position"))),
+    };
+
+    let mut values = Vec::new();
+    for enumeration in node
+        .children()
+        .filter(|child| child.has_tag_name("enumeratedValues"))
+    {
+        for value in enumeration
+            .children()
+            .filter(|child| child.has_tag_name("enumeratedValue"))
+        {
+            // Values with don't care bits are not listed
+            if let (Some(name), Some(Some(value))) =
+                (text(value, "name"), text(value, "value").map(parse_number))
+            {
+                values.push((value, name.to_string()));
+            }
+        }
+    }
+
+    let description = text(node, "description").unwrap_or("").to_string();
+    Ok(instances(node)?
+        .into_iter()
+        .map(|(name, increment)| Field {
+            name,
+            description: description.clone(),
+            bit_offset: bit_offset + increment,
+            bit_width,
+            values: values.clone(),
+        })
+        .collect())
+}
+
+fn parse_registers(
+    node: roxmltree::Node,
+    base_offset: u32,
+    prefix: &str,
+    size: u32,
+    registers: &mut Vec<Register>,
+) -> Result<(), err::Error> {
+    for element in node.children().filter(|child| child.is_element()) {
+        let is_cluster = element.has_tag_name("cluster");
+        if !is_cluster && !element.has_tag_name("register") {
+            continue;
+        }
+        let offset = base_offset + number(element, "addressOffset")?.unwrap_or(0);
+
+        for (name, increment) in instances(element)? {
+            let name = format!("{}{}", prefix, name);
+            if is_cluster {
+                let prefix = format!("{}_", name);
+                parse_registers(element, offset + increment, &prefix, size, registers)?;
+                continue;
+            }
+
+            let size = number(element, "size")?.unwrap_or(size);
+            let mut read_action = text(element, "readAction").map(str::to_string);
+            let mut fields = Vec::new();
+            if let Some(list) = child(element, "fields") {
+                for field in list.children().filter(|child| child.has_tag_name("field")) {
+                    read_action =
+                        read_action.or_else(|| text(field, "readAction").map(str::to_string));
+                    fields.extend(parse_field(field)?);
+                }
+            }
+            if let Some(field) = fields.iter().find(|field| field.end() > u64::from(size)) {
+                return Err(invalid(format!(
+                    "field {} of {} does not fit in {} bits",
+                    field.name, name, size
+                )));
+            }
+            registers.push(Register {
+                name,
+                description: text(element, "description").unwrap_or("").to_string(),
+                address_offset: offset + increment,
+                size,
+                access: text(element, "access").map(str::to_string),
+                reset_value: number(element, "resetValue")?.unwrap_or(0),
+                read_action,
+                fields,
+            });
+        }
+    }
+    Ok(())
+}
+
+impl Device {
+    pub fn load<P: AsRef<std::path::Path>>(path: P) -> Result<Self, err::Error> {
+        std::fs::read_to_string(path)?.parse()
+    }
+
+    pub fn peripheral(&self, name: &str) -> Option<&Peripheral> {
+        self.peripherals
+            .iter()
+            .find(|peripheral| peripheral.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Peripheral, register and optional field of `PERIPHERAL.REGISTER[.FIELD]`.
+    pub fn resolve(
+        &self,
+        path: &str,
+    ) -> Result<(&Peripheral, &Register, Option<&Field>), err::Error> {
+        let unknown = || err::Error::UnknownRegister(path.to_string());
+        let mut parts = path.split('.');
+        let peripheral = parts
+            .next()
+            .and_then(|name| self.peripheral(name))
+            .ok_or_else(unknown)?;
+        let register = parts
+            .next()
+            .and_then(|name| peripheral.register(name))
+            .ok_or_else(unknown)?;
+        let field = match parts.next() {
+            Some(name) => Some(register.field(name).ok_or_else(unknown)?),
+            None => None,
+        };
+        if parts.next().is_some() {
+            return Err(unknown());
+        }
+        Ok((peripheral, register, field))
+    }
+}
+
+impl std::str::FromStr for Device {
+    type Err = err::Error;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        let document =
+            roxmltree::Document::parse(source).map_err(|error| invalid(error.to_string()))?;
+        let root = document.root_element();
+        let size = number(root, "size")?.unwrap_or(32);
+        let nodes: Vec<roxmltree::Node> = child(root, "peripherals")
+            .map(|peripherals| {
+                peripherals
+                    .children()
+                    .filter(|child| child.has_tag_name("peripheral"))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let mut peripherals: Vec<Peripheral> = Vec::new();
+        for node in nodes.iter() {
+            let size = number(*node, "size")?.unwrap_or(size);
+            let mut registers = Vec::new();
+            if let Some(list) = child(*node, "registers") {
+                parse_registers(list, 0, "", size, &mut registers)?;
+            }
+            peripherals.push(Peripheral {
+                name: text(*node, "name").unwrap_or("").to_string(),
+                description: text(*node, "description").unwrap_or("").to_string(),
+                base_address: number(*node, "baseAddress")?.unwrap_or(0),
+                registers,
+            });
+        }
+
+        // Derived peripherals share the registers of their base when they do
+        // not declare their own
+        for (index, node) in nodes.iter().enumerate() {
+            if let Some(base) = node.attribute("derivedFrom") {
+                let base = peripherals
+                    .iter()
+                    .find(|peripheral| peripheral.name == base)
+                    .ok_or_else(|| invalid(format!("unknown peripheral {}", base)))?
+                    .clone();
+                let peripheral = &mut peripherals[index];
+                if peripheral.registers.is_empty() {
+                    peripheral.registers = base.registers;
+                }
+                if peripheral.description.is_empty() {
+                    peripheral.description = base.description;
+                }
+            }
+        }
+
+        Ok(Device {
+            name: text(root, "name").unwrap_or("").to_string(),
+            peripherals,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldDump {
+    pub name: String,
+    pub value: u32,
+    /// Name of the value, when documented.
+    pub meaning: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegisterDump {
+    pub name: String,
+    pub address: u32,
+    /// `None` for write-only registers and registers with a read action,
+    /// which are not read.
+    pub value: Option<u32>,
+    pub read_action: Option<String>,
+    pub fields: Vec<FieldDump>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeripheralDump {
+    pub name: String,
+    pub registers: Vec<RegisterDump>,
+}
+
+impl std::fmt::Display for PeripheralDump {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.name)?;
+        for register in self.registers.iter() {
+            write!(f, "\n  {} (0x{:08X}): ", register.name, register.address)?;
+            match (register.value, &register.read_action) {
+                (Some(value), _) => write!(f, "0x{:08X}", value)?,
+                (None, Some(read_action)) => write!(f, "not read ({} on read)", read_action)?,
+                (None, None) => write!(f, "write-only")?,
+            }
+            for field in register.fields.iter() {
+                write!(f, "\n    {} = {:#x}", field.name, field.value)?;
+                if let Some(meaning) = &field.meaning {
+                    write!(f, " ({})", meaning)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl STM32CubeProg {
+    pub fn load_svd<P: AsRef<std::path::Path>>(&self, path: P) -> Result<(), err::Error> {
+        self.svd.replace(Some(Device::load(path)?));
+        Ok(())
+    }
+
+    pub fn set_svd(&self, device: Option<Device>) {
+        self.svd.replace(device);
+    }
+
+    fn svd_device(&self) -> Result<std::cell::Ref<'_, Device>, err::Error> {
+        std::cell::Ref::filter_map(self.svd.borrow(), Option::as_ref)
+            .map_err(|_| invalid(String::from("no SVD file loaded")))
+    }
+
+    /// Byte width of a register of `size` bits and the options accessing it
+    /// in a single transfer of that width.
+    fn register_access(address: u32, size: u32) -> Result<(usize, TransferOptions), err::Error> {
+        let width = match size {
+            8 | 16 | 32 => size / 8,
+            _ => return Err(invalid(format!("unsupported register size {}", size))),
+        };
+        if !address.is_multiple_of(width) {
+            return Err(err::Error::UnalignedAccess {
+                address,
+                size: width,
+            });
+        }
+        let options = TransferOptions {
+            alignment: width,
+            ..TransferOptions::default()
+        };
+        Ok((width as usize, options))
+    }
+
+    fn read_sized(&self, address: u32, size: u32) -> Result<u32, err::Error> {
+        let (width, options) = Self::register_access(address, size)?;
+        let mut bytes = [0; 4];
+        self.read_chunked(address, &mut bytes[..width], &options, |_, _| {})?;
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    fn write_sized(&self, address: u32, size: u32, value: u32) -> Result<(), err::Error> {
+        let (width, options) = Self::register_access(address, size)?;
+        self.write_chunked(address, &value.to_le_bytes()[..width], &options, |_, _| {})
+    }
+
+    /// Value of a register (`RCC.CR`) or of one of its fields (`RCC.CR.HSEON`).
+    pub fn read_svd_register(&self, path: &str) -> Result<u32, err::Error> {
+        let device = self.svd_device()?;
+        let (peripheral, register, field) = device.resolve(path)?;
+        let value = self.read_sized(peripheral.register_address(register)?, register.size)?;
+        Ok(match field {
+            Some(field) => field.extract(value),
+            None => value,
+        })
+    }
+
+    /// Write a register, or a field with a read-modify-write of its register.
+    /// Fields of registers that are write-only or have a side effect on read
+    /// are refused, the whole register must be written instead.
+    pub fn write_svd_register(&self, path: &str, value: u32) -> Result<(), err::Error> {
+        let device = self.svd_device()?;
+        let (peripheral, register, field) = device.resolve(path)?;
+        let address = peripheral.register_address(register)?;
+
+        let value = match field {
+            Some(field) => {
+                if value > field.mask() >> field.bit_offset {
+                    return Err(err::Error::InvalidConfig(format!(
+                        "{:#x} does not fit the {} bits of {}",
+                        value, field.bit_width, path
+                    )));
+                }
+                if !register.is_readable() {
+                    return Err(err::Error::InvalidConfig(format!(
+                        "{} cannot be read back to write {}, it is write-only",
+                        register.name, path
+                    )));
+                }
+                if let Some(read_action) = register.read_action.as_ref() {
+                    return Err(err::Error::InvalidConfig(format!(
+                        "{} cannot be read back to write {} ({} on read)",
+                        register.name, path, read_action
+                    )));
+                }
+                let current = self.read_sized(address, register.size)?;
+                (current & !field.mask()) | (value << field.bit_offset)
+            }
+            None => value,
+        };
+        self.write_sized(address, register.size, value)
+    }
+
+    /// Read every readable register of `peripheral` and decode its fields.
+    /// Registers whose reading has side effects are listed but not read.
+    pub fn dump_peripheral(&self, peripheral: &str) -> Result<PeripheralDump, err::Error> {
+        let device = self.svd_device()?;
+        let peripheral = device
+            .peripheral(peripheral)
+            .ok_or_else(|| err::Error::UnknownRegister(peripheral.to_string()))?;
+
+        let mut registers = Vec::new();
+        for register in peripheral.registers.iter() {
+            let address = peripheral.register_address(register)?;
+            let value = if register.is_readable() && !register.has_read_action() {
+                Some(self.read_sized(address, register.size)?)
+            } else {
+                None
+            };
+            let fields = match value {
+                Some(value) => register
+                    .fields
+                    .iter()
+                    .map(|field| {
+                        let value = field.extract(value);
+                        FieldDump {
+                            name: field.name.clone(),
+                            value,
+                            meaning: field.value_name(value).map(str::to_string),
+                        }
+                    })
+                    .collect(),
+                None => Vec::new(),
+            };
+            registers.push(RegisterDump {
+                name: register.name.clone(),
+                address,
+                value,
+                read_action: register.read_action.clone(),
+                fields,
+            });
+        }
+
+        Ok(PeripheralDump {
+            name: peripheral.name.clone(),
+            registers,
+        })
+    }
+}
@@ -0,0 +1,275 @@
+use crate::elf::{Elf, ElfSymbol};
+use crate::err;
+use crate::memory::Pod;
+use crate::STM32CubeProg;
+
+/// Symbol table of the firmware running on the target, used to access its
+/// variables by name.
+///
+/// With the `dwarf` feature, the size of symbols missing from the symbol
+/// table is taken from the type of the variable in the debug information.
+#[derive(Debug, Clone)]
+pub struct Symbols {
+    symbols: Vec<ElfSymbol>,
+    /// Sizes of the global and file-level variables, by name and address.
+    type_sizes: TypeSizes,
+}
+
+impl Symbols {
+    pub fn load<P: AsRef<std::path::Path>>(path: P) -> Result<Self, err::Error> {
+        Self::from_elf(&Elf::load(path)?)
+    }
+
+    pub fn from_elf(elf: &Elf) -> Result<Self, err::Error> {
+        Ok(Symbols {
+            symbols: elf.symbols.clone(),
+            type_sizes: type_sizes(elf)?,
+        })
+    }
+
+    /// The symbol called `name`, with its size completed from the debug
+    /// information when needed.
+    pub fn symbol(&self, name: &str) -> Result<ElfSymbol, err::Error> {
+        let mut symbol = self
+            .symbols
+            .iter()
+            .find(|symbol| symbol.name == name)
+            .cloned()
+            .ok_or_else(|| err::Error::UnknownSymbol(name.to_string()))?;
+        if symbol.size == 0 {
+            symbol.size = self.variable_size(&symbol).unwrap_or(0);
+        }
+        Ok(symbol)
+    }
+
+    /// Size of the type of the variable `name` in the debug information,
+    /// when it is located at the address of the symbol `name`.
+    pub fn type_size(&self, name: &str) -> Option<u32> {
+        self.symbols
+            .iter()
+            .find(|symbol| symbol.name == name)
+            .and_then(|symbol| self.variable_size(symbol))
+    }
+
+    fn variable_size(&self, symbol: &ElfSymbol) -> Option<u32> {
+        self.type_sizes
+            .get(&(symbol.name.clone(), symbol.address))
+            .cloned()
+    }
+}
+
+type TypeSizes = std::collections::HashMap<(String, u32), u32>;
+
+#[cfg(not(feature = "dwarf"))]
+fn type_sizes(_elf: &Elf) -> Result<TypeSizes, err::Error> {
+    Ok(std::collections::HashMap::new())
+}
+
+#[cfg(feature = "dwarf")]
+fn type_sizes(elf: &Elf) -> Result<TypeSizes, err::Error> {
+    dwarf::type_sizes(elf)
+        .map_err(|error| err::Error::InvalidImage(format!("debug information: {}", error)))
+}
+
+#[cfg(feature = "dwarf")]
+mod dwarf {
+    use crate::elf::Elf;
+
+    type Reader<'a> = gimli::EndianSlice<'a, gimli::LittleEndian>;
+
+    /// Byte size of the variables declared at the top level of a compile
+    /// unit, by name and by the address of their location. Variables local
+    /// to functions are left out, even static ones, as they do not match the
+    /// symbols of the same name.
+    pub fn type_sizes(elf: &Elf) -> gimli::Result<super::TypeSizes> {
+        let dwarf = gimli::Dwarf::load(|section: gimli::SectionId| -> gimli::Result<Reader> {
+            Ok(gimli::EndianSlice::new(
+                elf.section_data(section.name()).unwrap_or(&[]),
+                gimli::LittleEndian,
+            ))
+        })?;
+
+        let mut sizes = std::collections::HashMap::new();
+        let mut headers = dwarf.units();
+        while let Some(header) = headers.next()? {
+            let unit = dwarf.unit(header)?;
+            let mut entries = unit.entries();
+            let mut depth = 0;
+            while let Some((delta, entry)) = entries.next_dfs()? {
+                depth += delta;
+                if depth != 1 || entry.tag() != gimli::DW_TAG_variable {
+                    continue;
+                }
+                let address = match location(&dwarf, &unit, entry)? {
+                    Some(address) => address,
+                    None => continue,
+                };
+
+                // Definitions of declared variables refer to the declaration
+                // for their name and type
+                let declaration = match entry.attr_value(gimli::DW_AT_specification)? {
+                    Some(gimli::AttributeValue::UnitRef(offset)) => Some(unit.entry(offset)?),
+                    _ => None,
+                };
+                let attribute = |name| match entry.attr_value(name)? {
+                    Some(value) => Ok(Some(value)),
+                    None => match &declaration {
+                        Some(declaration) => declaration.attr_value(name),
+                        None => Ok(None),
+                    },
+                };
+
+                let name = match attribute(gimli::DW_AT_name)? {
+                    Some(name) => dwarf
+                        .attr_string(&unit, name)?
+                        .to_string_lossy()
+                        .into_owned(),
+                    None => continue,
+                };
+                if let Some(gimli::AttributeValue::UnitRef(offset)) = attribute(gimli::DW_AT_type)?
+                {
+                    if let Some(size) = type_size(&unit, offset, 0)? {
+                        sizes.entry((name, address)).or_insert(size as u32);
+                    }
+                }
+            }
+        }
+
+        Ok(sizes)
+    }
+
+    /// Static address of a variable located by a single `DW_OP_addr`.
+    fn location(
+        dwarf: &gimli::Dwarf<Reader>,
+        unit: &gimli::Unit<Reader>,
+        entry: &gimli::DebuggingInformationEntry<Reader>,
+    ) -> gimli::Result<Option<u32>> {
+        let expression = match entry.attr_value(gimli::DW_AT_location)? {
+            Some(gimli::AttributeValue::Exprloc(expression)) => expression,
+            _ => return Ok(None),
+        };
+
+        let mut operations = expression.operations(unit.encoding());
+        let address = match operations.next()? {
+            Some(gimli::Operation::Address { address }) => address,
+            Some(gimli::Operation::AddressIndex { index }) => dwarf.address(unit, index)?,
+            _ => return Ok(None),
+        };
+        if operations.next()?.is_some() {
+            return Ok(None);
+        }
+        Ok(std::convert::TryInto::try_into(address).ok())
+    }
+
+    fn type_size(
+        unit: &gimli::Unit<Reader>,
+        offset: gimli::UnitOffset,
+        depth: u32,
+    ) -> gimli::Result<Option<u64>> {
+        // Guard against malformed, cyclic type chains
+        if depth > 32 {
+            return Ok(None);
+        }
+
+        let entry = unit.entry(offset)?;
+        if let Some(size) = entry
+            .attr_value(gimli::DW_AT_byte_size)?
+            .and_then(|size| size.udata_value())
+        {
+            return Ok(Some(size));
+        }
+
+        let inner = match entry.attr_value(gimli::DW_AT_type)? {
+            Some(gimli::AttributeValue::UnitRef(inner)) => inner,
+            _ => return Ok(None),
+        };
+        match entry.tag() {
+            gimli::DW_TAG_typedef
+            | gimli::DW_TAG_const_type
+            | gimli::DW_TAG_volatile_type
+            | gimli::DW_TAG_atomic_type => type_size(unit, inner, depth + 1),
+            gimli::DW_TAG_array_type => {
+                let element = match type_size(unit, inner, depth + 1)? {
+                    Some(element) => element,
+                    None => return Ok(None),
+                };
+                let mut count = 1;
+                let mut tree = unit.entries_tree(Some(offset))?;
+                let mut children = tree.root()?.children();
+                while let Some(child) = children.next()? {
+                    let child = child.entry();
+                    if child.tag() != gimli::DW_TAG_subrange_type {
+                        continue;
+                    }
+                    let length = match child.attr_value(gimli::DW_AT_count)? {
+                        Some(length) => length.udata_value(),
+                        None => child
+                            .attr_value(gimli::DW_AT_upper_bound)?
+                            .and_then(|bound| bound.udata_value())
+                            .map(|bound| bound + 1),
+                    };
+                    match length {
+                        Some(length) => count *= length,
+                        None => return Ok(None),
+                    }
+                }
+                Ok(Some(element * count))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+impl STM32CubeProg {
+    /// Use the symbols of `path` for `read_symbol` and `write_symbol`.
+    pub fn load_symbols<P: AsRef<std::path::Path>>(&self, path: P) -> Result<(), err::Error> {
+        self.symbols.replace(Some(Symbols::load(path)?));
+        Ok(())
+    }
+
+    pub fn set_symbols(&self, symbols: Option<Symbols>) {
+        self.symbols.replace(symbols);
+    }
+
+    fn loaded_symbol(&self, name: &str) -> Result<ElfSymbol, err::Error> {
+        match self.symbols.borrow().as_ref() {
+            Some(symbols) => symbols.symbol(name),
+            None => Err(err::Error::UnknownSymbol(name.to_string())),
+        }
+    }
+
+    /// Address of `name`, checking that `size` bytes fit in the symbol.
+    fn symbol_address(&self, name: &str, size: usize) -> Result<u32, err::Error> {
+        let symbol = self.loaded_symbol(name)?;
+        let size = std::convert::TryInto::try_into(size)?;
+        if size > symbol.size {
+            return Err(err::Error::OutOfRange {
+                address: symbol.address,
+                size,
+            });
+        }
+        Ok(symbol.address)
+    }
+
+    /// Read the whole variable `name`.
+    pub fn read_symbol_bytes(&self, name: &str) -> Result<Vec<u8>, err::Error> {
+        let symbol = self.loaded_symbol(name)?;
+        let mut data = vec![0; symbol.size as usize];
+        self.read_raw(symbol.address, &mut data)?;
+        Ok(data)
+    }
+
+    pub fn write_symbol_bytes(&self, name: &str, data: &[u8]) -> Result<(), err::Error> {
+        let address = self.symbol_address(name, data.len())?;
+        self.write_raw(address, data)
+    }
+
+    /// Read the variable `name`, which must be at least as large as `T`.
+    pub fn read_symbol<T: Pod>(&self, name: &str) -> Result<T, err::Error> {
+        self.read(self.symbol_address(name, std::mem::size_of::<T>())?)
+    }
+
+    pub fn write_symbol<T: Pod>(&self, name: &str, value: &T) -> Result<(), err::Error> {
+        self.write(self.symbol_address(name, std::mem::size_of::<T>())?, value)
+    }
+}
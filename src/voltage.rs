@@ -0,0 +1,169 @@
+use crate::err;
+use crate::{STLink, STM32CubeProg};
+
+/// Target voltage limits checked before connecting.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct VoltageThresholds {
+    /// Below this voltage the target is considered unpowered.
+    pub unpowered: f32,
+    /// Lowest voltage the target is allowed to run at.
+    pub minimum: f32,
+}
+
+impl Default for VoltageThresholds {
+    fn default() -> Self {
+        VoltageThresholds {
+            unpowered: 0.5,
+            minimum: 1.7,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum VoltageStatus {
+    Powered,
+    Undervoltage,
+    Unpowered,
+}
+
+impl VoltageThresholds {
+    pub fn status(&self, voltage: f32) -> VoltageStatus {
+        if voltage < self.unpowered {
+            VoltageStatus::Unpowered
+        } else if voltage < self.minimum {
+            VoltageStatus::Undervoltage
+        } else {
+            VoltageStatus::Powered
+        }
+    }
+
+    pub fn check(&self, voltage: f32) -> Result<f32, err::Error> {
+        match self.status(voltage) {
+            VoltageStatus::Powered => Ok(voltage),
+            VoltageStatus::Undervoltage => Err(err::Error::TargetUndervoltage {
+                voltage,
+                minimum: self.minimum,
+            }),
+            VoltageStatus::Unpowered => Err(err::Error::TargetUnpowered(voltage)),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct VoltageEvent {
+    pub voltage: f32,
+    pub previous: Option<f32>,
+    pub status: VoltageStatus,
+}
+
+/// Periodic target voltage sampling done by `monitor_target_voltage`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct VoltageMonitor {
+    pub interval: std::time::Duration,
+    /// Smallest voltage variation reported, status changes are always reported.
+    pub hysteresis: f32,
+    pub thresholds: VoltageThresholds,
+}
+
+impl Default for VoltageMonitor {
+    fn default() -> Self {
+        VoltageMonitor {
+            interval: std::time::Duration::from_millis(500),
+            hysteresis: 0.05,
+            thresholds: VoltageThresholds::default(),
+        }
+    }
+}
+
+impl STM32CubeProg {
+    /// Current target voltage measured by `stlink`.
+    ///
+    /// The probes are enumerated without connecting to them, and the interface
+    /// list is kept until `disconnect`, so that an open connection is not
+    /// disturbed.
+    pub fn read_target_voltage(&self, stlink: &STLink) -> Result<f32, err::Error> {
+        let serial_number = stlink.serial_number()?;
+        for probe in self.stlink_list(*self.vtable.get_stlink_enumeration_list, true)? {
+            if probe.serial_number()? == serial_number {
+                return probe.target_voltage();
+            }
+        }
+
+        Err(err::CubeProgrammerError::NoDeviceFound.into())
+    }
+
+    /// Sample the target voltage of `stlink` every `monitor.interval` and call
+    /// `callback` on the first sample and on every change, until it returns
+    /// `false`.
+    pub fn monitor_target_voltage<F>(
+        &self,
+        stlink: &STLink,
+        monitor: &VoltageMonitor,
+        mut callback: F,
+    ) -> Result<(), err::Error>
+    where
+        F: FnMut(VoltageEvent) -> bool,
+    {
+        let mut previous: Option<VoltageEvent> = None;
+        loop {
+            let voltage = self.read_target_voltage(stlink)?;
+            let status = monitor.thresholds.status(voltage);
+
+            let changed = match previous {
+                Some(event) => {
+                    event.status != status || (voltage - event.voltage).abs() >= monitor.hysteresis
+                }
+                None => true,
+            };
+
+            if changed {
+                let event = VoltageEvent {
+                    voltage,
+                    previous: previous.map(|event| event.voltage),
+                    status,
+                };
+                if !callback(event) {
+                    return Ok(());
+                }
+                previous = Some(event);
+            }
+
+            std::thread::sleep(monitor.interval);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn thresholds_classify_voltages() {
+        let thresholds = VoltageThresholds::default();
+        assert_eq!(thresholds.status(0.0), VoltageStatus::Unpowered);
+        assert_eq!(thresholds.status(0.49), VoltageStatus::Unpowered);
+        assert_eq!(thresholds.status(0.5), VoltageStatus::Undervoltage);
+        assert_eq!(thresholds.status(1.69), VoltageStatus::Undervoltage);
+        assert_eq!(thresholds.status(1.7), VoltageStatus::Powered);
+        assert_eq!(thresholds.status(3.3), VoltageStatus::Powered);
+    }
+
+    #[test]
+    fn thresholds_produce_typed_errors() {
+        let thresholds = VoltageThresholds {
+            unpowered: 1.0,
+            minimum: 3.0,
+        };
+        assert_eq!(thresholds.check(3.3).unwrap(), 3.3);
+        match thresholds.check(2.5) {
+            Err(err::Error::TargetUndervoltage { voltage, minimum }) => {
+                assert_eq!((voltage, minimum), (2.5, 3.0))
+            }
+            result => panic!("unexpected {:?}", result),
+        }
+        match thresholds.check(0.2) {
+            Err(err::Error::TargetUnpowered(voltage)) => assert_eq!(voltage, 0.2),
+            result => panic!("unexpected {:?}", result),
+        }
+    }
+}
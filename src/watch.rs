@@ -0,0 +1,155 @@
+use crate::err;
+use crate::{STLink, STM32CubeProg};
+
+/// USB product IDs of the ST-Link V2, V2-1, V3 and their variants.
+const STLINK_PRODUCT_IDS: [&str; 12] = [
+    "3744", "3748", "374a", "374b", "374d", "374e", "374f", "3752", "3753", "3754", "3755", "3757",
+];
+
+const STMICROELECTRONICS_VENDOR_ID: &str = "0483";
+
+#[derive(Debug, Clone)]
+pub enum ProbeEvent {
+    Attached(Box<STLink>),
+    /// Serial number of the unplugged probe.
+    Detached(String),
+}
+
+impl ProbeEvent {
+    pub fn serial_number(&self) -> Result<String, err::Error> {
+        match self {
+            ProbeEvent::Attached(stlink) => stlink.serial_number(),
+            ProbeEvent::Detached(serial_number) => Ok(serial_number.clone()),
+        }
+    }
+}
+
+/// Probes seen by the last `poll_probes`, keyed by serial number.
+///
+/// On Linux the USB devices listed by sysfs are compared first, and the
+/// probes are only enumerated again through the library when they changed.
+/// Udev is not used: sysfs is read on every poll. Other platforms enumerate
+/// through the library on every poll.
+#[derive(Debug, Clone)]
+pub struct ProbeWatcher {
+    pub interval: std::time::Duration,
+    probes: std::collections::BTreeMap<String, STLink>,
+    usb_devices: Option<Vec<String>>,
+}
+
+impl Default for ProbeWatcher {
+    fn default() -> Self {
+        ProbeWatcher::new(std::time::Duration::from_millis(500))
+    }
+}
+
+impl ProbeWatcher {
+    pub fn new(interval: std::time::Duration) -> Self {
+        ProbeWatcher {
+            interval,
+            probes: std::collections::BTreeMap::new(),
+            usb_devices: None,
+        }
+    }
+
+    /// Probes currently attached.
+    pub fn probes(&self) -> Vec<STLink> {
+        self.probes.values().cloned().collect()
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn usb_devices() -> Option<Vec<String>> {
+    let read = |path: std::path::PathBuf| {
+        std::fs::read_to_string(path)
+            .map(|content| content.trim().to_ascii_lowercase())
+            .unwrap_or_default()
+    };
+
+    let mut devices = Vec::new();
+    for entry in std::fs::read_dir("/sys/bus/usb/devices").ok()? {
+        let path = entry.ok()?.path();
+        if read(path.join("idVendor")) == STMICROELECTRONICS_VENDOR_ID
+            && STLINK_PRODUCT_IDS.contains(&read(path.join("idProduct")).as_str())
+        {
+            devices.push(format!(
+                "{}:{}",
+                path.file_name()?.to_string_lossy(),
+                read(path.join("devnum"))
+            ));
+        }
+    }
+
+    devices.sort();
+    Some(devices)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn usb_devices() -> Option<Vec<String>> {
+    None
+}
+
+impl STM32CubeProg {
+    /// Events since the previous call with the same `watcher`. The first call
+    /// reports every plugged probe as attached.
+    ///
+    /// Probes are enumerated in shared mode, so the ones held by another
+    /// process, or by this session, are reported too. Polling does not tear
+    /// down the open connection: the library releases its interface list
+    /// only once disconnected.
+    pub fn poll_probes(&self, watcher: &mut ProbeWatcher) -> Result<Vec<ProbeEvent>, err::Error> {
+        let usb_devices = usb_devices();
+        if usb_devices.is_some() && usb_devices == watcher.usb_devices {
+            return Ok(Vec::new());
+        }
+
+        let mut probes = std::collections::BTreeMap::new();
+        for stlink in self.stlink_list(*self.vtable.get_stlink_enumeration_list, true)? {
+            probes.insert(stlink.serial_number()?, stlink);
+        }
+
+        // The library may see a new device a little later than the kernel,
+        // enumerate again on the next poll until both agree.
+        watcher.usb_devices = match usb_devices {
+            Some(devices) if devices.len() == probes.len() => Some(devices),
+            _ => None,
+        };
+
+        let mut events: Vec<ProbeEvent> = watcher
+            .probes
+            .keys()
+            .filter(|serial_number| !probes.contains_key(*serial_number))
+            .map(|serial_number| ProbeEvent::Detached(serial_number.clone()))
+            .collect();
+        events.extend(
+            probes
+                .iter()
+                .filter(|(serial_number, _)| !watcher.probes.contains_key(*serial_number))
+                .map(|(_, stlink)| ProbeEvent::Attached(Box::new(stlink.clone()))),
+        );
+
+        watcher.probes = probes;
+        Ok(events)
+    }
+
+    /// Poll every `watcher.interval` and call `callback` on each event, until
+    /// it returns `false`.
+    pub fn watch_probes<F>(
+        &self,
+        watcher: &mut ProbeWatcher,
+        mut callback: F,
+    ) -> Result<(), err::Error>
+    where
+        F: FnMut(ProbeEvent) -> bool,
+    {
+        loop {
+            for event in self.poll_probes(watcher)? {
+                if !callback(event) {
+                    return Ok(());
+                }
+            }
+
+            std::thread::sleep(watcher.interval);
+        }
+    }
+}
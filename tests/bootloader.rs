@@ -0,0 +1,112 @@
+extern crate libloading;
+extern crate stm32cubeprog_rs;
+
+mod stub;
+
+use stm32cubeprog_rs::bootloader::{
+    CanBootloader, CanFilter, CanFilterMode, CanFilterScale, CanIdentifier, I2cBootloader,
+    SpiBootloader, SpiMode, SpiNss,
+};
+use stm32cubeprog_rs::err::Error;
+use stm32cubeprog_rs::STM32CubeProg;
+
+#[test]
+fn spi_parameters_are_passed_to_the_bootloader() {
+    let _lock = stub::lock();
+    let library = stub::library();
+    stub::reset(&library);
+    let stm32prog = STM32CubeProg::new(stub::installation()).unwrap();
+
+    let mut spi = SpiBootloader::default();
+    stm32prog.connect_spi(&spi).unwrap();
+    assert_eq!(
+        stub::bootloader(&library),
+        Some(("spi", vec![375, 7, 0, 0, 0, 0, 1, 0, 1, 1, 1, 1, 1]))
+    );
+
+    spi.set_baudrate(1500);
+    spi.set_mode(SpiMode::Mode3);
+    spi.set_nss(SpiNss::Software);
+    spi.set_crc_polynomial(Some(0x1021));
+    stm32prog.connect_spi(&spi).unwrap();
+    assert_eq!(
+        stub::bootloader(&library),
+        Some(("spi", vec![1500, 0x1021, 0, 1, 1, 1, 1, 0, 1, 1, 0, 1, 1]))
+    );
+}
+
+#[test]
+fn i2c_parameters_are_passed_to_the_bootloader() {
+    let _lock = stub::lock();
+    let library = stub::library();
+    stub::reset(&library);
+    let stm32prog = STM32CubeProg::new(stub::installation()).unwrap();
+
+    let mut i2c = I2cBootloader::new(0x38);
+    i2c.set_digital_filter(Some(5));
+    i2c.set_timings(100, 10);
+    stm32prog.connect_i2c(&i2c).unwrap();
+    assert_eq!(
+        stub::bootloader(&library),
+        Some(("i2c", vec![0x38, 400, 1, 0, 1, 1, 5, 100, 10]))
+    );
+}
+
+#[test]
+fn can_parameters_are_passed_to_the_bootloader() {
+    let _lock = stub::lock();
+    let library = stub::library();
+    stub::reset(&library);
+    let stm32prog = STM32CubeProg::new(stub::installation()).unwrap();
+
+    let mut can = CanBootloader::default();
+    stm32prog.connect_can(&can).unwrap();
+    assert_eq!(
+        stub::bootloader(&library),
+        Some(("can", vec![125, 0, 0, 0, 0, 0, 1, 1, 0]))
+    );
+
+    can.set_bitrate(500);
+    can.set_identifier(CanIdentifier::Extended);
+    can.set_filter(Some(CanFilter {
+        mode: CanFilterMode::List,
+        scale: CanFilterScale::Bits16,
+        bank: 3,
+        fifo: 1,
+    }));
+    stm32prog.connect_can(&can).unwrap();
+    assert_eq!(
+        stub::bootloader(&library),
+        Some(("can", vec![500, 0, 1, 0, 1, 1, 0, 1, 3]))
+    );
+}
+
+#[test]
+fn bootloader_connection_failures_are_reported() {
+    let _lock = stub::lock();
+    let library = stub::library();
+    stub::reset(&library);
+    let stm32prog = STM32CubeProg::new(stub::installation()).unwrap();
+
+    stub::fail_connections(&library, 1);
+    match stm32prog.connect_spi(&SpiBootloader::default()) {
+        Err(Error::CubeProgrammerError(_)) => {}
+        result => panic!("unexpected {:?}", result),
+    }
+    assert_eq!(stub::bootloader(&library), None);
+    stm32prog.connect_spi(&SpiBootloader::default()).unwrap();
+}
+
+#[test]
+fn bridges_are_only_probes_with_a_bridge() {
+    let _lock = stub::lock();
+    let library = stub::library();
+    stub::reset(&library);
+    let stm32prog = STM32CubeProg::new(stub::installation()).unwrap();
+
+    stub::plug_probe(&library, b"PROBE1", false);
+    match stm32prog.discover_bridges() {
+        Err(Error::CubeProgrammerError(_)) => {}
+        result => panic!("unexpected {:?}", result),
+    }
+}
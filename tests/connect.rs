@@ -0,0 +1,235 @@
+extern crate libloading;
+extern crate stm32cubeprog_rs;
+
+mod stub;
+
+use stm32cubeprog_rs::connect::{ConnectReport, ConnectStrategy};
+use stm32cubeprog_rs::err::Error;
+use stm32cubeprog_rs::memory::TransferOptions;
+use stm32cubeprog_rs::{DebugConnectMode, DebugResetMode, STM32CubeProg};
+
+#[test]
+fn step_down_selects_the_next_working_frequency() {
+    let _lock = stub::lock();
+    let library = stub::library();
+    stub::reset(&library);
+    stub::plug_probe(&library, b"PROBE", false);
+    let stm32prog = STM32CubeProg::new(stub::installation()).unwrap();
+
+    let mut stlink = stm32prog.discover().unwrap().remove(0);
+    assert_eq!(
+        stm32prog.connect_auto_frequency(&mut stlink).unwrap(),
+        24_000_000
+    );
+
+    stub::fail_connections(&library, 1);
+    assert_eq!(stm32prog.step_down_frequency().unwrap(), Some(3_300_000));
+    assert_eq!(stub::connected_frequency(&library), Some(3300));
+}
+
+#[test]
+fn failed_step_down_restores_the_original_frequency() {
+    let _lock = stub::lock();
+    let library = stub::library();
+    stub::reset(&library);
+    stub::plug_probe(&library, b"PROBE", false);
+    let stm32prog = STM32CubeProg::new(stub::installation()).unwrap();
+
+    let mut stlink = stm32prog.discover().unwrap().remove(0);
+    stm32prog.connect_auto_frequency(&mut stlink).unwrap();
+
+    stub::fail_connections(&library, 3);
+    assert!(stm32prog.step_down_frequency().is_err());
+    assert_eq!(stub::connected_frequency(&library), Some(24000));
+    assert_eq!(stm32prog.connected_stlink().unwrap().frequency(), 24000);
+}
+
+#[test]
+fn failed_restore_is_reported_with_every_attempt() {
+    let _lock = stub::lock();
+    let library = stub::library();
+    stub::reset(&library);
+    stub::plug_probe(&library, b"PROBE", false);
+    let stm32prog = STM32CubeProg::new(stub::installation()).unwrap();
+
+    let mut stlink = stm32prog.discover().unwrap().remove(0);
+    stm32prog.connect_auto_frequency(&mut stlink).unwrap();
+
+    stub::fail_connections(&library, 4);
+    match stm32prog.step_down_frequency() {
+        Err(Error::ConnectionFailed(report)) => {
+            let frequencies: Vec<i32> = report
+                .attempts
+                .iter()
+                .map(|attempt| attempt.frequency)
+                .collect();
+            assert_eq!(frequencies, vec![8000, 3300, 1000, 24000]);
+            assert!(report.succeeded().is_none());
+        }
+        result => panic!("unexpected {:?}", result),
+    }
+    assert_eq!(stub::connected_frequency(&library), None);
+    assert!(stm32prog.connected_stlink().is_none());
+}
+
+#[test]
+fn read_failures_step_down_the_frequency() {
+    let _lock = stub::lock();
+    let library = stub::library();
+    stub::reset(&library);
+    stub::plug_probe(&library, b"PROBE", false);
+    let stm32prog = STM32CubeProg::new(stub::installation()).unwrap();
+
+    let mut stlink = stm32prog.discover().unwrap().remove(0);
+    stm32prog.connect_auto_frequency(&mut stlink).unwrap();
+    let mut buffer = [0; 4];
+
+    // Three failures exhaust the retries of the default options
+    stub::fail_reads(&library, 3);
+    assert!(stm32prog
+        .read_chunked(
+            0x20000000,
+            &mut buffer,
+            &TransferOptions::default(),
+            |_, _| {}
+        )
+        .is_err());
+    assert_eq!(stub::connected_frequency(&library), Some(24000));
+
+    let options = TransferOptions {
+        step_down: true,
+        ..TransferOptions::default()
+    };
+    stub::fail_reads(&library, 3);
+    stm32prog
+        .read_chunked(0x20000000, &mut buffer, &options, |_, _| {})
+        .unwrap();
+    assert_eq!(stub::connected_frequency(&library), Some(8000));
+}
+
+fn strategy(
+    modes: Vec<(DebugConnectMode, DebugResetMode)>,
+    retries: u32,
+    frequency_fallback: bool,
+) -> ConnectStrategy {
+    ConnectStrategy {
+        modes,
+        retries,
+        delay: std::time::Duration::from_millis(0),
+        frequency_fallback,
+    }
+}
+
+fn attempts(report: &ConnectReport) -> Vec<(DebugConnectMode, DebugResetMode, i32, bool)> {
+    report
+        .attempts
+        .iter()
+        .map(|attempt| {
+            (
+                attempt.connection_mode,
+                attempt.reset_mode,
+                attempt.frequency,
+                attempt.result.is_ok(),
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn strategy_retries_then_falls_back_to_lower_frequencies() {
+    let _lock = stub::lock();
+    let library = stub::library();
+    stub::reset(&library);
+    stub::plug_probe(&library, b"PROBE", false);
+    let stm32prog = STM32CubeProg::new(stub::installation()).unwrap();
+
+    let mut stlink = stm32prog.discover().unwrap().remove(0);
+    stlink.set_frequency(8000);
+    let modes = vec![
+        (DebugConnectMode::Normal, DebugResetMode::SoftwareReset),
+        (DebugConnectMode::UnderReset, DebugResetMode::HardwareReset),
+    ];
+
+    stub::fail_connections(&library, 2);
+    let report = stm32prog
+        .connect_with_strategy(&mut stlink, &strategy(modes.clone(), 1, true))
+        .unwrap();
+    let normal = (DebugConnectMode::Normal, DebugResetMode::SoftwareReset);
+    assert_eq!(
+        attempts(&report),
+        vec![
+            (normal.0, normal.1, 8000, false),
+            (normal.0, normal.1, 8000, false),
+            (normal.0, normal.1, 3300, true),
+        ]
+    );
+    assert_eq!(report.succeeded().unwrap().frequency, 3300);
+    assert_eq!(stub::connected_frequency(&library), Some(3300));
+
+    // Without fallback, the next mode is tried at the configured frequency
+    stlink.set_frequency(8000);
+    stub::fail_connections(&library, 1);
+    let report = stm32prog
+        .connect_with_strategy(&mut stlink, &strategy(modes, 0, false))
+        .unwrap();
+    assert_eq!(
+        attempts(&report),
+        vec![
+            (normal.0, normal.1, 8000, false),
+            (
+                DebugConnectMode::UnderReset,
+                DebugResetMode::HardwareReset,
+                8000,
+                true
+            ),
+        ]
+    );
+}
+
+#[test]
+fn strategy_reports_every_failed_attempt() {
+    let _lock = stub::lock();
+    let library = stub::library();
+    stub::reset(&library);
+    stub::plug_probe(&library, b"PROBE", false);
+    let stm32prog = STM32CubeProg::new(stub::installation()).unwrap();
+
+    let mut stlink = stm32prog.discover().unwrap().remove(0);
+    stlink.set_frequency(3300);
+    let modes = vec![(DebugConnectMode::UnderReset, DebugResetMode::HardwareReset)];
+
+    stub::fail_connections(&library, 10);
+    match stm32prog.connect_with_strategy(&mut stlink, &strategy(modes, 2, true)) {
+        Err(Error::ConnectionFailed(report)) => {
+            let frequencies: Vec<i32> = report
+                .attempts
+                .iter()
+                .map(|attempt| attempt.frequency)
+                .collect();
+            assert_eq!(frequencies, vec![3300, 3300, 3300, 1000, 1000, 1000]);
+            assert!(report.succeeded().is_none());
+            assert!(report
+                .to_string()
+                .starts_with("Attempt 1: UnderReset/HardwareReset at 3300 kHz: "));
+        }
+        result => panic!("unexpected {:?}", result),
+    }
+    assert_eq!(stub::connected_frequency(&library), None);
+}
+
+#[test]
+fn strategy_rejects_negative_frequencies() {
+    let _lock = stub::lock();
+    let library = stub::library();
+    stub::reset(&library);
+    stub::plug_probe(&library, b"PROBE", false);
+    let stm32prog = STM32CubeProg::new(stub::installation()).unwrap();
+
+    let mut stlink = stm32prog.discover().unwrap().remove(0);
+    stlink.set_frequency(-1);
+    match stm32prog.connect_with_strategy(&mut stlink, &ConnectStrategy::default()) {
+        Err(Error::IntConversionError(_)) => {}
+        result => panic!("unexpected {:?}", result),
+    }
+    assert_eq!(stub::connected_frequency(&library), None);
+}
@@ -0,0 +1,62 @@
+extern crate libloading;
+extern crate stm32cubeprog_rs;
+
+mod stub;
+
+use stm32cubeprog_rs::err::{CubeProgrammerError, Error};
+use stm32cubeprog_rs::safety::SafetyPolicy;
+use stm32cubeprog_rs::STM32CubeProg;
+
+const DHCSR: u32 = 0xE000EDF0;
+
+#[test]
+fn halting_bypasses_dry_runs_and_the_safety_policy() {
+    let _lock = stub::lock();
+    let library = stub::library();
+    stub::reset(&library);
+    stub::plug_probe(&library, b"PROBE", false);
+    let stm32prog = STM32CubeProg::new(stub::installation()).unwrap();
+    let stlink = stm32prog.discover().unwrap().remove(0);
+    stm32prog.connect(&stlink).unwrap();
+    stm32prog.set_safety_policy(SafetyPolicy::new().protect("SCS", 0xE000E000, 0x1000));
+
+    let plan = stm32prog
+        .dry_run(|stm32prog| {
+            stm32prog.halt()?;
+            stm32prog.is_halted()?;
+            stm32prog.resume()
+        })
+        .unwrap();
+    assert!(plan.operations.is_empty());
+    assert_eq!(
+        stub::accesses(&library),
+        vec![(DHCSR, 4), (DHCSR, 4), (DHCSR, 4)]
+    );
+    assert_eq!(stm32prog.read::<u32>(DHCSR).unwrap(), 0xA05F0001);
+}
+
+#[test]
+fn cortex_a7_cores_cannot_be_halted() {
+    let _lock = stub::lock();
+    let library = stub::library();
+    stub::reset(&library);
+    stub::plug_probe(&library, b"PROBE", false);
+    stub::set_device_id(&library, 0x500);
+    let stm32prog = STM32CubeProg::new(stub::installation()).unwrap();
+    let stlink = stm32prog.discover().unwrap().remove(0);
+    stm32prog.connect(&stlink).unwrap();
+
+    for result in [
+        stm32prog.halt(),
+        stm32prog.resume(),
+        stm32prog.is_halted().map(|_| ()),
+    ]
+    .iter()
+    {
+        match result {
+            Err(Error::CubeProgrammerError(CubeProgrammerError::UnsupportedOperation)) => {}
+            result => panic!("unexpected {:?}", result),
+        }
+    }
+    assert!(stub::accesses(&library).is_empty());
+}
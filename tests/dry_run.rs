@@ -0,0 +1,81 @@
+extern crate libloading;
+extern crate stm32cubeprog_rs;
+
+mod stub;
+
+use stm32cubeprog_rs::checksum::{ChecksumAlgorithm, ChecksumMethod, OnTargetCrc};
+use stm32cubeprog_rs::dry_run::PlannedOperation;
+use stm32cubeprog_rs::STM32CubeProg;
+
+#[test]
+fn dry_run_records_without_writing() {
+    let _lock = stub::lock();
+    let library = stub::library();
+    stub::reset(&library);
+    let stm32prog = STM32CubeProg::new(stub::installation()).unwrap();
+
+    stm32prog.write_memory8(0x20000000, vec![0; 16]).unwrap();
+
+    let plan = stm32prog
+        .dry_run(|stm32prog| {
+            stm32prog.mass_erase()?;
+            stm32prog.write_memory8(0x20000000, vec![0xFF; 16])?;
+            stm32prog.set_option_bytes(&[("RDP", 0xBB)])
+        })
+        .unwrap();
+
+    assert_eq!(
+        plan.operations,
+        vec![
+            PlannedOperation::MassErase,
+            PlannedOperation::Write {
+                address: 0x20000000,
+                size: 16
+            },
+            PlannedOperation::OptionBytes {
+                command: String::from("-ob RDP=0xbb")
+            },
+        ]
+    );
+    assert!(!stm32prog.is_dry_run());
+    assert_eq!(stm32prog.read_memory8(0x20000000, 16).unwrap(), vec![0; 16]);
+    assert_eq!(stm32prog.option_byte("RDP").unwrap().value, 0xAA);
+}
+
+#[test]
+fn dry_run_reads_back_instead_of_running_the_crc_routine() {
+    let _lock = stub::lock();
+    let library = stub::library();
+    stub::reset(&library);
+    let stm32prog = STM32CubeProg::new(stub::installation()).unwrap();
+
+    let image = 0x12345678u32.to_le_bytes().to_vec();
+    stm32prog.write_memory8(0x08000000, image.clone()).unwrap();
+    let parameters = OnTargetCrc::for_device_id(0x469).unwrap();
+
+    let plan = stm32prog
+        .dry_run(|stm32prog| {
+            stm32prog
+                .verify_checksum(
+                    0x08000000,
+                    &image,
+                    ChecksumAlgorithm::Stm32,
+                    ChecksumMethod::OnTarget(parameters),
+                )
+                .map(|_| ())
+        })
+        .unwrap();
+
+    assert_eq!(
+        plan.operations,
+        vec![PlannedOperation::OnTargetChecksum {
+            address: 0x08000000,
+            size: 4,
+            ram_address: parameters.ram_address
+        }]
+    );
+    assert_eq!(
+        stm32prog.read_memory8(parameters.ram_address, 4).unwrap(),
+        vec![0xFF; 4]
+    );
+}
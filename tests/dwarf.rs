@@ -0,0 +1,22 @@
+#![cfg(feature = "dwarf")]
+
+extern crate stm32cubeprog_rs;
+
+mod firmware;
+
+use stm32cubeprog_rs::elf::Elf;
+use stm32cubeprog_rs::symbols::Symbols;
+
+#[test]
+fn sizes_come_from_the_variable_at_the_symbol_address() {
+    let symbols = Symbols::from_elf(&Elf::parse(firmware::debug_elf()).unwrap()).unwrap();
+
+    let counter = symbols.symbol("counter").unwrap();
+    assert_eq!(counter.address, 0x20000000);
+    assert_eq!(counter.size, 4);
+    assert_eq!(symbols.type_size("counter"), Some(4));
+
+    // The only `buffer` of the debug information is located elsewhere
+    assert_eq!(symbols.symbol("buffer").unwrap().size, 0);
+    assert_eq!(symbols.type_size("buffer"), None);
+}
@@ -0,0 +1,161 @@
+//! Hand-assembled firmware files.
+
+// Each test crate uses a different subset of the files.
+#![allow(dead_code)]
+
+fn push_u16(data: &mut Vec<u8>, value: u16) {
+    data.extend_from_slice(&value.to_le_bytes());
+}
+
+fn push_u32(data: &mut Vec<u8>, values: &[u32]) {
+    for value in values {
+        data.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+/// ELF32 file with 16 bytes at 0x08000000 and a 4-byte `serial` symbol at
+/// 0x08000008.
+pub fn elf() -> Vec<u8> {
+    let strings = b"\0serial\0";
+    let section_names = b"\0.text\0.symtab\0.strtab\0.shstrtab\0";
+
+    let mut elf = b"\x7fELF\x01\x01\x01\0\0\0\0\0\0\0\0\0".to_vec();
+    push_u16(&mut elf, 2);
+    push_u16(&mut elf, 40);
+    push_u32(&mut elf, &[1, 0x08000000, 52, 176, 0x05000000]);
+    for value in [52, 32, 1, 40, 5, 4] {
+        push_u16(&mut elf, value);
+    }
+
+    push_u32(&mut elf, &[1, 84, 0x08000000, 0x08000000, 16, 16, 5, 4]);
+    elf.extend((0..16).map(|byte| byte as u8));
+    push_u32(&mut elf, &[0, 0, 0, 0, 1, 0x08000008, 4]);
+    elf.extend_from_slice(&[0x11, 0, 1, 0]);
+    elf.extend_from_slice(strings);
+    elf.extend_from_slice(section_names);
+    elf.resize(176, 0);
+
+    push_u32(&mut elf, &[0; 10]);
+    push_u32(&mut elf, &[1, 1, 6, 0x08000000, 84, 16, 0, 0, 4, 0]);
+    push_u32(&mut elf, &[7, 2, 0, 0, 100, 32, 3, 1, 4, 16]);
+    push_u32(&mut elf, &[15, 3, 0, 0, 132, 8, 0, 0, 1, 0]);
+    push_u32(&mut elf, &[23, 3, 0, 0, 140, 33, 0, 0, 1, 0]);
+    elf
+}
+
+/// `.debug_abbrev` and `.debug_info` of `debug_elf`, in DWARF 4.
+fn debug_info() -> (Vec<u8>, Vec<u8>) {
+    let mut abbreviations = Vec::new();
+    // Compile unit, with children
+    abbreviations.extend_from_slice(&[0x01, 0x11, 0x01, 0x00, 0x00]);
+    // Base type: byte size
+    abbreviations.extend_from_slice(&[0x02, 0x24, 0x00, 0x0B, 0x0B, 0x00, 0x00]);
+    // Variable: name, type, location
+    abbreviations.extend_from_slice(&[
+        0x03, 0x34, 0x00, 0x03, 0x08, 0x49, 0x13, 0x02, 0x18, 0x00, 0x00,
+    ]);
+    // Subprogram, with children
+    abbreviations.extend_from_slice(&[0x04, 0x2E, 0x01, 0x00, 0x00]);
+    // Array type: element type, with children
+    abbreviations.extend_from_slice(&[0x05, 0x01, 0x01, 0x49, 0x13, 0x00, 0x00]);
+    // Subrange: count
+    abbreviations.extend_from_slice(&[0x06, 0x21, 0x00, 0x37, 0x0B, 0x00, 0x00]);
+    abbreviations.push(0x00);
+
+    // Unit header: length, version 4, abbreviations at 0, 4-byte addresses
+    let mut info = vec![0; 4];
+    push_u16(&mut info, 4);
+    push_u32(&mut info, &[0]);
+    info.push(4);
+
+    let variable = |info: &mut Vec<u8>, name: &str, kind: u32, address: u32| {
+        info.push(0x03);
+        info.extend_from_slice(name.as_bytes());
+        info.push(0);
+        push_u32(info, &[kind]);
+        info.extend_from_slice(&[0x05, 0x03]);
+        push_u32(info, &[address]);
+    };
+
+    info.push(0x01);
+    let word = info.len() as u32;
+    info.extend_from_slice(&[0x02, 4]);
+    let byte = info.len() as u32;
+    info.extend_from_slice(&[0x02, 1]);
+    let array = info.len() as u32;
+    info.push(0x05);
+    push_u32(&mut info, &[byte]);
+    info.extend_from_slice(&[0x06, 16, 0x00]);
+
+    // Static variable of a function, sharing the name of a global one
+    info.push(0x04);
+    variable(&mut info, "counter", array, 0x20000010);
+    info.push(0x00);
+
+    variable(&mut info, "counter", word, 0x20000000);
+    // File-level variable of another object than the `buffer` symbol
+    variable(&mut info, "buffer", array, 0x20000100);
+    info.push(0x00);
+
+    let length = info.len() as u32 - 4;
+    info[..4].copy_from_slice(&length.to_le_bytes());
+    (abbreviations, info)
+}
+
+/// ELF32 file without program, with `counter` at 0x20000000 and `buffer` at
+/// 0x20000020 in its symbol table, both without size, and debug information
+/// describing them.
+pub fn debug_elf() -> Vec<u8> {
+    let strings = b"\0counter\0buffer\0";
+    let section_names = b"\0.symtab\0.strtab\0.debug_abbrev\0.debug_info\0.shstrtab\0";
+    let (abbreviations, info) = debug_info();
+
+    let mut symbols = vec![0; 16];
+    push_u32(&mut symbols, &[1, 0x20000000, 0]);
+    symbols.extend_from_slice(&[0x11, 0, 0, 0]);
+    push_u32(&mut symbols, &[9, 0x20000020, 0]);
+    symbols.extend_from_slice(&[0x11, 0, 0, 0]);
+
+    // Name offset, type and link of each section after the null one
+    let sections: [(u32, u32, u32, &[u8]); 5] = [
+        (1, 2, 2, &symbols),
+        (9, 3, 0, strings),
+        (17, 1, 0, &abbreviations),
+        (31, 1, 0, &info),
+        (43, 3, 0, section_names),
+    ];
+
+    let mut elf = b"\x7fELF\x01\x01\x01\0\0\0\0\0\0\0\0\0".to_vec();
+    push_u16(&mut elf, 2);
+    push_u16(&mut elf, 40);
+    elf.resize(52, 0);
+    let mut headers = vec![0; 40];
+    for &(name, kind, link, data) in sections.iter() {
+        let entry_size = if kind == 2 { 16 } else { 0 };
+        push_u32(
+            &mut headers,
+            &[
+                name,
+                kind,
+                0,
+                0,
+                elf.len() as u32,
+                data.len() as u32,
+                link,
+                0,
+                1,
+                entry_size,
+            ],
+        );
+        elf.extend_from_slice(data);
+    }
+
+    let section_headers = elf.len() as u32;
+    elf[0x20..0x24].copy_from_slice(&section_headers.to_le_bytes());
+    elf[0x28..0x2A].copy_from_slice(&52u16.to_le_bytes());
+    elf[0x2E..0x30].copy_from_slice(&40u16.to_le_bytes());
+    elf[0x30..0x32].copy_from_slice(&6u16.to_le_bytes());
+    elf[0x32..0x34].copy_from_slice(&5u16.to_le_bytes());
+    elf.extend_from_slice(&headers);
+    elf
+}
@@ -0,0 +1,115 @@
+extern crate libloading;
+extern crate stm32cubeprog_rs;
+
+mod stub;
+
+use stm32cubeprog_rs::checksum::ChecksumMethod;
+use stm32cubeprog_rs::flash::{FlashLayout, SectorComparison};
+use stm32cubeprog_rs::STM32CubeProg;
+
+const FLASH: u32 = 0x08000000;
+
+fn smart_download_rewrites_changed_sectors(comparison: SectorComparison) {
+    let _lock = stub::lock();
+    let library = stub::library();
+    stub::reset(&library);
+    let stm32prog = STM32CubeProg::new(stub::installation()).unwrap();
+
+    let layout = FlashLayout::uniform(FLASH, 0, 0x100, 4).unwrap();
+    let mut image: Vec<u8> = (0..0x380).map(|byte| byte as u8).collect();
+    stm32prog.write_memory8(FLASH, image.clone()).unwrap();
+    stm32prog
+        .write_memory8(FLASH + 0x3F0, vec![0x5A; 16])
+        .unwrap();
+
+    // Sector 1 changes and sector 3 is only partially covered by the image
+    image[0x180] ^= 0xFF;
+    image[0x300] ^= 0xFF;
+
+    let report = stm32prog
+        .smart_download(&image, FLASH, &layout, comparison)
+        .unwrap();
+    assert_eq!(report.programmed, vec![1, 3]);
+    assert_eq!(report.skipped, vec![0, 2]);
+    assert_eq!((report.programmed_count(), report.skipped_count()), (2, 2));
+    assert_eq!(stub::erased_sectors(&library), vec![1, 3]);
+
+    assert_eq!(stm32prog.read_memory8(FLASH, 0x380).unwrap(), image);
+    // Bytes of sector 3 past the end of the image are preserved
+    assert_eq!(
+        stm32prog.read_memory8(FLASH + 0x3F0, 16).unwrap(),
+        vec![0x5A; 16]
+    );
+
+    let report = stm32prog
+        .smart_download(&image, FLASH, &layout, comparison)
+        .unwrap();
+    assert!(report.programmed.is_empty());
+    assert_eq!(report.skipped, vec![0, 1, 2, 3]);
+    assert_eq!(stub::erased_sectors(&library), vec![1, 3]);
+}
+
+#[test]
+fn smart_download_compares_sectors_by_read_back() {
+    smart_download_rewrites_changed_sectors(SectorComparison::ReadBack);
+}
+
+#[test]
+fn smart_download_compares_sectors_by_checksum() {
+    smart_download_rewrites_changed_sectors(SectorComparison::Checksum(ChecksumMethod::ReadBack));
+}
+
+#[test]
+fn smart_download_rejects_images_outside_the_layout() {
+    let _lock = stub::lock();
+    let library = stub::library();
+    stub::reset(&library);
+    let stm32prog = STM32CubeProg::new(stub::installation()).unwrap();
+
+    let layout = FlashLayout::uniform(FLASH, 0, 0x100, 4).unwrap();
+    assert!(stm32prog
+        .smart_download(
+            &[0; 0x10],
+            FLASH + 0x3F8,
+            &layout,
+            SectorComparison::ReadBack
+        )
+        .is_err());
+    assert!(stub::erased_sectors(&library).is_empty());
+}
+
+#[test]
+fn smart_download_erases_the_sectors_of_the_layout() {
+    let _lock = stub::lock();
+    let library = stub::library();
+    stub::reset(&library);
+    let stm32prog = STM32CubeProg::new(stub::installation()).unwrap();
+
+    // Region above a two sector bootloader
+    let layout = FlashLayout::uniform(FLASH + 0x200, 2, 0x100, 2).unwrap();
+    let image = vec![0xA5; 0x200];
+    stm32prog
+        .write_memory8(FLASH + 0x200, image.clone())
+        .unwrap();
+
+    let mut changed = image.clone();
+    changed[0x100] = 0x00;
+    let report = stm32prog
+        .smart_download(&changed, FLASH + 0x200, &layout, SectorComparison::ReadBack)
+        .unwrap();
+    assert_eq!(report.programmed, vec![3]);
+    assert_eq!(report.skipped, vec![2]);
+    assert_eq!(stub::erased_sectors(&library), vec![3]);
+}
+
+#[test]
+fn uniform_layouts_must_fit_in_the_address_space() {
+    let layout = FlashLayout::uniform(0xFFFFF000, 7, 0x800, 2).unwrap();
+    assert_eq!(layout.sectors()[1].index, 8);
+    assert_eq!(layout.sectors()[1].address, 0xFFFFF800);
+
+    assert!(FlashLayout::uniform(0xFFFFF000, 0, 0x800, 3).is_err());
+    assert!(FlashLayout::uniform(FLASH, 0, 0x8000_0000, 3).is_err());
+    assert!(FlashLayout::uniform(FLASH, u32::MAX, 0x100, 2).is_err());
+    assert!(FlashLayout::uniform(FLASH, 0, 0, 1).is_err());
+}
@@ -0,0 +1,119 @@
+extern crate libloading;
+extern crate stm32cubeprog_rs;
+
+mod stub;
+
+use stm32cubeprog_rs::err::{CubeProgrammerError, Error};
+use stm32cubeprog_rs::safety::{IrreversibleChange, SafetyRule};
+use stm32cubeprog_rs::STM32CubeProg;
+
+#[test]
+fn otp_words_are_written_once() {
+    let _lock = stub::lock();
+    let library = stub::library();
+    stub::reset(&library);
+    let stm32prog = STM32CubeProg::new(stub::installation()).unwrap();
+
+    let mac = [0x00, 0x80, 0xE1, 0x12, 0x34, 0x56, 0xFF, 0xFF];
+    let preview = stm32prog.preview_otp(0x1FFF7008, &mac, false).unwrap();
+    assert_eq!(preview.writes, vec![(0x1FFF7008, mac.to_vec())]);
+
+    match stm32prog.program_otp(&preview) {
+        Err(Error::SafetyViolation {
+            rule: SafetyRule::Unconfirmed(IrreversibleChange::OtpWrite),
+            ..
+        }) => {}
+        result => panic!("unexpected {:?}", result),
+    }
+    stm32prog.confirm_irreversible(IrreversibleChange::OtpWrite);
+    stm32prog.program_otp(&preview).unwrap();
+    assert_eq!(stm32prog.read_memory8(0x1FFF7008, 8).unwrap(), mac.to_vec());
+
+    // Programming the same content again is a no-op
+    let preview = stm32prog.preview_otp(0x1FFF7008, &mac, false).unwrap();
+    assert!(preview.writes.is_empty());
+    assert_eq!(preview.unchanged, vec![0x1FFF7008]);
+
+    match stm32prog.preview_otp(0x1FFF7008, &[0; 8], false) {
+        Err(Error::OtpProgrammed(0x1FFF7008)) => {}
+        result => panic!("unexpected {:?}", result),
+    }
+    match stm32prog.preview_otp(0x1FFF7004, &[0; 8], false) {
+        Err(Error::UnalignedAccess { .. }) => {}
+        result => panic!("unexpected {:?}", result),
+    }
+}
+
+#[test]
+fn f4_blocks_are_locked_with_their_lock_byte() {
+    let _lock = stub::lock();
+    let library = stub::library();
+    stub::reset(&library);
+    stub::set_device_id(&library, 0x413);
+    let stm32prog = STM32CubeProg::new(stub::installation()).unwrap();
+
+    let serial = [0x5A; 32];
+    let preview = stm32prog.preview_otp(0x1FFF7820, &serial, true).unwrap();
+    assert_eq!(preview.writes, vec![(0x1FFF7820, serial.to_vec())]);
+    assert_eq!(preview.locks, vec![1]);
+
+    stm32prog.confirm_irreversible(IrreversibleChange::OtpWrite);
+    stm32prog.program_otp(&preview).unwrap();
+    assert_eq!(
+        stm32prog.read_memory8(0x1FFF7A00, 3).unwrap(),
+        vec![0xFF, 0x00, 0xFF]
+    );
+
+    let state = stm32prog.read_otp().unwrap();
+    assert_eq!(state.locked[..3], [false, true, false]);
+    assert_eq!(state.is_programmed(0x1FFF7820), Some(true));
+    assert_eq!(state.is_programmed(0x1FFF7840), Some(false));
+
+    // Locked blocks cannot be written, even where still erased
+    match stm32prog.preview_otp(0x1FFF7820, &[0xA5; 32], false) {
+        Err(Error::OtpLocked(1)) => {}
+        result => panic!("unexpected {:?}", result),
+    }
+
+    // Only the blocks not locked yet are locked
+    let mut data = serial.to_vec();
+    data.extend_from_slice(&[0x11; 32]);
+    let preview = stm32prog.preview_otp(0x1FFF7820, &data, true).unwrap();
+    assert_eq!(preview.writes, vec![(0x1FFF7840, vec![0x11; 32])]);
+    assert_eq!(preview.unchanged.len(), 32);
+    assert_eq!(preview.locks, vec![2]);
+}
+
+#[test]
+fn otp_lookups_outside_of_the_area_are_rejected() {
+    let _lock = stub::lock();
+    let library = stub::library();
+    stub::reset(&library);
+    stub::set_device_id(&library, 0x413);
+    let stm32prog = STM32CubeProg::new(stub::installation()).unwrap();
+
+    let state = stm32prog.read_otp().unwrap();
+    assert_eq!(state.layout.block_of(0x1FFF781F), Some(0));
+    assert_eq!(state.layout.block_of(0x1FFF79FF), Some(15));
+    assert_eq!(state.layout.block_of(0x1FFF7A00), None);
+    assert_eq!(state.layout.block_of(0x1FFF77FF), None);
+    assert_eq!(state.is_programmed(0x1FFF7A00), None);
+
+    match stm32prog.preview_otp(0x1FFF79F0, &[0; 32], false) {
+        Err(Error::OutOfRange { .. }) => {}
+        result => panic!("unexpected {:?}", result),
+    }
+}
+
+#[test]
+fn locking_requires_lock_bytes() {
+    let _lock = stub::lock();
+    let library = stub::library();
+    stub::reset(&library);
+    let stm32prog = STM32CubeProg::new(stub::installation()).unwrap();
+
+    match stm32prog.preview_otp(0x1FFF7000, &[0; 8], true) {
+        Err(Error::CubeProgrammerError(CubeProgrammerError::UnsupportedOperation)) => {}
+        result => panic!("unexpected {:?}", result),
+    }
+}
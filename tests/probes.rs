@@ -0,0 +1,146 @@
+extern crate libloading;
+extern crate stm32cubeprog_rs;
+
+mod stub;
+
+use stm32cubeprog_rs::err::{CubeProgrammerError, Error};
+use stm32cubeprog_rs::watch::{ProbeEvent, ProbeWatcher};
+use stm32cubeprog_rs::STM32CubeProg;
+
+fn serial_numbers(stm32prog: &STM32CubeProg) -> Vec<String> {
+    stm32prog
+        .discover()
+        .unwrap()
+        .iter()
+        .map(|stlink| stlink.serial_number().unwrap())
+        .collect()
+}
+
+#[test]
+fn probes_held_by_other_processes_are_busy() {
+    let _lock = stub::lock();
+    let library = stub::library();
+    stub::reset(&library);
+    stub::plug_probe(&library, b"FREE", false);
+    stub::plug_probe(&library, b"HELD", true);
+    let stm32prog = STM32CubeProg::new(stub::installation()).unwrap();
+
+    assert_eq!(serial_numbers(&stm32prog), vec!["FREE"]);
+    assert_eq!(stm32prog.busy_probes().unwrap(), vec!["HELD"]);
+
+    let shared = STM32CubeProg::builder(stub::installation())
+        .shared(true)
+        .build()
+        .unwrap();
+    let mut held = shared.discover().unwrap().remove(1);
+    held.set_shared(false);
+    match stm32prog.connect(&held) {
+        Err(Error::ProbeBusy(serial_number)) => assert_eq!(serial_number, "HELD"),
+        result => panic!("unexpected {:?}", result),
+    }
+    assert!(stm32prog.connected_stlink().is_none());
+}
+
+#[test]
+fn shared_sessions_open_held_probes() {
+    let _lock = stub::lock();
+    let library = stub::library();
+    stub::reset(&library);
+    stub::plug_probe(&library, b"FREE", false);
+    stub::plug_probe(&library, b"HELD", true);
+    let stm32prog = STM32CubeProg::builder(stub::installation())
+        .shared(true)
+        .build()
+        .unwrap();
+
+    let stlinks = stm32prog.discover().unwrap();
+    assert_eq!(serial_numbers(&stm32prog), vec!["FREE", "HELD"]);
+    assert!(stlinks.iter().all(|stlink| stlink.shared()));
+
+    stm32prog.connect(&stlinks[1]).unwrap();
+    assert!(stm32prog.connected_stlink().unwrap().shared());
+    assert!(stub::connected_frequency(&library).is_some());
+}
+
+#[test]
+fn enumeration_failures_keep_the_connection_error() {
+    let _lock = stub::lock();
+    let library = stub::library();
+    stub::reset(&library);
+    stub::plug_probe(&library, b"HELD", true);
+    // A serial number that is not valid UTF-8 makes `busy_probes` fail
+    stub::plug_probe(&library, b"\xFF\xFE", false);
+    let stm32prog = STM32CubeProg::new(stub::installation()).unwrap();
+    assert!(stm32prog.busy_probes().is_err());
+
+    let shared = STM32CubeProg::builder(stub::installation())
+        .shared(true)
+        .build()
+        .unwrap();
+    let mut held = shared.discover().unwrap().remove(0);
+    held.set_shared(false);
+    match stm32prog.connect(&held) {
+        Err(Error::CubeProgrammerError(CubeProgrammerError::ConnectionError)) => {}
+        result => panic!("unexpected {:?}", result),
+    }
+}
+
+fn serial_numbers_of(events: &[ProbeEvent]) -> Vec<(bool, String)> {
+    events
+        .iter()
+        .map(|event| {
+            let attached = matches!(event, ProbeEvent::Attached(_));
+            (attached, event.serial_number().unwrap())
+        })
+        .collect()
+}
+
+#[test]
+fn polling_reports_attached_and_detached_probes() {
+    let _lock = stub::lock();
+    let library = stub::library();
+    stub::reset(&library);
+    stub::plug_probe(&library, b"FIRST", false);
+    stub::plug_probe(&library, b"HELD", true);
+    let stm32prog = STM32CubeProg::new(stub::installation()).unwrap();
+    let mut watcher = ProbeWatcher::default();
+
+    assert_eq!(
+        serial_numbers_of(&stm32prog.poll_probes(&mut watcher).unwrap()),
+        vec![(true, "FIRST".into()), (true, "HELD".into())]
+    );
+    assert!(stm32prog.poll_probes(&mut watcher).unwrap().is_empty());
+
+    stub::plug_probe(&library, b"SECOND", false);
+    assert_eq!(
+        serial_numbers_of(&stm32prog.poll_probes(&mut watcher).unwrap()),
+        vec![(true, "SECOND".into())]
+    );
+
+    stub::unplug_probe(&library, b"FIRST");
+    assert_eq!(
+        serial_numbers_of(&stm32prog.poll_probes(&mut watcher).unwrap()),
+        vec![(false, "FIRST".into())]
+    );
+    assert_eq!(watcher.probes().len(), 2);
+}
+
+#[test]
+fn polling_keeps_the_connection() {
+    let _lock = stub::lock();
+    let library = stub::library();
+    stub::reset(&library);
+    stub::plug_probe(&library, b"FIRST", false);
+    let stm32prog = STM32CubeProg::new(stub::installation()).unwrap();
+    let stlink = stm32prog.discover().unwrap().remove(0);
+    stm32prog.connect(&stlink).unwrap();
+
+    let mut watcher = ProbeWatcher::default();
+    stub::plug_probe(&library, b"SECOND", false);
+    assert_eq!(stm32prog.poll_probes(&mut watcher).unwrap().len(), 2);
+    stub::unplug_probe(&library, b"SECOND");
+    assert_eq!(stm32prog.poll_probes(&mut watcher).unwrap().len(), 1);
+
+    assert!(stub::connected_frequency(&library).is_some());
+    assert!(stm32prog.read_memory8(0x08000000, 4).is_ok());
+}
@@ -0,0 +1,191 @@
+extern crate libloading;
+extern crate stm32cubeprog_rs;
+
+mod stub;
+
+use stm32cubeprog_rs::dry_run::PlannedOperation;
+use stm32cubeprog_rs::err::{CubeProgrammerError, Error};
+use stm32cubeprog_rs::rdp::{RdpLevel, UnlockOptions};
+use stm32cubeprog_rs::safety::IrreversibleChange;
+use stm32cubeprog_rs::STM32CubeProg;
+
+/// Connected session on a target at readout protection level 1.
+fn protected_target(library: &libloading::Library) -> STM32CubeProg {
+    stub::reset(library);
+    stub::plug_probe(library, b"PROBE", false);
+    let stm32prog = STM32CubeProg::new(stub::installation()).unwrap();
+
+    let stlink = stm32prog.discover().unwrap().remove(0);
+    stm32prog.connect(&stlink).unwrap();
+    stm32prog.set_option_bytes(&[("RDP", 0xBB)]).unwrap();
+    stm32prog
+}
+
+fn options() -> UnlockOptions {
+    UnlockOptions {
+        timeout: std::time::Duration::from_millis(100),
+        delay: std::time::Duration::from_millis(1),
+        verify_blank: true,
+    }
+}
+
+#[test]
+fn unlock_regresses_and_checks_the_flash() {
+    let _lock = stub::lock();
+    let library = stub::library();
+    let stm32prog = protected_target(&library);
+
+    stm32prog.confirm_irreversible(IrreversibleChange::ReadoutProtectionRegression);
+    let report = stm32prog.unlock(&options()).unwrap();
+
+    assert_eq!(report.previous, RdpLevel::Level1);
+    assert!(report.regressed);
+    assert!(!report.planned);
+    assert_eq!(report.reconnect_attempts, 1);
+    assert_eq!(report.verified_size, 0x80000);
+    assert_eq!(stm32prog.rdp_level().unwrap(), RdpLevel::Level0);
+    assert!(stm32prog.connected_stlink().is_some());
+
+    let report = stm32prog.unlock(&options()).unwrap();
+    assert_eq!(report.previous, RdpLevel::Level0);
+    assert!(!report.regressed);
+}
+
+#[test]
+fn unlock_requires_confirmation() {
+    let _lock = stub::lock();
+    let library = stub::library();
+    let stm32prog = protected_target(&library);
+
+    match stm32prog.unlock(&options()) {
+        Err(Error::SafetyViolation { .. }) => {}
+        result => panic!("unexpected {:?}", result),
+    }
+    assert_eq!(stm32prog.rdp_level().unwrap(), RdpLevel::Level1);
+}
+
+#[test]
+fn unlock_refuses_level_2() {
+    let _lock = stub::lock();
+    let library = stub::library();
+    let stm32prog = protected_target(&library);
+
+    stm32prog.confirm_irreversible(IrreversibleChange::ReadoutProtectionLevel2);
+    stm32prog.set_option_bytes(&[("RDP", 0xCC)]).unwrap();
+    stm32prog.confirm_irreversible(IrreversibleChange::ReadoutProtectionRegression);
+
+    match stm32prog.unlock(&options()) {
+        Err(Error::CubeProgrammerError(CubeProgrammerError::RdpEnabledError)) => {}
+        result => panic!("unexpected {:?}", result),
+    }
+    assert_eq!(stm32prog.rdp_level().unwrap(), RdpLevel::Level2);
+}
+
+#[test]
+fn unlock_keeps_reconnecting_until_the_timeout() {
+    let _lock = stub::lock();
+    let library = stub::library();
+    let stm32prog = protected_target(&library);
+
+    stub::fail_connections(&library, 2);
+    stm32prog.confirm_irreversible(IrreversibleChange::ReadoutProtectionRegression);
+    assert_eq!(stm32prog.unlock(&options()).unwrap().reconnect_attempts, 3);
+
+    stm32prog.set_option_bytes(&[("RDP", 0xBB)]).unwrap();
+    stub::fail_connections(&library, usize::MAX);
+    stm32prog.confirm_irreversible(IrreversibleChange::ReadoutProtectionRegression);
+    match stm32prog.unlock(&options()) {
+        Err(Error::CubeProgrammerError(CubeProgrammerError::ConnectionError)) => {}
+        result => panic!("unexpected {:?}", result),
+    }
+    assert!(stm32prog.connected_stlink().is_none());
+}
+
+#[test]
+fn unlock_reports_flash_left_programmed() {
+    let _lock = stub::lock();
+    let library = stub::library();
+    let stm32prog = protected_target(&library);
+
+    stm32prog.write_memory8(0x08000100, vec![0x00]).unwrap();
+    stm32prog.confirm_irreversible(IrreversibleChange::ReadoutProtectionRegression);
+
+    match stm32prog.unlock(&options()) {
+        Err(Error::UnlockFailed(message)) => assert!(message.contains("0x08000100")),
+        result => panic!("unexpected {:?}", result),
+    }
+}
+
+#[test]
+fn unlock_reports_the_regression_and_reconnection_errors() {
+    let _lock = stub::lock();
+    let library = stub::library();
+    let stm32prog = protected_target(&library);
+
+    stub::fail_option_bytes(&library, 1);
+    stub::fail_connections(&library, usize::MAX);
+    stm32prog.confirm_irreversible(IrreversibleChange::ReadoutProtectionRegression);
+    match stm32prog.unlock(&options()) {
+        Err(Error::RegressionFailed {
+            regression,
+            reconnection,
+        }) => match (*regression, *reconnection) {
+            (
+                Error::CubeProgrammerError(CubeProgrammerError::DeviceNotConnected),
+                Error::CubeProgrammerError(CubeProgrammerError::ConnectionError),
+            ) => {}
+            errors => panic!("unexpected {:?}", errors),
+        },
+        result => panic!("unexpected {:?}", result),
+    }
+}
+
+#[test]
+fn unlock_checks_the_erased_value_of_the_family() {
+    let _lock = stub::lock();
+    let library = stub::library();
+    let stm32prog = protected_target(&library);
+
+    // STM32L0 flash reads as zeros once erased
+    stub::set_device_id(&library, 0x417);
+    stm32prog.confirm_irreversible(IrreversibleChange::ReadoutProtectionRegression);
+    assert_eq!(stm32prog.unlock(&options()).unwrap().verified_size, 0x80000);
+
+    stm32prog.set_option_bytes(&[("RDP", 0xBB)]).unwrap();
+    stm32prog.write_memory8(0x08000200, vec![0xFF]).unwrap();
+    stm32prog.confirm_irreversible(IrreversibleChange::ReadoutProtectionRegression);
+    match stm32prog.unlock(&options()) {
+        Err(Error::UnlockFailed(message)) => assert!(message.contains("0x08000200")),
+        result => panic!("unexpected {:?}", result),
+    }
+
+    // The check is skipped when the erased value is unknown
+    stub::set_device_id(&library, 0x999);
+    stm32prog.set_option_bytes(&[("RDP", 0xBB)]).unwrap();
+    stm32prog.confirm_irreversible(IrreversibleChange::ReadoutProtectionRegression);
+    assert_eq!(stm32prog.unlock(&options()).unwrap().verified_size, 0);
+}
+
+#[test]
+fn unlock_is_only_planned_in_dry_run() {
+    let _lock = stub::lock();
+    let library = stub::library();
+    let stm32prog = protected_target(&library);
+
+    stm32prog.confirm_irreversible(IrreversibleChange::ReadoutProtectionRegression);
+    stm32prog.start_dry_run();
+    let report = stm32prog.unlock(&options()).unwrap();
+    let plan = stm32prog.finish_dry_run().unwrap();
+
+    assert!(report.planned);
+    assert!(!report.regressed);
+    assert_eq!(report.reconnect_attempts, 0);
+    assert_eq!(
+        plan.operations,
+        vec![PlannedOperation::OptionBytes {
+            command: String::from("-ob RDP=0xaa")
+        }]
+    );
+    assert_eq!(stm32prog.rdp_level().unwrap(), RdpLevel::Level1);
+    assert!(stm32prog.connected_stlink().is_some());
+}
@@ -0,0 +1,49 @@
+extern crate libloading;
+extern crate stm32cubeprog_rs;
+
+mod stub;
+
+use stm32cubeprog_rs::STM32CubeProg;
+
+#[test]
+fn reads_release_library_buffers() {
+    let _lock = stub::lock();
+    let library = stub::library();
+    stub::reset(&library);
+    let stm32prog = STM32CubeProg::new(stub::installation()).unwrap();
+
+    stm32prog
+        .write_memory8(0x20000000, (0..=255).collect())
+        .unwrap();
+
+    for _ in 0..10_000 {
+        let data = stm32prog.read_memory8(0x20000000, 256).unwrap();
+        assert_eq!(data[0x42], 0x42);
+
+        let data = stm32prog.read_memory32(0x20000000, 4).unwrap();
+        assert_eq!(data[1], 0x07060504);
+
+        let data: u64 = stm32prog.read(0x20000003).unwrap();
+        assert_eq!(data, 0x0A09080706050403);
+    }
+
+    assert_eq!(stub::outstanding_allocations(&library), 0);
+}
+
+#[test]
+fn failed_reads_release_library_buffers() {
+    let _lock = stub::lock();
+    let library = stub::library();
+    stub::reset(&library);
+    let stm32prog = STM32CubeProg::new(stub::installation()).unwrap();
+
+    // Each read is attempted three times before giving up
+    stub::fail_reads(&library, 300);
+    for _ in 0..100 {
+        assert!(stm32prog.read_memory8(0x20000000, 256).is_err());
+    }
+
+    assert_eq!(stub::outstanding_allocations(&library), 0);
+    assert!(stm32prog.read_memory8(0x20000000, 256).is_ok());
+    assert_eq!(stub::outstanding_allocations(&library), 0);
+}
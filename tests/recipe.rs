@@ -0,0 +1,277 @@
+#![cfg(feature = "recipe")]
+
+extern crate libloading;
+extern crate stm32cubeprog_rs;
+
+mod stub;
+
+use stm32cubeprog_rs::err::Error;
+use stm32cubeprog_rs::recipe::{MemoryMap, MemoryRegion, Recipe, Step};
+use stm32cubeprog_rs::STM32CubeProg;
+
+/// Empty directory for the files of one test.
+fn directory(name: &str) -> std::path::PathBuf {
+    let directory = std::path::Path::new(env!("CARGO_TARGET_TMPDIR"))
+        .join("recipe")
+        .join(name);
+    let _ = std::fs::remove_dir_all(&directory);
+    std::fs::create_dir_all(&directory).unwrap();
+    directory
+}
+
+fn memory_map() -> MemoryMap {
+    MemoryMap {
+        regions: vec![
+            MemoryRegion {
+                name: "flash".to_string(),
+                address: 0x08000000,
+                size: 0x80000,
+                sector_size: Some(0x800),
+            },
+            MemoryRegion {
+                name: "sram".to_string(),
+                address: 0x20000000,
+                size: 0x20000,
+                sector_size: None,
+            },
+        ],
+    }
+}
+
+fn issues(result: Result<(), Error>) -> Vec<String> {
+    match result {
+        Err(Error::InvalidRecipe(issues)) => issues,
+        result => panic!("unexpected {:?}", result),
+    }
+}
+
+#[test]
+fn recipes_are_parsed() {
+    let recipe: Recipe = r#"
+        probe = "stlink://0670FF*?mode=under-reset"
+
+        [connection]
+        frequency = 4000
+
+        [[memory]]
+        name = "flash"
+        address = 0x08000000
+        size = 0x100000
+        sector_size = 0x20000
+
+        [[steps]]
+        action = "erase"
+        sectors = [0, 1]
+
+        [[steps]]
+        action = "program"
+        file = "bootloader.bin"
+        address = 0x08000000
+
+        [[steps]]
+        action = "option-bytes"
+        values = { nBOOT0 = 1 }
+
+        [[steps]]
+        action = "reset"
+    "#
+    .parse()
+    .unwrap();
+
+    assert!(recipe.probe.is_some());
+    assert_eq!(recipe.memory.regions[0].sector_count(), Some(8));
+    assert_eq!(
+        recipe.steps,
+        vec![
+            Step::Erase {
+                sectors: Some(vec![0, 1])
+            },
+            Step::Program {
+                file: "bootloader.bin".into(),
+                address: Some(0x08000000),
+                verify: true,
+                skip_erase: false,
+            },
+            Step::OptionBytes {
+                values: vec![("nBOOT0".to_string(), 1)].into_iter().collect(),
+            },
+            Step::Reset {},
+        ]
+    );
+}
+
+#[test]
+fn unknown_keys_are_rejected() {
+    for text in [
+        "[[steps]]\naction = \"reset\"\nmode = \"hardware\"",
+        "[[steps]]\naction = \"erase\"\nsector = [0]",
+        "[[steps]]\naction = \"flash\"",
+        "steps = []\nprobes = \"stlink://\"",
+    ]
+    .iter()
+    {
+        match text.parse::<Recipe>() {
+            Err(Error::InvalidRecipe(_)) => {}
+            result => panic!("{:?} gave {:?}", text, result),
+        }
+    }
+}
+
+#[test]
+fn steps_are_validated_against_the_memory_map() {
+    let directory = directory("validate");
+    std::fs::write(directory.join("small.bin"), [0u8; 0x100]).unwrap();
+    std::fs::write(directory.join("app.hex"), ":00000001FF\n").unwrap();
+
+    let mut recipe: Recipe = r#"
+        [[steps]]
+        action = "erase"
+        sectors = [0, 255]
+
+        [[steps]]
+        action = "program"
+        file = "app.hex"
+
+        [[steps]]
+        action = "write"
+        file = "small.bin"
+        address = 0x2001FF00
+
+        [[steps]]
+        action = "verify"
+        file = "small.bin"
+        address = 0x08000000
+    "#
+    .parse()
+    .unwrap();
+    recipe.base_directory = directory.clone();
+    recipe.validate(&memory_map()).unwrap();
+
+    let mut recipe: Recipe = r#"
+        [[steps]]
+        action = "erase"
+        sectors = [256]
+
+        [[steps]]
+        action = "program"
+        file = "small.bin"
+
+        [[steps]]
+        action = "write"
+        file = "small.bin"
+        address = 0x2001FF01
+
+        [[steps]]
+        action = "verify"
+        file = "missing.bin"
+        address = 0x08000000
+
+        [[steps]]
+        action = "option-bytes"
+        values = {}
+    "#
+    .parse()
+    .unwrap();
+    recipe.base_directory = directory;
+    let issues = issues(recipe.validate(&memory_map()));
+
+    assert_eq!(issues.len(), 5);
+    assert!(issues[0].starts_with("step 1 "));
+    assert!(issues[1].contains("need an address"));
+    assert!(issues[2].contains("do not fit"));
+    assert!(issues[3].contains("missing.bin"));
+    assert!(issues[4].contains("no option byte"));
+}
+
+#[test]
+fn recipe_without_flash_cannot_erase_sectors() {
+    let recipe: Recipe = "[[steps]]\naction = \"erase\"\nsectors = [0]"
+        .parse()
+        .unwrap();
+
+    assert!(issues(recipe.validate(&MemoryMap::default()))[0].contains("no flash region"));
+}
+
+#[test]
+fn steps_are_logged_until_the_first_failure() {
+    let _lock = stub::lock();
+    let library = stub::library();
+    stub::reset(&library);
+    stub::plug_probe(&library, b"0670FF", false);
+    let stm32prog = STM32CubeProg::new(stub::installation()).unwrap();
+
+    let directory = directory("run");
+    std::fs::write(directory.join("data.bin"), [0x5A; 16]).unwrap();
+    std::fs::write(directory.join("other.bin"), [0xA5; 16]).unwrap();
+    let path = directory.join("recipe.toml");
+    std::fs::write(
+        &path,
+        r#"
+        probe = "stlink://0670FF"
+
+        [[memory]]
+        name = "sram"
+        address = 0x20000000
+        size = 0x20000
+
+        [[steps]]
+        action = "write"
+        file = "data.bin"
+        address = 0x20000000
+
+        [[steps]]
+        action = "verify"
+        file = "data.bin"
+        address = 0x20000000
+
+        [[steps]]
+        action = "verify"
+        file = "other.bin"
+        address = 0x20000000
+
+        [[steps]]
+        action = "reset"
+        "#,
+    )
+    .unwrap();
+    let recipe = Recipe::load(&path).unwrap();
+
+    let log = stm32prog.run_recipe(&recipe).unwrap();
+
+    assert!(!log.succeeded());
+    assert_eq!(log.probe.as_deref(), Some("0670FF"));
+    assert_eq!(log.steps.len(), 3);
+    assert!(log.steps[..2].iter().all(|step| step.error.is_none()));
+    assert_eq!(log.steps[2].index, 2);
+    assert!(log.steps[2].step.starts_with("verify"));
+    assert!(log.steps[2].error.is_some());
+}
+
+#[test]
+fn unknown_option_bytes_fail_before_any_step() {
+    let _lock = stub::lock();
+    let library = stub::library();
+    stub::reset(&library);
+    stub::plug_probe(&library, b"0670FF", false);
+    let stm32prog = STM32CubeProg::new(stub::installation()).unwrap();
+
+    let recipe: Recipe = r#"
+        [[memory]]
+        name = "flash"
+        address = 0x08000000
+        size = 0x80000
+
+        [[steps]]
+        action = "option-bytes"
+        values = { nBOOT0 = 0, WRP1 = 0 }
+        "#
+    .parse()
+    .unwrap();
+
+    assert_eq!(
+        issues(stm32prog.validate_recipe(&recipe)),
+        vec!["unknown option byte WRP1"]
+    );
+    assert!(stm32prog.run_recipe(&recipe).is_err());
+    assert_eq!(stm32prog.option_byte("nBOOT0").unwrap().value, 1);
+}
@@ -0,0 +1,214 @@
+extern crate libloading;
+extern crate stm32cubeprog_rs;
+
+mod stub;
+
+use stm32cubeprog_rs::err::Error;
+use stm32cubeprog_rs::rdp::RdpLevel;
+use stm32cubeprog_rs::safety::{IrreversibleChange, SafetyPolicy, SafetyRule};
+use stm32cubeprog_rs::STM32CubeProg;
+
+#[test]
+fn protected_regions_block_writes() {
+    let _lock = stub::lock();
+    let library = stub::library();
+    stub::reset(&library);
+    let stm32prog = STM32CubeProg::builder(stub::installation())
+        .safety_policy(SafetyPolicy::new().protect("calibration", 0x20000100, 0x100))
+        .build()
+        .unwrap();
+
+    stm32prog.write_memory8(0x200000F0, vec![0; 16]).unwrap();
+    match stm32prog.write_memory8(0x200000F0, vec![0; 17]) {
+        Err(Error::SafetyViolation {
+            rule: SafetyRule::ProtectedRegion(region),
+            ..
+        }) => assert_eq!(region.name, "calibration"),
+        result => panic!("unexpected {:?}", result),
+    }
+    match stm32prog.mass_erase() {
+        Err(Error::SafetyViolation {
+            rule: SafetyRule::UnknownRange,
+            ..
+        }) => {}
+        result => panic!("unexpected {:?}", result),
+    }
+}
+
+#[test]
+fn readout_protection_level_2_requires_confirmation() {
+    let _lock = stub::lock();
+    let library = stub::library();
+    stub::reset(&library);
+    let stm32prog = STM32CubeProg::new(stub::installation()).unwrap();
+
+    match stm32prog.set_option_bytes(&[("RDP", 0xCC)]) {
+        Err(Error::SafetyViolation {
+            rule: SafetyRule::Unconfirmed(IrreversibleChange::ReadoutProtectionLevel2),
+            ..
+        }) => {}
+        result => panic!("unexpected {:?}", result),
+    }
+    assert_eq!(stm32prog.option_byte("RDP").unwrap().value, 0xAA);
+
+    stm32prog.confirm_irreversible(IrreversibleChange::ReadoutProtectionLevel2);
+    stm32prog.set_option_bytes(&[("RDP", 0xCC)]).unwrap();
+    assert_eq!(stm32prog.option_byte("RDP").unwrap().value, 0xCC);
+
+    // Confirmations are only good for one operation
+    assert!(stm32prog.set_option_bytes(&[("RDP", 0xCC)]).is_err());
+}
+
+#[test]
+fn readout_protection_regression_requires_confirmation() {
+    let _lock = stub::lock();
+    let library = stub::library();
+    stub::reset(&library);
+    let stm32prog = STM32CubeProg::new(stub::installation()).unwrap();
+
+    stm32prog.set_option_bytes(&[("RDP", 0xBB)]).unwrap();
+    assert_eq!(stm32prog.rdp_level().unwrap(), RdpLevel::Level1);
+
+    match stm32prog.set_option_bytes(&[("RDP", 0xAA)]) {
+        Err(Error::SafetyViolation {
+            rule: SafetyRule::Unconfirmed(IrreversibleChange::ReadoutProtectionRegression),
+            ..
+        }) => {}
+        result => panic!("unexpected {:?}", result),
+    }
+
+    stm32prog.confirm_irreversible(IrreversibleChange::ReadoutProtectionRegression);
+    stm32prog.set_option_bytes(&[("RDP", 0xAA)]).unwrap();
+    assert_eq!(stm32prog.rdp_level().unwrap(), RdpLevel::Level0);
+}
+
+/// S3 record of `data` at `address`.
+fn srec_record(address: u32, data: &[u8]) -> String {
+    let mut bytes = vec![data.len() as u8 + 5];
+    bytes.extend_from_slice(&address.to_be_bytes());
+    bytes.extend_from_slice(data);
+    let checksum = !bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+    bytes.push(checksum);
+
+    let digits: Vec<String> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+    format!("S3{}", digits.concat())
+}
+
+fn write_file(name: &str, content: &str) -> std::path::PathBuf {
+    let path = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join(name);
+    std::fs::write(&path, content).unwrap();
+    path
+}
+
+#[test]
+fn srec_downloads_are_checked_against_their_ranges() {
+    let _lock = stub::lock();
+    let library = stub::library();
+    stub::reset(&library);
+    let stm32prog = STM32CubeProg::new(stub::installation()).unwrap();
+
+    let flash = write_file(
+        "safety-flash.srec",
+        &format!(
+            "S00600004844521B\n{}\nS70500000000FA\n",
+            srec_record(0x08000000, &[1, 2, 3, 4])
+        ),
+    );
+    let otp = write_file(
+        "safety-otp.s37",
+        &format!("{}\n", srec_record(0x1FFF7000, &[0; 8])),
+    );
+
+    stm32prog.download(&flash, None, None, None).unwrap();
+    match stm32prog.download(&otp, None, None, None) {
+        Err(Error::SafetyViolation {
+            rule: SafetyRule::Unconfirmed(IrreversibleChange::OtpWrite),
+            ..
+        }) => {}
+        result => panic!("unexpected {:?}", result),
+    }
+
+    stm32prog.set_safety_policy(SafetyPolicy::new().protect("bootloader", 0x08000000, 0x1000));
+    match stm32prog.download(&flash, None, None, None) {
+        Err(Error::SafetyViolation {
+            rule: SafetyRule::ProtectedRegion(region),
+            ..
+        }) => assert_eq!(region.name, "bootloader"),
+        result => panic!("unexpected {:?}", result),
+    }
+}
+
+#[test]
+fn unparsed_downloads_are_left_to_the_library() {
+    let _lock = stub::lock();
+    let library = stub::library();
+    stub::reset(&library);
+    let stm32prog = STM32CubeProg::new(stub::installation()).unwrap();
+
+    let path = write_file("safety-unknown.img", "unknown format");
+    stm32prog.download(&path, None, None, None).unwrap();
+
+    // The address of the image is known to be in the OTP area
+    match stm32prog.download(&path, Some(0x1FFF7000), None, None) {
+        Err(Error::SafetyViolation {
+            rule: SafetyRule::Unconfirmed(IrreversibleChange::OtpWrite),
+            ..
+        }) => {}
+        result => panic!("unexpected {:?}", result),
+    }
+    stm32prog.confirm_irreversible(IrreversibleChange::OtpWrite);
+    stm32prog
+        .download(&path, Some(0x1FFF7000), None, None)
+        .unwrap();
+
+    stm32prog.set_safety_policy(SafetyPolicy::new().protect("bootloader", 0x08000000, 0x1000));
+    match stm32prog.download(&path, None, None, None) {
+        Err(Error::SafetyViolation {
+            rule: SafetyRule::UnknownRange,
+            ..
+        }) => {}
+        result => panic!("unexpected {:?}", result),
+    }
+}
+
+#[test]
+fn unreadable_downloads_report_the_io_error() {
+    let _lock = stub::lock();
+    let library = stub::library();
+    stub::reset(&library);
+    let stm32prog = STM32CubeProg::new(stub::installation()).unwrap();
+
+    let directory = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("safety-directory.hex");
+    std::fs::create_dir_all(&directory).unwrap();
+    match stm32prog.download(&directory, None, None, None) {
+        Err(Error::IoError(_)) => {}
+        result => panic!("unexpected {:?}", result),
+    }
+    match stm32prog.download(directory.join("missing.hex"), None, None, None) {
+        Err(Error::IoError(_)) => {}
+        result => panic!("unexpected {:?}", result),
+    }
+}
+
+#[test]
+fn dry_runs_do_not_consume_confirmations() {
+    let _lock = stub::lock();
+    let library = stub::library();
+    stub::reset(&library);
+    let stm32prog = STM32CubeProg::new(stub::installation()).unwrap();
+
+    stm32prog.confirm_irreversible(IrreversibleChange::ReadoutProtectionLevel2);
+    stm32prog.confirm_irreversible(IrreversibleChange::OtpWrite);
+    let plan = stm32prog
+        .dry_run(|stm32prog| {
+            stm32prog.write_memory8(0x1FFF7000, vec![0; 8])?;
+            stm32prog.set_option_bytes(&[("RDP", 0xCC)])
+        })
+        .unwrap();
+    assert_eq!(plan.operations.len(), 2);
+    assert_eq!(stm32prog.option_byte("RDP").unwrap().value, 0xAA);
+
+    stm32prog.write_memory8(0x1FFF7000, vec![0; 8]).unwrap();
+    stm32prog.set_option_bytes(&[("RDP", 0xCC)]).unwrap();
+    assert_eq!(stm32prog.option_byte("RDP").unwrap().value, 0xCC);
+}
@@ -0,0 +1,46 @@
+#![cfg(feature = "serde")]
+
+extern crate libloading;
+extern crate serde_json;
+extern crate stm32cubeprog_rs;
+
+mod stub;
+
+use stm32cubeprog_rs::snapshot::{DeviceInfoSnapshot, FrequenciesSnapshot, STLinkSnapshot};
+use stm32cubeprog_rs::STM32CubeProg;
+
+#[test]
+fn snapshots_round_trip_through_serde() {
+    let _lock = stub::lock();
+    let library = stub::library();
+    stub::reset(&library);
+    stub::plug_probe(&library, b"PROBE", false);
+    let stm32prog = STM32CubeProg::new(stub::installation()).unwrap();
+    let stlink = stm32prog.discover().unwrap().remove(0);
+
+    let snapshot = stlink.snapshot().unwrap();
+    assert_eq!(snapshot.serial_number, "PROBE");
+    assert_eq!(snapshot.target_voltage, Some(3.29));
+    let json = serde_json::to_string(&snapshot).unwrap();
+    assert_eq!(
+        serde_json::from_str::<STLinkSnapshot>(&json).unwrap(),
+        snapshot
+    );
+
+    let frequencies = stlink.frequencies().snapshot();
+    assert_eq!(frequencies.swd, vec![24000, 8000, 3300, 1000]);
+    let json = serde_json::to_string(&frequencies).unwrap();
+    assert_eq!(
+        serde_json::from_str::<FrequenciesSnapshot>(&json).unwrap(),
+        frequencies
+    );
+
+    stm32prog.connect(&stlink).unwrap();
+    let device_info = stm32prog.device_info().unwrap().snapshot().unwrap();
+    assert_eq!(device_info.device_id, 0x469);
+    let json = serde_json::to_string(&device_info).unwrap();
+    assert_eq!(
+        serde_json::from_str::<DeviceInfoSnapshot>(&json).unwrap(),
+        device_info
+    );
+}
@@ -0,0 +1,64 @@
+extern crate libloading;
+extern crate stm32cubeprog_rs;
+
+mod firmware;
+mod stub;
+
+use stm32cubeprog_rs::elf::Elf;
+use stm32cubeprog_rs::image::Image;
+use stm32cubeprog_rs::stamp::{Stamp, StampLocation, StampSource};
+use stm32cubeprog_rs::STM32CubeProg;
+
+#[test]
+fn images_round_trip_through_intel_hex() {
+    let image = Image::from_elf(&Elf::parse(firmware::elf()).unwrap());
+    assert_eq!(image.ranges(), vec![(0x08000000, 16)]);
+    assert_eq!(image.symbol("serial").unwrap().address, 0x08000008);
+
+    let hex = Image::from_hex(&image.to_hex()).unwrap();
+    assert_eq!(hex.segments(), image.segments());
+}
+
+#[test]
+fn stamps_patch_the_image_and_increment_counters() {
+    let _lock = stub::lock();
+    let library = stub::library();
+    stub::reset(&library);
+    let stm32prog = STM32CubeProg::new(stub::installation()).unwrap();
+
+    let counter = std::env::temp_dir().join(format!("stamp-counter-{}", std::process::id()));
+    std::fs::write(&counter, "41\n").unwrap();
+
+    let mut image = Image::from_elf(&Elf::parse(firmware::elf()).unwrap());
+    let stamps = [
+        Stamp::new(
+            StampLocation::Symbol(String::from("serial")),
+            StampSource::Counter {
+                path: counter.clone(),
+                size: 4,
+            },
+        ),
+        Stamp::new(
+            StampLocation::Address(0x08000000),
+            StampSource::Callback(Box::new(|context| Ok(vec![context.device_id as u8]))),
+        ),
+    ];
+    let records = stm32prog
+        .program_stamped(&mut image, &stamps, true)
+        .unwrap();
+
+    assert_eq!(records[0].address, 0x08000008);
+    assert_eq!(records[0].value, vec![41, 0, 0, 0]);
+    assert_eq!(image.read(0x08000000, 12).unwrap()[..1], [0x69]);
+    assert_eq!(image.read(0x08000008, 4).unwrap(), [41, 0, 0, 0]);
+    assert_eq!(std::fs::read_to_string(&counter).unwrap(), "42\n");
+
+    // Values larger than the symbol are refused
+    let stamps = [Stamp::new(
+        StampLocation::Symbol(String::from("serial")),
+        StampSource::Uid,
+    )];
+    assert!(stm32prog.stamp(&mut image, &stamps).is_err());
+
+    std::fs::remove_file(&counter).unwrap();
+}
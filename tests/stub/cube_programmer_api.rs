@@ -0,0 +1,658 @@
+//! Stand-in for the STM32CubeProgrammer API library, exposing the symbols
+//! loaded by the crate on top of a simulated target memory.
+
+#![allow(clippy::missing_safety_doc)]
+
+use std::collections::BTreeMap;
+use std::os::raw::{c_char, c_int, c_uchar, c_uint, c_void};
+use std::sync::atomic::{AtomicIsize, AtomicU32, AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+extern "C" {
+    fn malloc(size: usize) -> *mut c_void;
+    fn free(data: *mut c_void);
+}
+
+static MEMORY: Mutex<BTreeMap<u32, u8>> = Mutex::new(BTreeMap::new());
+static OUTSTANDING: AtomicIsize = AtomicIsize::new(0);
+static FAILING_READS: AtomicUsize = AtomicUsize::new(0);
+static FAILING_CONNECTIONS: AtomicUsize = AtomicUsize::new(0);
+static FAILING_OPTION_BYTES: AtomicUsize = AtomicUsize::new(0);
+static DEVICE_ID: AtomicU32 = AtomicU32::new(DEFAULT_DEVICE_ID);
+static OPTION_BYTES: Mutex<Vec<(&str, c_uint, c_uint)>> = Mutex::new(Vec::new());
+static ERASED_SECTORS: Mutex<Vec<c_uint>> = Mutex::new(Vec::new());
+/// Address and size of each memory read and write.
+static ACCESSES: Mutex<Vec<(c_uint, c_uint)>> = Mutex::new(Vec::new());
+/// Plugged probes: serial number and whether another process holds them in
+/// shared mode.
+static PROBES: Mutex<Vec<(Vec<u8>, bool)>> = Mutex::new(Vec::new());
+static PROBE_LIST: Mutex<Vec<DebugConnectParameters>> = Mutex::new(Vec::new());
+/// Target voltages reported by the next enumerations, the last one staying.
+static TARGET_VOLTAGES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+static CONNECTED: Mutex<Option<DebugConnectParameters>> = Mutex::new(None);
+/// Interface and parameters of the last bootloader connection.
+static BOOTLOADER: Mutex<Option<(c_int, Vec<c_int>)>> = Mutex::new(None);
+static PERIPHERAL: OnceLock<usize> = OnceLock::new();
+
+/// STM32G474, reported by `getDeviceGeneralInf` unless changed.
+const DEFAULT_DEVICE_ID: u32 = 0x469;
+
+/// Option bytes of the simulated device: name, width and reset value.
+const DEFAULT_OPTION_BYTES: [(&str, c_uint, c_uint); 2] = [("RDP", 8, 0xAA), ("nBOOT0", 1, 1)];
+
+#[no_mangle]
+pub extern "C" fn stub_outstanding_allocations() -> isize {
+    OUTSTANDING.load(Ordering::SeqCst)
+}
+
+/// Make the next `count` reads allocate their buffer and then fail.
+#[no_mangle]
+pub extern "C" fn stub_fail_reads(count: usize) {
+    FAILING_READS.store(count, Ordering::SeqCst);
+}
+
+/// Copy the sectors erased since the last reset into `sectors` and return
+/// their count.
+#[no_mangle]
+pub unsafe extern "C" fn stub_erased_sectors(sectors: *mut c_uint, capacity: usize) -> usize {
+    let erased = ERASED_SECTORS.lock().unwrap();
+    for (index, &sector) in erased.iter().take(capacity).enumerate() {
+        *sectors.add(index) = sector;
+    }
+    erased.len()
+}
+
+/// Copy the address and size of the memory accesses made since the last
+/// reset into `accesses`, as pairs, and return their count.
+#[no_mangle]
+pub unsafe extern "C" fn stub_accesses(accesses: *mut c_uint, capacity: usize) -> usize {
+    let recorded = ACCESSES.lock().unwrap();
+    for (index, &(address, size)) in recorded.iter().take(capacity).enumerate() {
+        *accesses.add(2 * index) = address;
+        *accesses.add(2 * index + 1) = size;
+    }
+    recorded.len()
+}
+
+/// Plug a probe. Probes `held` by another process in shared mode are only
+/// listed and opened in shared mode.
+#[no_mangle]
+pub unsafe extern "C" fn stub_plug_probe(serial_number: *const c_char, held: bool) {
+    let serial_number = std::ffi::CStr::from_ptr(serial_number).to_bytes().to_vec();
+    PROBES.lock().unwrap().push((serial_number, held));
+}
+
+/// Unplug the probe with `serial_number`.
+#[no_mangle]
+pub unsafe extern "C" fn stub_unplug_probe(serial_number: *const c_char) {
+    let serial_number = std::ffi::CStr::from_ptr(serial_number).to_bytes();
+    PROBES
+        .lock()
+        .unwrap()
+        .retain(|(plugged, _)| plugged != serial_number);
+}
+
+/// Report the comma-separated `voltages`, one per enumeration of the probes.
+#[no_mangle]
+pub unsafe extern "C" fn stub_set_target_voltages(voltages: *const c_char) {
+    let voltages = std::ffi::CStr::from_ptr(voltages).to_str().unwrap();
+    *TARGET_VOLTAGES.lock().unwrap() = voltages.split(',').map(String::from).collect();
+}
+
+/// Report `device_id` in the device information.
+#[no_mangle]
+pub extern "C" fn stub_set_device_id(device_id: c_uint) {
+    DEVICE_ID.store(device_id, Ordering::SeqCst);
+}
+
+/// Make the next `count` connections fail.
+#[no_mangle]
+pub extern "C" fn stub_fail_connections(count: usize) {
+    FAILING_CONNECTIONS.store(count, Ordering::SeqCst);
+}
+
+/// Make the next `count` option byte commands fail without being applied.
+#[no_mangle]
+pub extern "C" fn stub_fail_option_bytes(count: usize) {
+    FAILING_OPTION_BYTES.store(count, Ordering::SeqCst);
+}
+
+/// Frequency in kHz of the current connection, -1 when disconnected.
+#[no_mangle]
+pub extern "C" fn stub_connected_frequency() -> c_int {
+    CONNECTED
+        .lock()
+        .unwrap()
+        .map_or(-1, |parameters| parameters.frequency)
+}
+
+/// Copy the parameters of the last bootloader connection into `parameters`
+/// and return the interface, 1 for SPI, 2 for I2C, 3 for CAN, 0 if none.
+#[no_mangle]
+pub unsafe extern "C" fn stub_bootloader(parameters: *mut c_int, capacity: usize) -> c_int {
+    match BOOTLOADER.lock().unwrap().as_ref() {
+        Some((interface, values)) => {
+            for (index, &value) in values.iter().take(capacity).enumerate() {
+                *parameters.add(index) = value;
+            }
+            *interface
+        }
+        None => 0,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn stub_reset() {
+    MEMORY.lock().unwrap().clear();
+    ERASED_SECTORS.lock().unwrap().clear();
+    ACCESSES.lock().unwrap().clear();
+    PROBES.lock().unwrap().clear();
+    TARGET_VOLTAGES.lock().unwrap().clear();
+    *CONNECTED.lock().unwrap() = None;
+    *BOOTLOADER.lock().unwrap() = None;
+    FAILING_READS.store(0, Ordering::SeqCst);
+    FAILING_CONNECTIONS.store(0, Ordering::SeqCst);
+    FAILING_OPTION_BYTES.store(0, Ordering::SeqCst);
+    DEVICE_ID.store(DEFAULT_DEVICE_ID, Ordering::SeqCst);
+    *OPTION_BYTES.lock().unwrap() = DEFAULT_OPTION_BYTES.to_vec();
+}
+
+#[no_mangle]
+pub extern "C" fn setLoadersPath(_path: *const c_char) {}
+
+#[repr(C)]
+pub struct DisplayCallbacks {
+    init_progress_bar: usize,
+    log_message: usize,
+    load_bar: usize,
+}
+
+#[no_mangle]
+pub extern "C" fn setDisplayCallbacks(_callbacks: DisplayCallbacks) {}
+
+#[no_mangle]
+pub extern "C" fn setVerbosityLevel(_level: c_int) {}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct Frequencies {
+    jtag_freq: [c_uint; 12],
+    jtag_freq_count: c_uint,
+    swd_freq: [c_uint; 12],
+    swd_freq_count: c_uint,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct DebugConnectParameters {
+    debug_port: c_int,
+    index: c_int,
+    serial_number: [c_char; 33],
+    firmware_version: [c_char; 20],
+    target_voltage: [c_char; 5],
+    access_port_count: c_int,
+    access_port: c_int,
+    connection_mode: c_int,
+    reset_mode: c_int,
+    old_firmware: c_int,
+    frequencies: Frequencies,
+    frequency: c_int,
+    bridge: c_int,
+    shared: c_int,
+    board: [c_char; 100],
+    debug_sleep: c_int,
+    speed: c_int,
+}
+
+/// SWD frequencies in kHz supported by the simulated probes.
+const SWD_FREQUENCIES: [c_uint; 4] = [24000, 8000, 3300, 1000];
+
+fn probe_parameters(
+    index: usize,
+    serial_number: &[u8],
+    target_voltage: &str,
+) -> DebugConnectParameters {
+    let mut swd_freq = [0; 12];
+    swd_freq[..SWD_FREQUENCIES.len()].copy_from_slice(&SWD_FREQUENCIES);
+    let mut parameters = DebugConnectParameters {
+        debug_port: 1,
+        index: index as c_int,
+        serial_number: [0; 33],
+        firmware_version: c_chars("V3J10M3"),
+        target_voltage: c_chars(target_voltage),
+        access_port_count: 1,
+        access_port: 0,
+        connection_mode: 0,
+        reset_mode: 0,
+        old_firmware: 0,
+        frequencies: Frequencies {
+            jtag_freq: [0; 12],
+            jtag_freq_count: 0,
+            swd_freq,
+            swd_freq_count: SWD_FREQUENCIES.len() as c_uint,
+        },
+        frequency: SWD_FREQUENCIES[0] as c_int,
+        bridge: 0,
+        shared: 0,
+        board: c_chars("NUCLEO-G474RE"),
+        debug_sleep: 0,
+        speed: 0,
+    };
+    for (c, &byte) in parameters.serial_number.iter_mut().zip(serial_number) {
+        *c = byte as c_char;
+    }
+    parameters
+}
+
+unsafe fn probe_list(list: *mut *mut DebugConnectParameters, all: bool) -> c_int {
+    let mut voltages = TARGET_VOLTAGES.lock().unwrap();
+    let target_voltage = match voltages.len() {
+        0 => String::from("3.29"),
+        1 => voltages[0].clone(),
+        _ => voltages.remove(0),
+    };
+
+    let mut probe_list = PROBE_LIST.lock().unwrap();
+    *probe_list = PROBES
+        .lock()
+        .unwrap()
+        .iter()
+        .enumerate()
+        .filter(|(_, (_, held))| all || !held)
+        .map(|(index, (serial_number, _))| probe_parameters(index, serial_number, &target_voltage))
+        .collect();
+    *list = if probe_list.is_empty() {
+        std::ptr::null_mut()
+    } else {
+        probe_list.as_mut_ptr()
+    };
+    probe_list.len() as c_int
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn getStLinkList(
+    list: *mut *mut DebugConnectParameters,
+    shared: c_int,
+) -> c_int {
+    probe_list(list, shared != 0)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn getStLinkEnumerationList(
+    list: *mut *mut DebugConnectParameters,
+    _shared: c_int,
+) -> c_int {
+    probe_list(list, true)
+}
+
+#[no_mangle]
+pub extern "C" fn connectStLink(parameters: DebugConnectParameters) -> c_int {
+    let serial_number: Vec<u8> = parameters
+        .serial_number
+        .iter()
+        .take_while(|&&c| c != 0)
+        .map(|&c| c as u8)
+        .collect();
+    let held = match PROBES
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|(probe, _)| *probe == serial_number)
+    {
+        Some(&(_, held)) => held,
+        None => return -2,
+    };
+    if held && parameters.shared == 0 {
+        return -3;
+    }
+    if FAILING_CONNECTIONS
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |count| {
+            count.checked_sub(1)
+        })
+        .is_ok()
+    {
+        return -3;
+    }
+
+    *CONNECTED.lock().unwrap() = Some(parameters);
+    0
+}
+
+/// Bootloader connection parameters, all made of `int` fields.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct SpiConnectParameters([c_int; 13]);
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct I2cConnectParameters([c_int; 9]);
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct CanConnectParameters([c_int; 9]);
+
+fn connect_bootloader(interface: c_int, parameters: &[c_int]) -> c_int {
+    if FAILING_CONNECTIONS
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |count| {
+            count.checked_sub(1)
+        })
+        .is_ok()
+    {
+        return -3;
+    }
+
+    *CONNECTED.lock().unwrap() = None;
+    *BOOTLOADER.lock().unwrap() = Some((interface, parameters.to_vec()));
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn connectSpiBootloader(parameters: SpiConnectParameters) -> c_int {
+    connect_bootloader(1, &parameters.0)
+}
+
+#[no_mangle]
+pub extern "C" fn connectI2cBootloader(parameters: I2cConnectParameters) -> c_int {
+    connect_bootloader(2, &parameters.0)
+}
+
+#[no_mangle]
+pub extern "C" fn connectCanBootloader(parameters: CanConnectParameters) -> c_int {
+    connect_bootloader(3, &parameters.0)
+}
+
+/// Like the library, also drops the open connection.
+#[no_mangle]
+pub extern "C" fn deleteInterfaceList() {
+    PROBE_LIST.lock().unwrap().clear();
+    *CONNECTED.lock().unwrap() = None;
+}
+
+#[no_mangle]
+pub extern "C" fn disconnect() {
+    *CONNECTED.lock().unwrap() = None;
+}
+
+#[no_mangle]
+pub extern "C" fn reset(_mode: c_int) -> c_int {
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn massErase() -> c_int {
+    MEMORY.lock().unwrap().clear();
+    0
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sectorErase(
+    sectors: *mut c_uint,
+    count: c_uint,
+    _name: *mut c_char,
+) -> c_int {
+    let mut erased = ERASED_SECTORS.lock().unwrap();
+    for index in 0..count {
+        erased.push(*sectors.add(index as usize));
+    }
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn downloadFile(
+    _file: *const c_void,
+    _address: c_uint,
+    _skip_erase: c_uint,
+    _verify: c_uint,
+    _path: *const c_void,
+) -> c_int {
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn getTargetInterfaceType() -> c_int {
+    0
+}
+
+#[repr(C)]
+pub struct DeviceGeneralInfo {
+    device_id: u16,
+    flash_size: c_int,
+    bootloader_version: c_int,
+    category: [c_char; 4],
+    cpu: [c_char; 20],
+    name: [c_char; 100],
+    series: [c_char; 100],
+    description: [c_char; 150],
+    revision_id: [c_char; 8],
+    board: [c_char; 100],
+}
+
+/// Reports an STM32G474 with 512 KB of flash, or the device id set by
+/// `stub_set_device_id`.
+#[no_mangle]
+pub extern "C" fn getDeviceGeneralInf() -> *mut DeviceGeneralInfo {
+    static DEVICE: OnceLock<usize> = OnceLock::new();
+    let device = *DEVICE.get_or_init(|| {
+        Box::into_raw(Box::new(DeviceGeneralInfo {
+            device_id: DEFAULT_DEVICE_ID as u16,
+            flash_size: 0x80000,
+            bootloader_version: 0xD4,
+            category: c_chars("3"),
+            cpu: c_chars("Cortex-M4"),
+            name: c_chars("STM32G47x/G48x"),
+            series: c_chars("STM32G4"),
+            description: c_chars("MCU"),
+            revision_id: c_chars("X"),
+            board: c_chars("--"),
+        })) as usize
+    }) as *mut DeviceGeneralInfo;
+    unsafe { (*device).device_id = DEVICE_ID.load(Ordering::SeqCst) as u16 };
+    device
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn readMemory(
+    address: c_uint,
+    data: *mut *mut c_uchar,
+    size: c_uint,
+) -> c_int {
+    ACCESSES.lock().unwrap().push((address, size));
+    let buffer = malloc(size as usize) as *mut c_uchar;
+    OUTSTANDING.fetch_add(1, Ordering::SeqCst);
+    *data = buffer;
+
+    if FAILING_READS
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |count| {
+            count.checked_sub(1)
+        })
+        .is_ok()
+    {
+        return -9;
+    }
+
+    // Erased flash of the STM32L0 and STM32L1 reads as zeros
+    let erased = match DEVICE_ID.load(Ordering::SeqCst) {
+        0x417 | 0x425 | 0x447 | 0x457 | 0x416 | 0x429 | 0x427 | 0x436 | 0x437 => 0x00,
+        _ => 0xFF,
+    };
+    let memory = MEMORY.lock().unwrap();
+    for offset in 0..size {
+        let byte = memory.get(&(address + offset)).cloned().unwrap_or(erased);
+        *buffer.add(offset as usize) = byte;
+    }
+    0
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn freeLibraryMemory(data: *mut c_void) {
+    OUTSTANDING.fetch_sub(1, Ordering::SeqCst);
+    free(data);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn writeMemory(address: c_uint, data: *mut c_uchar, size: c_uint) -> c_int {
+    ACCESSES.lock().unwrap().push((address, size));
+    let mut memory = MEMORY.lock().unwrap();
+    for offset in 0..size {
+        memory.insert(address + offset, *data.add(offset as usize));
+    }
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn readCortexReg(_register: c_uint, data: *mut c_uint) -> c_int {
+    unsafe { *data = 0 };
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn writeCortexRegistres(_register: c_uint, _data: c_uint) -> c_int {
+    0
+}
+
+#[repr(C)]
+pub struct Bit {
+    name: [c_char; 32],
+    description: [c_char; 300],
+    word_offset: c_uint,
+    bit_offset: c_uint,
+    bit_width: c_uint,
+    access: c_uchar,
+    values_count: c_uint,
+    values: *mut c_void,
+    multiplier: c_uint,
+    offset: c_uint,
+    reference: *mut c_uchar,
+    bit_value: c_uint,
+}
+
+#[repr(C)]
+pub struct Category {
+    name: [c_char; 100],
+    bits_count: c_uint,
+    bits: *mut *mut Bit,
+}
+
+#[repr(C)]
+pub struct Bank {
+    size: c_uint,
+    address: c_uint,
+    access: c_uchar,
+    categories_count: c_uint,
+    categories: *mut *mut Category,
+}
+
+#[repr(C)]
+pub struct Peripheral {
+    name: [c_char; 32],
+    description: [c_char; 200],
+    banks_count: c_uint,
+    banks: *mut *mut Bank,
+}
+
+fn c_chars<const N: usize>(text: &str) -> [c_char; N] {
+    let mut chars = [0; N];
+    for (c, byte) in chars.iter_mut().zip(text.bytes()) {
+        *c = byte as c_char;
+    }
+    chars
+}
+
+fn leak_array<T>(items: Vec<T>) -> *mut *mut T {
+    let pointers: Vec<*mut T> = items
+        .into_iter()
+        .map(|item| Box::into_raw(Box::new(item)))
+        .collect();
+    Box::leak(pointers.into_boxed_slice()).as_mut_ptr()
+}
+
+fn option_bytes() -> std::sync::MutexGuard<'static, Vec<(&'static str, c_uint, c_uint)>> {
+    let mut option_bytes = OPTION_BYTES.lock().unwrap();
+    if option_bytes.is_empty() {
+        *option_bytes = DEFAULT_OPTION_BYTES.to_vec();
+    }
+    option_bytes
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn initOptionBytesInterface() -> *mut Peripheral {
+    let option_bytes = option_bytes();
+    let peripheral = *PERIPHERAL.get_or_init(|| {
+        let bits = option_bytes
+            .iter()
+            .enumerate()
+            .map(|(index, &(name, width, _))| Bit {
+                name: c_chars(name),
+                description: c_chars(name),
+                word_offset: 0,
+                bit_offset: index as c_uint * 8,
+                bit_width: width,
+                access: 2,
+                values_count: 0,
+                values: std::ptr::null_mut(),
+                multiplier: 1,
+                offset: 0,
+                reference: std::ptr::null_mut(),
+                bit_value: 0,
+            })
+            .collect::<Vec<Bit>>();
+        let category = Category {
+            name: c_chars("User Configuration"),
+            bits_count: bits.len() as c_uint,
+            bits: leak_array(bits),
+        };
+        let bank = Bank {
+            size: 4,
+            address: 0x1FFF_7800,
+            access: 2,
+            categories_count: 1,
+            categories: leak_array(vec![category]),
+        };
+        Box::into_raw(Box::new(Peripheral {
+            name: c_chars("Option Bytes"),
+            description: c_chars("Option Bytes"),
+            banks_count: 1,
+            banks: leak_array(vec![bank]),
+        })) as usize
+    }) as *mut Peripheral;
+
+    let category = &**(**(*peripheral).banks).categories;
+    for (index, &(_, _, value)) in option_bytes.iter().enumerate() {
+        (**category.bits.add(index)).bit_value = value;
+    }
+    peripheral
+}
+
+/// Accepts `-ob NAME=VALUE...` commands.
+#[no_mangle]
+pub unsafe extern "C" fn sendOptionBytesCmd(command: *mut c_char) -> c_int {
+    let command = std::ffi::CStr::from_ptr(command).to_string_lossy();
+    let mut arguments = command.split_whitespace();
+    if arguments.next() != Some("-ob") {
+        return -8;
+    }
+    if FAILING_OPTION_BYTES
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |count| {
+            count.checked_sub(1)
+        })
+        .is_ok()
+    {
+        return -1;
+    }
+
+    let mut option_bytes = option_bytes();
+    for argument in arguments {
+        let parsed = argument.split_once('=').and_then(|(name, value)| {
+            let value = c_uint::from_str_radix(value.trim_start_matches("0x"), 16).ok()?;
+            let entry = option_bytes
+                .iter_mut()
+                .find(|(entry, _, _)| entry.eq_ignore_ascii_case(name))?;
+            Some((entry, value))
+        });
+        match parsed {
+            Some((entry, value)) => entry.2 = value,
+            None => return -8,
+        }
+    }
+    0
+}
@@ -0,0 +1,154 @@
+//! Builds the stand-in library of `cube_programmer_api.rs` into a directory
+//! laid out like an STM32CubeProgrammer installation.
+
+// Each test crate uses a different subset of the helpers.
+#![allow(dead_code)]
+
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::{Mutex, MutexGuard, OnceLock};
+
+static INSTALLATION: OnceLock<PathBuf> = OnceLock::new();
+static LOCK: Mutex<()> = Mutex::new(());
+
+#[cfg(unix)]
+const LIBRARY: &str = "lib/libCubeProgrammer_API.so";
+#[cfg(windows)]
+const LIBRARY: &str = "api/lib/CubeProgrammer_API.dll";
+
+/// Directory to pass to `STM32CubeProg::new`.
+pub fn installation() -> PathBuf {
+    INSTALLATION
+        .get_or_init(|| {
+            let root = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("stub");
+            let library = root.join(LIBRARY);
+            std::fs::create_dir_all(library.parent().unwrap()).unwrap();
+
+            let source =
+                PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/stub/cube_programmer_api.rs");
+            let status = Command::new(std::env::var("RUSTC").unwrap_or("rustc".into()))
+                .args(["--edition", "2021", "--crate-type", "cdylib", "-o"])
+                .arg(&library)
+                .arg(source)
+                .status()
+                .expect("Failed to run rustc");
+            assert!(status.success(), "Failed to build the stand-in library");
+
+            root
+        })
+        .clone()
+}
+
+/// Serializes the tests sharing the state of the stand-in library.
+pub fn lock() -> MutexGuard<'static, ()> {
+    LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Direct access to the stand-in library, for its `stub_*` controls.
+pub fn library() -> libloading::Library {
+    unsafe { libloading::Library::new(installation().join(LIBRARY)).unwrap() }
+}
+
+pub fn outstanding_allocations(library: &libloading::Library) -> isize {
+    let function: libloading::Symbol<unsafe extern "C" fn() -> isize> =
+        unsafe { library.get(b"stub_outstanding_allocations\0").unwrap() };
+    unsafe { function() }
+}
+
+pub fn fail_option_bytes(library: &libloading::Library, count: usize) {
+    let function: libloading::Symbol<unsafe extern "C" fn(usize)> =
+        unsafe { library.get(b"stub_fail_option_bytes\0").unwrap() };
+    unsafe { function(count) }
+}
+
+pub fn fail_reads(library: &libloading::Library, count: usize) {
+    let function: libloading::Symbol<unsafe extern "C" fn(usize)> =
+        unsafe { library.get(b"stub_fail_reads\0").unwrap() };
+    unsafe { function(count) }
+}
+
+pub fn erased_sectors(library: &libloading::Library) -> Vec<u32> {
+    let function: libloading::Symbol<unsafe extern "C" fn(*mut u32, usize) -> usize> =
+        unsafe { library.get(b"stub_erased_sectors\0").unwrap() };
+    let mut sectors = vec![0; 64];
+    let count = unsafe { function(sectors.as_mut_ptr(), sectors.len()) };
+    sectors.truncate(count);
+    sectors
+}
+
+/// Address and size of the memory reads and writes since the last reset.
+pub fn accesses(library: &libloading::Library) -> Vec<(u32, u32)> {
+    let function: libloading::Symbol<unsafe extern "C" fn(*mut u32, usize) -> usize> =
+        unsafe { library.get(b"stub_accesses\0").unwrap() };
+    let mut accesses = vec![0; 2 * 1024];
+    let count = unsafe { function(accesses.as_mut_ptr(), 1024) };
+    accesses.truncate(2 * std::cmp::min(count, 1024));
+    accesses
+        .chunks(2)
+        .map(|access| (access[0], access[1]))
+        .collect()
+}
+
+pub fn plug_probe(library: &libloading::Library, serial_number: &[u8], held: bool) {
+    let function: libloading::Symbol<unsafe extern "C" fn(*const std::os::raw::c_char, bool)> =
+        unsafe { library.get(b"stub_plug_probe\0").unwrap() };
+    let serial_number = std::ffi::CString::new(serial_number).unwrap();
+    unsafe { function(serial_number.as_ptr(), held) }
+}
+
+pub fn unplug_probe(library: &libloading::Library, serial_number: &[u8]) {
+    let function: libloading::Symbol<unsafe extern "C" fn(*const std::os::raw::c_char)> =
+        unsafe { library.get(b"stub_unplug_probe\0").unwrap() };
+    let serial_number = std::ffi::CString::new(serial_number).unwrap();
+    unsafe { function(serial_number.as_ptr()) }
+}
+
+/// Report `voltages`, one per enumeration of the probes, the last one staying.
+pub fn set_target_voltages(library: &libloading::Library, voltages: &[&str]) {
+    let function: libloading::Symbol<unsafe extern "C" fn(*const std::os::raw::c_char)> =
+        unsafe { library.get(b"stub_set_target_voltages\0").unwrap() };
+    let voltages = std::ffi::CString::new(voltages.join(",")).unwrap();
+    unsafe { function(voltages.as_ptr()) }
+}
+
+pub fn set_device_id(library: &libloading::Library, device_id: u32) {
+    let function: libloading::Symbol<unsafe extern "C" fn(u32)> =
+        unsafe { library.get(b"stub_set_device_id\0").unwrap() };
+    unsafe { function(device_id) }
+}
+
+pub fn fail_connections(library: &libloading::Library, count: usize) {
+    let function: libloading::Symbol<unsafe extern "C" fn(usize)> =
+        unsafe { library.get(b"stub_fail_connections\0").unwrap() };
+    unsafe { function(count) }
+}
+
+/// Frequency in kHz of the current connection, `None` when disconnected.
+pub fn connected_frequency(library: &libloading::Library) -> Option<i32> {
+    let function: libloading::Symbol<unsafe extern "C" fn() -> std::os::raw::c_int> =
+        unsafe { library.get(b"stub_connected_frequency\0").unwrap() };
+    Some(unsafe { function() }).filter(|&frequency| frequency >= 0)
+}
+
+/// Interface (`"spi"`, `"i2c"` or `"can"`) and parameters of the last
+/// bootloader connection.
+pub fn bootloader(library: &libloading::Library) -> Option<(&'static str, Vec<i32>)> {
+    let function: libloading::Symbol<
+        unsafe extern "C" fn(*mut std::os::raw::c_int, usize) -> std::os::raw::c_int,
+    > = unsafe { library.get(b"stub_bootloader\0").unwrap() };
+    let mut parameters = vec![0; 13];
+    let (interface, count) = match unsafe { function(parameters.as_mut_ptr(), parameters.len()) } {
+        1 => ("spi", 13),
+        2 => ("i2c", 9),
+        3 => ("can", 9),
+        _ => return None,
+    };
+    parameters.truncate(count);
+    Some((interface, parameters))
+}
+
+pub fn reset(library: &libloading::Library) {
+    let function: libloading::Symbol<unsafe extern "C" fn()> =
+        unsafe { library.get(b"stub_reset\0").unwrap() };
+    unsafe { function() }
+}
@@ -0,0 +1,310 @@
+#![cfg(feature = "svd")]
+
+extern crate libloading;
+extern crate stm32cubeprog_rs;
+
+mod stub;
+
+use stm32cubeprog_rs::err::Error;
+use stm32cubeprog_rs::svd::Device;
+use stm32cubeprog_rs::STM32CubeProg;
+
+const SVD: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<device>
+  <name>STM32G474</name>
+  <size>32</size>
+  <peripherals>
+    <peripheral>
+      <name>RCC</name>
+      <description>Reset and clock control</description>
+      <baseAddress>0x40021000</baseAddress>
+      <registers>
+        <register>
+          <name>CR</name>
+          <addressOffset>0x0</addressOffset>
+          <resetValue>0x00000063</resetValue>
+          <fields>
+            <field>
+              <name>HSION</name>
+              <bitOffset>8</bitOffset>
+              <bitWidth>1</bitWidth>
+            </field>
+            <field>
+              <name>HSEON</name>
+              <bitRange>[16:16]</bitRange>
+              <enumeratedValues>
+                <enumeratedValue><name>Off</name><value>0</value></enumeratedValue>
+                <enumeratedValue><name>On</name><value>1</value></enumeratedValue>
+              </enumeratedValues>
+            </field>
+            <field>
+              <name>PLLM</name>
+              <lsb>4</lsb>
+              <msb>7</msb>
+            </field>
+          </fields>
+        </register>
+        <register>
+          <name>KEY%s</name>
+          <dim>2</dim>
+          <dimIncrement>4</dimIncrement>
+          <addressOffset>0x8</addressOffset>
+          <access>write-only</access>
+        </register>
+      </registers>
+    </peripheral>
+    <peripheral derivedFrom="RCC">
+      <name>RCC2</name>
+      <baseAddress>0x40022000</baseAddress>
+    </peripheral>
+  </peripherals>
+</device>
+"#;
+
+#[test]
+fn svd_files_are_parsed() {
+    let device: Device = SVD.parse().unwrap();
+    let (peripheral, register, field) = device.resolve("rcc2.cr.pllm").unwrap();
+    assert_eq!(peripheral.base_address, 0x40022000);
+    assert_eq!(register.reset_value, 0x63);
+    let field = field.unwrap();
+    assert_eq!((field.bit_offset, field.bit_width), (4, 4));
+
+    let rcc = device.peripheral("RCC").unwrap();
+    assert_eq!(rcc.register("KEY1").unwrap().address_offset, 0xC);
+    assert!(!rcc.register("KEY0").unwrap().is_readable());
+
+    match device.resolve("RCC.CR.HSEON.X") {
+        Err(Error::UnknownRegister(path)) => assert_eq!(path, "RCC.CR.HSEON.X"),
+        result => panic!("unexpected {:?}", result),
+    }
+}
+
+#[test]
+fn registers_are_accessed_by_name() {
+    let _lock = stub::lock();
+    let library = stub::library();
+    stub::reset(&library);
+    let stm32prog = STM32CubeProg::new(stub::installation()).unwrap();
+    stm32prog.set_svd(Some(SVD.parse().unwrap()));
+
+    stm32prog.write_svd_register("RCC.CR", 0x63).unwrap();
+    stm32prog.write_svd_register("RCC.CR.HSEON", 1).unwrap();
+    assert_eq!(stm32prog.read_svd_register("RCC.CR").unwrap(), 0x10063);
+    assert_eq!(stm32prog.read_svd_register("RCC.CR.PLLM").unwrap(), 6);
+    assert_eq!(stm32prog.read::<u32>(0x40021000).unwrap(), 0x10063);
+
+    match stm32prog.write_svd_register("RCC.CR.HSEON", 2) {
+        Err(Error::InvalidConfig(_)) => {}
+        result => panic!("unexpected {:?}", result),
+    }
+
+    let dump = stm32prog.dump_peripheral("RCC").unwrap();
+    assert_eq!(dump.registers.len(), 3);
+    assert_eq!(dump.registers[0].value, Some(0x10063));
+    let hseon = &dump.registers[0].fields[1];
+    assert_eq!((hseon.value, hseon.meaning.as_deref()), (1, Some("On")));
+    assert_eq!(dump.registers[1].value, None);
+    assert!(dump.to_string().contains("HSEON = 0x1 (On)"));
+}
+
+const USART: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<device>
+  <name>STM32F103</name>
+  <peripherals>
+    <peripheral>
+      <name>USART</name>
+      <baseAddress>0x40013800</baseAddress>
+      <registers>
+        <register>
+          <name>SR</name>
+          <addressOffset>0x0</addressOffset>
+          <readAction>clear</readAction>
+        </register>
+        <register>
+          <name>DR</name>
+          <addressOffset>0x4</addressOffset>
+          <size>16</size>
+          <fields>
+            <field><name>DR</name><bitOffset>0</bitOffset><bitWidth>9</bitWidth></field>
+            <field><name>PARITY</name><bitOffset>15</bitOffset><bitWidth>1</bitWidth></field>
+          </fields>
+        </register>
+        <register>
+          <name>CFG</name>
+          <addressOffset>0x7</addressOffset>
+          <size>8</size>
+        </register>
+        <register>
+          <name>ISR</name>
+          <addressOffset>0x8</addressOffset>
+          <fields>
+            <field>
+              <name>ORE</name>
+              <bitOffset>3</bitOffset>
+              <bitWidth>1</bitWidth>
+              <readAction>clear</readAction>
+            </field>
+          </fields>
+        </register>
+        <register>
+          <name>CTRL</name>
+          <addressOffset>0xC</addressOffset>
+          <access>write-only</access>
+          <fields>
+            <field><name>EN</name><bitOffset>0</bitOffset><bitWidth>1</bitWidth></field>
+          </fields>
+        </register>
+      </registers>
+    </peripheral>
+    <peripheral>
+      <name>UNALIGNED</name>
+      <baseAddress>0x40013C00</baseAddress>
+      <registers>
+        <register>
+          <name>ODD</name>
+          <addressOffset>0xD</addressOffset>
+          <size>16</size>
+        </register>
+      </registers>
+    </peripheral>
+  </peripherals>
+</device>
+"#;
+
+#[test]
+fn registers_are_accessed_with_their_width() {
+    let _lock = stub::lock();
+    let library = stub::library();
+    stub::reset(&library);
+    let stm32prog = STM32CubeProg::new(stub::installation()).unwrap();
+    stm32prog.set_svd(Some(USART.parse().unwrap()));
+
+    stm32prog.write_svd_register("USART.DR", 0x1FF).unwrap();
+    stm32prog.write_svd_register("USART.DR.PARITY", 1).unwrap();
+    assert_eq!(stm32prog.read_svd_register("USART.DR").unwrap(), 0x81FF);
+    stm32prog.write_svd_register("USART.CFG", 0x5A).unwrap();
+    assert_eq!(stm32prog.read_svd_register("USART.CFG").unwrap(), 0x5A);
+    assert_eq!(stm32prog.read::<u8>(0x40013806).unwrap(), 0xFF);
+
+    assert_eq!(
+        stub::accesses(&library),
+        vec![
+            (0x40013804, 2),
+            (0x40013804, 2),
+            (0x40013804, 2),
+            (0x40013804, 2),
+            (0x40013807, 1),
+            (0x40013807, 1),
+            (0x40013806, 1),
+        ]
+    );
+
+    match stm32prog.read_svd_register("UNALIGNED.ODD") {
+        Err(Error::UnalignedAccess {
+            address: 0x40013C0D,
+            size: 2,
+        }) => {}
+        result => panic!("unexpected {:?}", result),
+    }
+}
+
+#[test]
+fn registers_with_a_read_action_are_not_dumped() {
+    let _lock = stub::lock();
+    let library = stub::library();
+    stub::reset(&library);
+    let stm32prog = STM32CubeProg::new(stub::installation()).unwrap();
+    stm32prog.set_svd(Some(USART.parse().unwrap()));
+
+    let dump = stm32prog.dump_peripheral("USART").unwrap();
+    let sr = &dump.registers[0];
+    assert_eq!((sr.value, sr.read_action.as_deref()), (None, Some("clear")));
+    let isr = &dump.registers[3];
+    assert_eq!(
+        (isr.value, isr.read_action.as_deref()),
+        (None, Some("clear"))
+    );
+    assert!(dump
+        .to_string()
+        .contains("SR (0x40013800): not read (clear on read)"));
+
+    let read: Vec<u32> = stub::accesses(&library)
+        .into_iter()
+        .map(|(address, _)| address)
+        .collect();
+    assert!(!read.contains(&0x40013800));
+    assert!(!read.contains(&0x40013808));
+}
+
+#[test]
+fn fields_are_only_written_to_registers_read_without_side_effects() {
+    let _lock = stub::lock();
+    let library = stub::library();
+    stub::reset(&library);
+    let stm32prog = STM32CubeProg::new(stub::installation()).unwrap();
+    stm32prog.set_svd(Some(USART.parse().unwrap()));
+
+    for path in ["USART.ISR.ORE", "USART.CTRL.EN"].iter() {
+        match stm32prog.write_svd_register(path, 1) {
+            Err(Error::InvalidConfig(_)) => {}
+            result => panic!("unexpected {:?} for {}", result, path),
+        }
+    }
+    assert!(stub::accesses(&library).is_empty());
+
+    stm32prog.write_svd_register("USART.ISR", 0x8).unwrap();
+    stm32prog.write_svd_register("USART.CTRL", 0x1).unwrap();
+    assert_eq!(
+        stub::accesses(&library),
+        vec![(0x40013808, 4), (0x4001380C, 4)]
+    );
+}
+
+#[test]
+fn registers_outside_of_the_address_space_are_refused() {
+    let _lock = stub::lock();
+    let library = stub::library();
+    stub::reset(&library);
+    let stm32prog = STM32CubeProg::new(stub::installation()).unwrap();
+    let svd = "<device><peripherals><peripheral><name>P</name>\
+               <baseAddress>0xFFFFFFFC</baseAddress><registers><register>\
+               <name>R</name><addressOffset>0x8</addressOffset></register>\
+               </registers></peripheral></peripherals></device>";
+    stm32prog.set_svd(Some(svd.parse().unwrap()));
+
+    for result in [
+        stm32prog.read_svd_register("P.R").map(|_| ()),
+        stm32prog.write_svd_register("P.R", 0),
+        stm32prog.dump_peripheral("P").map(|_| ()),
+    ]
+    .iter()
+    {
+        match result {
+            Err(Error::InvalidSvd(_)) => {}
+            result => panic!("unexpected {:?}", result),
+        }
+    }
+}
+
+#[test]
+fn fields_must_fit_in_their_register() {
+    let field = |size: u32, offset: u32, width: u32| {
+        format!(
+            "<device><peripherals><peripheral><name>P</name><registers><register>\
+             <name>R</name><size>{}</size><fields><field><name>F</name>\
+             <bitOffset>{}</bitOffset><bitWidth>{}</bitWidth></field></fields>\
+             </register></registers></peripheral></peripherals></device>",
+            size, offset, width
+        )
+    };
+
+    assert!(field(32, 28, 4).parse::<Device>().is_ok());
+    assert!(field(8, 0, 8).parse::<Device>().is_ok());
+    for (size, offset, width) in [(32, 30, 4), (32, 40, 1), (8, 8, 1), (16, 0, 17)].iter() {
+        match field(*size, *offset, *width).parse::<Device>() {
+            Err(Error::InvalidSvd(_)) => {}
+            result => panic!("unexpected {:?}", result),
+        }
+    }
+}
@@ -0,0 +1,60 @@
+extern crate libloading;
+extern crate stm32cubeprog_rs;
+
+mod firmware;
+mod stub;
+
+use stm32cubeprog_rs::elf::Elf;
+use stm32cubeprog_rs::err::Error;
+use stm32cubeprog_rs::symbols::Symbols;
+use stm32cubeprog_rs::STM32CubeProg;
+
+#[test]
+fn symbols_are_accessed_by_name_within_bounds() {
+    let _lock = stub::lock();
+    let library = stub::library();
+    stub::reset(&library);
+    let stm32prog = STM32CubeProg::new(stub::installation()).unwrap();
+
+    let elf = Elf::parse(firmware::elf()).unwrap();
+    stm32prog.set_symbols(Some(Symbols::from_elf(&elf).unwrap()));
+
+    stm32prog.write_symbol("serial", &0x12345678u32).unwrap();
+    assert_eq!(stm32prog.read_symbol::<u32>("serial").unwrap(), 0x12345678);
+    assert_eq!(
+        stm32prog.read_symbol_bytes("serial").unwrap(),
+        vec![0x78, 0x56, 0x34, 0x12]
+    );
+
+    match stm32prog.read_symbol::<u64>("serial") {
+        Err(Error::OutOfRange {
+            address: 0x08000008,
+            size: 8,
+        }) => {}
+        result => panic!("unexpected {:?}", result),
+    }
+    match stm32prog.write_symbol("missing", &0u8) {
+        Err(Error::UnknownSymbol(name)) => assert_eq!(name, "missing"),
+        result => panic!("unexpected {:?}", result),
+    }
+}
+
+#[test]
+fn malformed_headers_are_invalid_images() {
+    // Program and section header tables past the end of the address space
+    for &offset in [0x1C, 0x20].iter() {
+        let mut elf = firmware::elf();
+        elf[offset..offset + 4].copy_from_slice(&0xFFFFFFF0u32.to_le_bytes());
+        match Elf::parse(elf) {
+            Err(Error::InvalidImage(_)) => {}
+            result => panic!("unexpected {:?}", result),
+        }
+    }
+
+    let mut elf = firmware::elf();
+    elf[0x2A..0x2C].copy_from_slice(&0xFFFFu16.to_le_bytes());
+    match Elf::parse(elf) {
+        Err(Error::InvalidImage(_)) => {}
+        result => panic!("unexpected {:?}", result),
+    }
+}
@@ -0,0 +1,91 @@
+extern crate libloading;
+extern crate stm32cubeprog_rs;
+
+mod stub;
+
+use stm32cubeprog_rs::err::Error;
+use stm32cubeprog_rs::voltage::{VoltageMonitor, VoltageStatus, VoltageThresholds};
+use stm32cubeprog_rs::STM32CubeProg;
+
+#[test]
+fn reading_the_voltage_keeps_the_connection() {
+    let _lock = stub::lock();
+    let library = stub::library();
+    stub::reset(&library);
+    stub::plug_probe(&library, b"PROBE", false);
+    let stm32prog = STM32CubeProg::new(stub::installation()).unwrap();
+
+    let stlink = stm32prog.discover().unwrap().remove(0);
+    stm32prog.connect(&stlink).unwrap();
+
+    stub::set_target_voltages(&library, &["3.31", "2.95"]);
+    assert_eq!(stm32prog.read_target_voltage(&stlink).unwrap(), 3.31);
+    assert_eq!(stm32prog.read_target_voltage(&stlink).unwrap(), 2.95);
+    assert_eq!(stub::connected_frequency(&library), Some(24000));
+    assert!(stm32prog.read::<u32>(0x20000000).is_ok());
+
+    stm32prog.disconnect();
+    assert_eq!(stub::connected_frequency(&library), None);
+}
+
+#[test]
+fn thresholds_are_checked_before_connecting() {
+    let _lock = stub::lock();
+    let library = stub::library();
+    stub::reset(&library);
+    stub::plug_probe(&library, b"PROBE", false);
+    let stm32prog = STM32CubeProg::builder(stub::installation())
+        .voltage_thresholds(VoltageThresholds::default())
+        .build()
+        .unwrap();
+    let stlink = stm32prog.discover().unwrap().remove(0);
+
+    stub::set_target_voltages(&library, &["0.1"]);
+    match stm32prog.connect(&stlink) {
+        Err(Error::TargetUnpowered(voltage)) => assert_eq!(voltage, 0.1),
+        result => panic!("unexpected {:?}", result),
+    }
+    stub::set_target_voltages(&library, &["1.2"]);
+    match stm32prog.connect(&stlink) {
+        Err(Error::TargetUndervoltage { voltage, .. }) => assert_eq!(voltage, 1.2),
+        result => panic!("unexpected {:?}", result),
+    }
+    assert_eq!(stub::connected_frequency(&library), None);
+
+    stub::set_target_voltages(&library, &["3.3"]);
+    stm32prog.connect(&stlink).unwrap();
+    assert_eq!(stub::connected_frequency(&library), Some(24000));
+}
+
+#[test]
+fn monitor_reports_changes_beyond_the_hysteresis() {
+    let _lock = stub::lock();
+    let library = stub::library();
+    stub::reset(&library);
+    stub::plug_probe(&library, b"PROBE", false);
+    let stm32prog = STM32CubeProg::new(stub::installation()).unwrap();
+    let stlink = stm32prog.discover().unwrap().remove(0);
+
+    stub::set_target_voltages(&library, &["3.29", "3.3", "1.2", "0.1", "0.12", "3.3"]);
+    let monitor = VoltageMonitor {
+        interval: std::time::Duration::from_millis(0),
+        ..VoltageMonitor::default()
+    };
+    let mut events = Vec::new();
+    stm32prog
+        .monitor_target_voltage(&stlink, &monitor, |event| {
+            events.push((event.voltage, event.previous, event.status));
+            events.len() < 4
+        })
+        .unwrap();
+
+    assert_eq!(
+        events,
+        vec![
+            (3.29, None, VoltageStatus::Powered),
+            (1.2, Some(3.29), VoltageStatus::Undervoltage),
+            (0.1, Some(1.2), VoltageStatus::Unpowered),
+            (3.3, Some(0.1), VoltageStatus::Powered),
+        ]
+    );
+}
@@ -0,0 +1,44 @@
+extern crate libloading;
+extern crate stm32cubeprog_rs;
+
+mod stub;
+
+use stm32cubeprog_rs::err::Error;
+use stm32cubeprog_rs::memory::TransferOptions;
+use stm32cubeprog_rs::STM32CubeProg;
+
+#[test]
+fn unaligned_writes_need_read_modify_write() {
+    let _lock = stub::lock();
+    let library = stub::library();
+    stub::reset(&library);
+    let stm32prog = STM32CubeProg::new(stub::installation()).unwrap();
+
+    stm32prog.write_memory8(0x20000000, vec![0x11; 8]).unwrap();
+    stm32prog.write_memory8(0x20000003, vec![0x22]).unwrap();
+
+    let mut options = TransferOptions {
+        alignment: 4,
+        ..TransferOptions::default()
+    };
+    match stm32prog.write_chunked(0x20000002, &[0x33; 4], &options, |_, _| {}) {
+        Err(Error::UnalignedAccess {
+            address: 0x20000002,
+            size: 4,
+        }) => {}
+        result => panic!("unexpected {:?}", result),
+    }
+    assert_eq!(
+        stm32prog.read_memory8(0x20000000, 8).unwrap(),
+        vec![0x11, 0x11, 0x11, 0x22, 0x11, 0x11, 0x11, 0x11]
+    );
+
+    options.read_modify_write = true;
+    stm32prog
+        .write_chunked(0x20000002, &[0x33; 4], &options, |_, _| {})
+        .unwrap();
+    assert_eq!(
+        stm32prog.read_memory8(0x20000000, 8).unwrap(),
+        vec![0x11, 0x11, 0x33, 0x33, 0x33, 0x33, 0x11, 0x11]
+    );
+}